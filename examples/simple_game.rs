@@ -1,25 +1,39 @@
 use engine::prelude::*;
-use jaren_ecs::system::System;
+use jaren_ecs::{spawn, system::World};
 
 fn main() {
-    // add example
-    todo!();
+    #[cfg(not(target_arch = "wasm32"))]
+    env_logger::init();
+
+    App::new(GameConfig {
+        title: "simple_game".into(),
+        ..Default::default()
+    })
+    .add_system(FunctionMode::Startup, startup_function_1)
+    .add_system(FunctionMode::Update, update_function_1)
+    .run();
 }
 
-pub fn startup_function_1(system: &mut System) {
+pub fn startup_function_1(world: &mut World) {
     #[cfg(target_arch = "wasm32")]
     web_sys::console::log_1(&"Running startup system 1!".into());
     #[cfg(not(target_arch = "wasm32"))]
     println!("Running startup system 1!");
     // Example: register components, spawn entities, etc.
-    let entity = system.arena.allocate();
-    system.entity_to_archetype.insert(entity, (0, 0));
+    spawn!(
+        world,
+        Camera2d {
+            scaling_mode: ScalingMode::WindowSize,
+            ..Default::default()
+        }
+    );
+    let image = world
+        .resource_mut::<AssetServer>()
+        .expect("App inserts the AssetServer")
+        .load::<Image>("25010123242900.jpeg");
+    spawn!(world, Sprite::from_image(image), Transform2d::default());
 }
 
-pub fn update_function_1(system: &mut System) {
-    #[cfg(target_arch = "wasm32")]
-    web_sys::console::log_1(&"Running update system 1!".into());
-    #[cfg(not(target_arch = "wasm32"))]
-    println!("Running update system 1!");
+pub fn update_function_1(_world: &mut World) {
     // Example: update logic, move entities, etc.
 }
@@ -0,0 +1,36 @@
+// Reading asset files on the web, where they are fetched from the server hosting the page.
+
+use js_sys::Uint8Array;
+use std::{io, path::Path};
+use wasm_bindgen::JsCast;
+use wasm_bindgen_futures::JsFuture;
+
+fn js_error(err: wasm_bindgen::JsValue) -> io::Error {
+    io::Error::other(format!("{err:?}"))
+}
+
+/// The bytes at `path`, relative to the page's URL.
+pub(crate) async fn fetch(path: &Path) -> io::Result<Vec<u8>> {
+    let window = web_sys::window().ok_or_else(|| io::Error::other("no window to fetch from"))?;
+    let url = path.to_string_lossy();
+    let response: web_sys::Response = JsFuture::from(window.fetch_with_str(&url))
+        .await
+        .map_err(js_error)?
+        .dyn_into()
+        .map_err(js_error)?;
+    if !response.ok() {
+        let kind = match response.status() {
+            404 => io::ErrorKind::NotFound,
+            403 => io::ErrorKind::PermissionDenied,
+            _ => io::ErrorKind::Other,
+        };
+        return Err(io::Error::new(
+            kind,
+            format!("HTTP {} {}", response.status(), response.status_text()),
+        ));
+    }
+    let buffer = JsFuture::from(response.array_buffer().map_err(js_error)?)
+        .await
+        .map_err(js_error)?;
+    Ok(Uint8Array::new(&buffer).to_vec())
+}
@@ -0,0 +1,185 @@
+use std::{
+    any::TypeId,
+    collections::HashMap,
+    fmt,
+    hash::{Hash, Hasher},
+    marker::PhantomData,
+    path::{Path, PathBuf},
+    sync::{
+        Arc, Mutex, Weak,
+        atomic::{AtomicU64, Ordering},
+        mpsc::Sender,
+    },
+};
+
+/// Untyped id of an asset slot in the [`AssetServer`](crate::AssetServer).
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct HandleId(pub(crate) u64);
+
+// Shared by every strong handle to the same asset. When the last one goes away
+// the server is told so it can free the asset on its next `update`.
+pub(crate) struct StrongHandle {
+    pub(crate) id: HandleId,
+    drop_sender: Sender<HandleId>,
+}
+
+impl StrongHandle {
+    pub(crate) fn new(id: HandleId, drop_sender: Sender<HandleId>) -> Arc<Self> {
+        Arc::new(Self { id, drop_sender })
+    }
+}
+
+impl Drop for StrongHandle {
+    fn drop(&mut self) {
+        // The server may already be gone, nothing left to free in that case.
+        let _ = self.drop_sender.send(self.id);
+    }
+}
+
+// Hands out handle ids and remembers which path each live handle was loaded from.
+// Shared with loader threads so a loader can request its dependencies without
+// waiting for the server.
+pub(crate) struct HandleTable {
+    next_id: AtomicU64,
+    paths: Mutex<HashMap<(PathBuf, TypeId), Weak<StrongHandle>>>,
+    drop_sender: Sender<HandleId>,
+}
+
+impl HandleTable {
+    pub(crate) fn new(drop_sender: Sender<HandleId>) -> Self {
+        Self {
+            next_id: AtomicU64::new(0),
+            paths: Mutex::default(),
+            drop_sender,
+        }
+    }
+
+    pub(crate) fn allocate(&self) -> Arc<StrongHandle> {
+        let id = HandleId(self.next_id.fetch_add(1, Ordering::Relaxed));
+        StrongHandle::new(id, self.drop_sender.clone())
+    }
+
+    /// The live handle for `path`, or a fresh one. The flag is true when the caller
+    /// has to start loading it.
+    pub(crate) fn get_or_allocate(
+        &self,
+        path: &Path,
+        type_id: TypeId,
+    ) -> (Arc<StrongHandle>, bool) {
+        let mut paths = self.paths.lock().unwrap_or_else(|e| e.into_inner());
+        let key = (path.to_path_buf(), type_id);
+        if let Some(strong) = paths.get(&key).and_then(Weak::upgrade) {
+            return (strong, false);
+        }
+        let strong = self.allocate();
+        paths.insert(key, Arc::downgrade(&strong));
+        (strong, true)
+    }
+
+    /// Live handles loaded from a path, with the path and asset type.
+    pub(crate) fn loaded_paths(&self) -> Vec<(PathBuf, TypeId, Arc<StrongHandle>)> {
+        let paths = self.paths.lock().unwrap_or_else(|e| e.into_inner());
+        paths
+            .iter()
+            .filter_map(|((path, type_id), weak)| Some((path.clone(), *type_id, weak.upgrade()?)))
+            .collect()
+    }
+
+    pub(crate) fn forget_dropped(&self) {
+        let mut paths = self.paths.lock().unwrap_or_else(|e| e.into_inner());
+        paths.retain(|_, weak| weak.strong_count() > 0);
+    }
+}
+
+/// Strong, typed reference to an asset. The asset stays alive while any clone of this exists.
+pub struct Handle<T> {
+    pub(crate) inner: Arc<StrongHandle>,
+    _marker: PhantomData<fn() -> T>,
+}
+
+impl<T> Handle<T> {
+    pub(crate) fn from_strong(inner: Arc<StrongHandle>) -> Self {
+        Self {
+            inner,
+            _marker: PhantomData,
+        }
+    }
+
+    pub fn id(&self) -> HandleId {
+        self.inner.id
+    }
+
+    /// A reference that does not keep the asset alive.
+    pub fn downgrade(&self) -> WeakHandle<T> {
+        WeakHandle {
+            id: self.inner.id,
+            inner: Arc::downgrade(&self.inner),
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<T> Clone for Handle<T> {
+    fn clone(&self) -> Self {
+        Self::from_strong(self.inner.clone())
+    }
+}
+
+impl<T> PartialEq for Handle<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.id() == other.id()
+    }
+}
+
+impl<T> Eq for Handle<T> {}
+
+impl<T> Hash for Handle<T> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.id().hash(state);
+    }
+}
+
+impl<T> fmt::Debug for Handle<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Handle<{}>({})", std::any::type_name::<T>(), self.id().0)
+    }
+}
+
+/// Typed reference that does not keep the asset alive.
+pub struct WeakHandle<T> {
+    id: HandleId,
+    inner: Weak<StrongHandle>,
+    _marker: PhantomData<fn() -> T>,
+}
+
+impl<T> WeakHandle<T> {
+    pub fn id(&self) -> HandleId {
+        self.id
+    }
+
+    /// Get a strong handle back, if the asset has not been freed yet.
+    pub fn upgrade(&self) -> Option<Handle<T>> {
+        self.inner.upgrade().map(Handle::from_strong)
+    }
+}
+
+impl<T> Clone for WeakHandle<T> {
+    fn clone(&self) -> Self {
+        Self {
+            id: self.id,
+            inner: self.inner.clone(),
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<T> fmt::Debug for WeakHandle<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "WeakHandle<{}>({})",
+            std::any::type_name::<T>(),
+            self.id.0
+        )
+    }
+}
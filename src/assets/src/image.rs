@@ -0,0 +1,114 @@
+use crate::Asset;
+use std::fmt;
+
+/// Decoded image, always stored as tightly packed RGBA8 (sRGB).
+#[derive(Clone, Debug, PartialEq)]
+pub struct Image {
+    pub width: u32,
+    pub height: u32,
+    pub data: Vec<u8>,
+}
+
+impl Asset for Image {}
+
+/// Why pixel data cannot be made into an [`Image`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ImageDataError {
+    /// `width` x `height` RGBA8 pixels do not fit in memory.
+    TooLarge { width: u32, height: u32 },
+    /// The data is not `width` x `height` RGBA8 pixels.
+    WrongLength { expected: usize, actual: usize },
+}
+
+impl fmt::Display for ImageDataError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ImageDataError::TooLarge { width, height } => {
+                write!(f, "a {width}x{height} image is too large")
+            }
+            ImageDataError::WrongLength { expected, actual } => write!(
+                f,
+                "image data is {actual} bytes, RGBA8 pixels of that size need {expected}"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ImageDataError {}
+
+// Bytes of `width` x `height` RGBA8 pixels.
+fn data_len(width: u32, height: u32) -> Result<usize, ImageDataError> {
+    (width as usize)
+        .checked_mul(height as usize)
+        .and_then(|pixels| pixels.checked_mul(4))
+        .ok_or(ImageDataError::TooLarge { width, height })
+}
+
+impl Image {
+    /// `data` must be `width` x `height` RGBA8 pixels, row by row.
+    pub fn new(width: u32, height: u32, data: Vec<u8>) -> Result<Self, ImageDataError> {
+        let expected = data_len(width, height)?;
+        if data.len() != expected {
+            return Err(ImageDataError::WrongLength {
+                expected,
+                actual: data.len(),
+            });
+        }
+        Ok(Self {
+            width,
+            height,
+            data,
+        })
+    }
+
+    /// Fully transparent image, e.g. as a render target.
+    pub fn blank(width: u32, height: u32) -> Result<Self, ImageDataError> {
+        Self::new(width, height, vec![0; data_len(width, height)?])
+    }
+
+    /// Decode any format the `image` crate understands.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, image::ImageError> {
+        let rgba = image::load_from_memory(bytes)?.to_rgba8();
+        let (width, height) = rgba.dimensions();
+        Ok(Self {
+            width,
+            height,
+            data: rgba.into_raw(),
+        })
+    }
+
+    pub fn save_png(&self, path: impl AsRef<std::path::Path>) -> Result<(), image::ImageError> {
+        image::save_buffer_with_format(
+            path,
+            &self.data,
+            self.width,
+            self.height,
+            image::ColorType::Rgba8,
+            image::ImageFormat::Png,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_new_checks_data_length() {
+        assert!(Image::new(2, 1, vec![0; 8]).is_ok());
+        assert_eq!(
+            Image::new(2, 2, vec![0; 8]),
+            Err(ImageDataError::WrongLength {
+                expected: 16,
+                actual: 8
+            })
+        );
+        assert_eq!(
+            Image::blank(u32::MAX, u32::MAX),
+            Err(ImageDataError::TooLarge {
+                width: u32::MAX,
+                height: u32::MAX
+            })
+        );
+    }
+}
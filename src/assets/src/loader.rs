@@ -0,0 +1,272 @@
+use crate::{
+    Asset, Handle, Image, Shader,
+    handle::{HandleId, HandleTable, StrongHandle},
+};
+use std::{
+    any::{Any, TypeId},
+    fmt::Display,
+    future::Future,
+    path::{Path, PathBuf},
+    pin::Pin,
+    sync::{Arc, Weak, mpsc::Sender},
+};
+
+/// Turns the bytes of a file into an asset.
+///
+/// Loaders run on the [`AssetServer`](crate::AssetServer)'s loader threads, or on the browser's
+/// event loop on the web, and are picked by the asset type being requested together with the
+/// file extension, so several loaders can share an extension (e.g. many RON-backed types) as
+/// long as they produce different types.
+pub trait AssetLoader: Send + Sync + 'static {
+    type Asset: Asset;
+    type Error: Display;
+
+    /// File extensions this loader accepts, without the leading dot.
+    fn extensions(&self) -> &[&str];
+
+    fn load(
+        &self,
+        bytes: &[u8],
+        ctx: &mut LoadContext,
+    ) -> impl Future<Output = Result<Self::Asset, Self::Error>> + Send;
+}
+
+// Sent back to the server when a loader asks for another asset.
+pub(crate) struct LoadRequest {
+    pub(crate) id: HandleId,
+    pub(crate) path: PathBuf,
+    pub(crate) type_id: TypeId,
+    pub(crate) type_name: &'static str,
+    // Lets the server skip requests whose handle was dropped before it got to them.
+    pub(crate) handle: Weak<StrongHandle>,
+}
+
+/// Passed to [`AssetLoader::load`]. Used to start loading the assets this one depends on.
+pub struct LoadContext {
+    path: PathBuf,
+    root: PathBuf,
+    handles: Arc<HandleTable>,
+    requests: Sender<LoadRequest>,
+    pub(crate) dependencies: Vec<Arc<StrongHandle>>,
+    // Assets the loader produced alongside its main one, stored when the load finishes.
+    pub(crate) added: Vec<(Arc<StrongHandle>, BoxedAsset)>,
+}
+
+impl LoadContext {
+    pub(crate) fn new(
+        path: PathBuf,
+        root: PathBuf,
+        handles: Arc<HandleTable>,
+        requests: Sender<LoadRequest>,
+    ) -> Self {
+        Self {
+            path,
+            root,
+            handles,
+            requests,
+            dependencies: Vec::new(),
+            added: Vec::new(),
+        }
+    }
+
+    /// Path of the asset being loaded, relative to the server root.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Load an asset this one depends on. The path is relative to the server root, like
+    /// [`AssetServer::load`](crate::AssetServer::load). The dependency is kept alive for as
+    /// long as the asset being loaded is.
+    pub fn load<T: Asset>(&mut self, path: impl AsRef<Path>) -> Handle<T> {
+        let (strong, is_new) = self
+            .handles
+            .get_or_allocate(path.as_ref(), TypeId::of::<T>());
+        if is_new {
+            let _ = self.requests.send(LoadRequest {
+                id: strong.id,
+                path: path.as_ref().to_path_buf(),
+                type_id: TypeId::of::<T>(),
+                type_name: std::any::type_name::<T>(),
+                handle: Arc::downgrade(&strong),
+            });
+        }
+        self.dependencies.push(strong.clone());
+        Handle::from_strong(strong)
+    }
+
+    /// Read another file right away, for loaders that need its contents rather than a handle.
+    /// The path is relative to the server root.
+    ///
+    /// Not supported on the web, where files can only be fetched asynchronously: this always
+    /// returns an [`Unsupported`](std::io::ErrorKind::Unsupported) error there. Loaders that
+    /// should work on the web have to [`load`](Self::load) other files as dependencies.
+    pub fn read(&self, path: impl AsRef<Path>) -> std::io::Result<Vec<u8>> {
+        #[cfg(not(target_arch = "wasm32"))]
+        return std::fs::read(self.root.join(path));
+        #[cfg(target_arch = "wasm32")]
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            format!(
+                "cannot read {} while loading on the web, load it as a dependency instead",
+                path.as_ref().display()
+            ),
+        ));
+    }
+
+    /// Store an asset the loader built on the side, e.g. the packed image of an atlas.
+    /// It becomes available once this load finishes and lives as long as the asset being loaded.
+    pub fn add<T: Asset>(&mut self, asset: T) -> Handle<T> {
+        let strong = self.handles.allocate();
+        self.dependencies.push(strong.clone());
+        self.added.push((strong.clone(), Box::new(asset)));
+        Handle::from_strong(strong)
+    }
+}
+
+pub(crate) type BoxedAsset = Box<dyn Any + Send + Sync>;
+
+type BoxedLoad<'a> = Pin<Box<dyn Future<Output = Result<BoxedAsset, String>> + Send + 'a>>;
+
+// Object-safe side of `AssetLoader` so loaders of different types can live in one list.
+pub(crate) trait ErasedLoader: Send + Sync {
+    fn extensions(&self) -> &[&str];
+    fn asset_type(&self) -> TypeId;
+    fn load<'a>(&'a self, bytes: &'a [u8], ctx: &'a mut LoadContext) -> BoxedLoad<'a>;
+}
+
+impl<L: AssetLoader> ErasedLoader for L {
+    fn extensions(&self) -> &[&str] {
+        AssetLoader::extensions(self)
+    }
+
+    fn asset_type(&self) -> TypeId {
+        TypeId::of::<L::Asset>()
+    }
+
+    fn load<'a>(&'a self, bytes: &'a [u8], ctx: &'a mut LoadContext) -> BoxedLoad<'a> {
+        Box::pin(async move {
+            AssetLoader::load(self, bytes, ctx)
+                .await
+                .map(|asset| Box::new(asset) as BoxedAsset)
+                .map_err(|e| e.to_string())
+        })
+    }
+}
+
+pub struct ImageLoader;
+
+impl AssetLoader for ImageLoader {
+    type Asset = Image;
+    type Error = image::ImageError;
+
+    fn extensions(&self) -> &[&str] {
+        &[
+            "png", "jpg", "jpeg", "bmp", "gif", "ico", "tga", "tif", "tiff", "webp",
+        ]
+    }
+
+    async fn load(&self, bytes: &[u8], _ctx: &mut LoadContext) -> Result<Image, Self::Error> {
+        Image::from_bytes(bytes)
+    }
+}
+
+pub struct ShaderLoader;
+
+impl AssetLoader for ShaderLoader {
+    type Asset = Shader;
+    type Error = std::str::Utf8Error;
+
+    fn extensions(&self) -> &[&str] {
+        &["wgsl"]
+    }
+
+    async fn load(&self, bytes: &[u8], _ctx: &mut LoadContext) -> Result<Shader, Self::Error> {
+        Shader::from_bytes(bytes)
+    }
+}
+
+/// Loads any `T: Deserialize` from a RON file.
+#[cfg(feature = "serde")]
+pub struct RonLoader<T> {
+    extensions: &'static [&'static str],
+    _marker: std::marker::PhantomData<fn() -> T>,
+}
+
+#[cfg(feature = "serde")]
+impl<T> RonLoader<T> {
+    /// Accepts `.ron` files.
+    pub fn new() -> Self {
+        Self::with_extensions(&["ron"])
+    }
+
+    pub fn with_extensions(extensions: &'static [&'static str]) -> Self {
+        Self {
+            extensions,
+            _marker: std::marker::PhantomData,
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<T> Default for RonLoader<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<T: Asset + serde::de::DeserializeOwned> AssetLoader for RonLoader<T> {
+    type Asset = T;
+    type Error = ron::error::SpannedError;
+
+    fn extensions(&self) -> &[&str] {
+        self.extensions
+    }
+
+    async fn load(&self, bytes: &[u8], _ctx: &mut LoadContext) -> Result<T, Self::Error> {
+        ron::de::from_bytes(bytes)
+    }
+}
+
+/// Loads any `T: Deserialize` from a JSON file.
+#[cfg(feature = "serde")]
+pub struct JsonLoader<T> {
+    extensions: &'static [&'static str],
+    _marker: std::marker::PhantomData<fn() -> T>,
+}
+
+#[cfg(feature = "serde")]
+impl<T> JsonLoader<T> {
+    /// Accepts `.json` files.
+    pub fn new() -> Self {
+        Self::with_extensions(&["json"])
+    }
+
+    pub fn with_extensions(extensions: &'static [&'static str]) -> Self {
+        Self {
+            extensions,
+            _marker: std::marker::PhantomData,
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<T> Default for JsonLoader<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<T: Asset + serde::de::DeserializeOwned> AssetLoader for JsonLoader<T> {
+    type Asset = T;
+    type Error = serde_json::Error;
+
+    fn extensions(&self) -> &[&str] {
+        self.extensions
+    }
+
+    async fn load(&self, bytes: &[u8], _ctx: &mut LoadContext) -> Result<T, Self::Error> {
+        serde_json::from_slice(bytes)
+    }
+}
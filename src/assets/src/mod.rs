@@ -0,0 +1,21 @@
+#[cfg(target_arch = "wasm32")]
+mod fetch;
+pub mod handle;
+pub mod image;
+pub mod loader;
+pub mod server;
+pub mod shader;
+mod task_pool;
+#[cfg(test)]
+mod test_dir;
+#[cfg(not(target_arch = "wasm32"))]
+mod watcher;
+
+pub use handle::{Handle, HandleId, WeakHandle};
+pub use image::{Image, ImageDataError};
+pub use loader::{AssetLoader, LoadContext};
+pub use server::{AssetError, AssetServer, LoadState};
+pub use shader::Shader;
+
+/// Marker for types that can be stored in the [`AssetServer`].
+pub trait Asset: Send + Sync + 'static {}
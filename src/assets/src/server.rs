@@ -0,0 +1,778 @@
+#[cfg(not(target_arch = "wasm32"))]
+use crate::watcher::FileWatcher;
+use crate::{
+    Asset,
+    handle::{Handle, HandleId, HandleTable, StrongHandle},
+    loader::{
+        AssetLoader, BoxedAsset, ErasedLoader, ImageLoader, LoadContext, LoadRequest, ShaderLoader,
+    },
+    task_pool::TaskPool,
+};
+use std::{
+    any::TypeId,
+    collections::{HashMap, HashSet},
+    fmt,
+    path::{Path, PathBuf},
+    sync::{
+        Arc, Mutex, PoisonError,
+        mpsc::{self, Receiver, Sender},
+    },
+};
+
+struct LoadResult {
+    id: HandleId,
+    result: Result<BoxedAsset, AssetError>,
+    dependencies: Vec<Arc<StrongHandle>>,
+    added: Vec<(Arc<StrongHandle>, BoxedAsset)>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum AssetError {
+    /// The file could not be read.
+    Io { path: PathBuf, message: String },
+    /// No registered loader produces the requested asset type from this file extension.
+    NoLoader {
+        path: PathBuf,
+        type_name: &'static str,
+    },
+    /// The file was read but the loader rejected it.
+    Decode { path: PathBuf, message: String },
+    /// Assets depend on each other in a loop, starting and ending at the same asset.
+    Cycle(Vec<PathBuf>),
+}
+
+impl fmt::Display for AssetError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AssetError::Io { path, message } => {
+                write!(f, "failed to read {}: {message}", path.display())
+            }
+            AssetError::NoLoader { path, type_name } => {
+                write!(f, "no loader for {type_name} (loading {})", path.display())
+            }
+            AssetError::Decode { path, message } => {
+                write!(f, "failed to decode {}: {message}", path.display())
+            }
+            AssetError::Cycle(paths) => {
+                let paths: Vec<_> = paths
+                    .iter()
+                    .map(|path| path.display().to_string())
+                    .collect();
+                write!(f, "assets depend on each other: {}", paths.join(" -> "))
+            }
+        }
+    }
+}
+
+impl std::error::Error for AssetError {}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum LoadState {
+    Loading,
+    Loaded,
+    Failed(AssetError),
+}
+
+struct AssetSlot {
+    state: LoadState,
+    // Where the asset was loaded from, `None` for assets added at runtime.
+    path: Option<PathBuf>,
+    value: Option<BoxedAsset>,
+    // Bumped every time the value is replaced, so users can tell a reload happened.
+    version: u64,
+    // Assets requested through the `LoadContext`, kept alive alongside this one.
+    dependencies: Vec<Arc<StrongHandle>>,
+}
+
+impl AssetSlot {
+    fn new(state: LoadState) -> Self {
+        Self {
+            state,
+            path: None,
+            value: None,
+            version: 0,
+            dependencies: Vec::new(),
+        }
+    }
+}
+
+/// Loads assets in the background and hands out [`Handle`]s to them.
+///
+/// Handles are returned immediately; the data shows up after a later [`AssetServer::update`]
+/// once the loader thread is done. Loading the same path twice gives back the same handle
+/// while it is alive, and an asset is freed on the `update` after its last strong handle drops.
+///
+/// On native targets [`AssetServer::watch_for_changes`] turns on hot reloading: edited files
+/// are loaded again into their existing handles. On the web, files are fetched over HTTP
+/// relative to the page, and [`LoadContext::read`] is not available to loaders.
+pub struct AssetServer {
+    root: PathBuf,
+    handles: Arc<HandleTable>,
+    slots: HashMap<HandleId, AssetSlot>,
+    loaders: Vec<Arc<dyn ErasedLoader>>,
+    pool: TaskPool,
+    // Receivers are not `Sync`, the mutexes let the server be a world resource. They are
+    // only used through `&mut self`, see `receiver`.
+    drop_receiver: Mutex<Receiver<HandleId>>,
+    result_sender: Sender<LoadResult>,
+    result_receiver: Mutex<Receiver<LoadResult>>,
+    request_sender: Sender<LoadRequest>,
+    request_receiver: Mutex<Receiver<LoadRequest>>,
+    #[cfg(not(target_arch = "wasm32"))]
+    watcher: Option<FileWatcher>,
+}
+
+impl Default for AssetServer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AssetServer {
+    /// Server resolving paths relative to the working directory.
+    pub fn new() -> Self {
+        Self::with_root("")
+    }
+
+    /// Server resolving paths relative to `root`.
+    pub fn with_root(root: impl Into<PathBuf>) -> Self {
+        let (drop_sender, drop_receiver) = mpsc::channel();
+        let (result_sender, result_receiver) = mpsc::channel();
+        let (request_sender, request_receiver) = mpsc::channel();
+        let mut server = Self {
+            root: root.into(),
+            handles: Arc::new(HandleTable::new(drop_sender)),
+            slots: HashMap::new(),
+            loaders: Vec::new(),
+            pool: TaskPool::new(),
+            drop_receiver: Mutex::new(drop_receiver),
+            result_sender,
+            result_receiver: Mutex::new(result_receiver),
+            request_sender,
+            request_receiver: Mutex::new(request_receiver),
+            #[cfg(not(target_arch = "wasm32"))]
+            watcher: None,
+        };
+        server.register_loader(ImageLoader);
+        server.register_loader(ShaderLoader);
+        server
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Reload files into their existing handles when they change on disk.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn watch_for_changes(&mut self) {
+        if self.watcher.is_some() {
+            return;
+        }
+        let watcher = FileWatcher::new();
+        for (path, _, _) in self.handles.loaded_paths() {
+            watcher.watch(self.root.join(path));
+        }
+        self.watcher = Some(watcher);
+    }
+
+    /// Add a loader. A loader registered later wins over an earlier one for the same
+    /// asset type and extension.
+    pub fn register_loader<L: AssetLoader>(&mut self, loader: L) {
+        self.loaders.insert(0, Arc::new(loader));
+    }
+
+    fn find_loader(&self, type_id: TypeId, path: &Path) -> Option<Arc<dyn ErasedLoader>> {
+        let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+        self.loaders
+            .iter()
+            .find(|loader| {
+                loader.asset_type() == type_id && loader.extensions().contains(&extension.as_str())
+            })
+            .cloned()
+    }
+
+    /// Start loading `path` in the background. Returns the existing handle if the same path
+    /// is already loaded (or loading) as `T`.
+    pub fn load<T: Asset>(&mut self, path: impl AsRef<Path>) -> Handle<T> {
+        let path = path.as_ref();
+        let (strong, is_new) = self.handles.get_or_allocate(path, TypeId::of::<T>());
+        if is_new {
+            self.start_load(LoadRequest {
+                id: strong.id,
+                path: path.to_path_buf(),
+                type_id: TypeId::of::<T>(),
+                type_name: std::any::type_name::<T>(),
+                handle: Arc::downgrade(&strong),
+            });
+        }
+        Handle::from_strong(strong)
+    }
+
+    fn start_load(&mut self, request: LoadRequest) {
+        self.slots.entry(request.id).or_insert_with(|| AssetSlot {
+            path: Some(request.path.clone()),
+            ..AssetSlot::new(LoadState::Loading)
+        });
+
+        #[cfg(not(target_arch = "wasm32"))]
+        if let Some(watcher) = &self.watcher {
+            watcher.watch(self.root.join(&request.path));
+        }
+
+        if self.find_loader(request.type_id, &request.path).is_some() {
+            self.spawn_load(request.id, request.path, request.type_id);
+        } else {
+            self.finish(LoadResult {
+                id: request.id,
+                result: Err(AssetError::NoLoader {
+                    path: request.path,
+                    type_name: request.type_name,
+                }),
+                dependencies: Vec::new(),
+                added: Vec::new(),
+            });
+        }
+    }
+
+    fn spawn_load(&self, id: HandleId, path: PathBuf, type_id: TypeId) {
+        let Some(loader) = self.find_loader(type_id, &path) else {
+            return;
+        };
+        let full_path = self.root.join(&path);
+        let mut ctx = LoadContext::new(
+            path,
+            self.root.clone(),
+            self.handles.clone(),
+            self.request_sender.clone(),
+        );
+        let results = self.result_sender.clone();
+        self.pool.spawn(async move {
+            let result = match read_file(&full_path).await {
+                Ok(bytes) => {
+                    loader
+                        .load(&bytes, &mut ctx)
+                        .await
+                        .map_err(|message| AssetError::Decode {
+                            path: ctx.path().to_path_buf(),
+                            message,
+                        })
+                }
+                Err(e) => Err(AssetError::Io {
+                    path: ctx.path().to_path_buf(),
+                    message: e.to_string(),
+                }),
+            };
+            let _ = results.send(LoadResult {
+                id,
+                result,
+                dependencies: ctx.dependencies,
+                added: ctx.added,
+            });
+        });
+    }
+
+    /// Store an asset created at runtime.
+    pub fn add<T: Asset>(&mut self, asset: T) -> Handle<T> {
+        let strong = self.handles.allocate();
+        self.insert_loaded(strong.id, Box::new(asset));
+        Handle::from_strong(strong)
+    }
+
+    fn insert_loaded(&mut self, id: HandleId, value: BoxedAsset) {
+        let mut slot = AssetSlot::new(LoadState::Loaded);
+        slot.value = Some(value);
+        slot.version = 1;
+        self.slots.insert(id, slot);
+    }
+
+    pub fn get<T: Asset>(&self, handle: &Handle<T>) -> Option<&T> {
+        self.slots
+            .get(&handle.id())?
+            .value
+            .as_ref()?
+            .downcast_ref::<T>()
+    }
+
+    pub fn get_mut<T: Asset>(&mut self, handle: &Handle<T>) -> Option<&mut T> {
+        self.slots
+            .get_mut(&handle.id())?
+            .value
+            .as_mut()?
+            .downcast_mut::<T>()
+    }
+
+    pub fn load_state<T>(&self, handle: &Handle<T>) -> LoadState {
+        self.slots
+            .get(&handle.id())
+            .map(|slot| slot.state.clone())
+            .unwrap_or(LoadState::Loading)
+    }
+
+    /// Whether the asset and everything its loader asked for (recursively) has loaded.
+    pub fn is_loaded_with_dependencies(&self, id: HandleId) -> bool {
+        let mut visited = HashSet::new();
+        let mut stack = vec![id];
+        while let Some(id) = stack.pop() {
+            if !visited.insert(id) {
+                continue;
+            }
+            let Some(slot) = self.slots.get(&id) else {
+                return false;
+            };
+            if slot.state != LoadState::Loaded {
+                return false;
+            }
+            stack.extend(slot.dependencies.iter().map(|dep| dep.id));
+        }
+        true
+    }
+
+    /// Whether any asset is still waiting for its first load to finish.
+    pub fn is_loading(&self) -> bool {
+        self.slots
+            .values()
+            .any(|slot| slot.state == LoadState::Loading)
+    }
+
+    /// How many times the asset behind `id` has been (re)loaded. Zero while still loading.
+    pub fn version(&self, id: HandleId) -> u64 {
+        self.slots.get(&id).map_or(0, |slot| slot.version)
+    }
+
+    /// Whether the asset behind `id` has not been freed yet.
+    pub fn contains(&self, id: HandleId) -> bool {
+        self.slots.contains_key(&id)
+    }
+
+    /// Collect finished loads, start loading dependencies, free assets whose last strong
+    /// handle was dropped and start reloading changed files. Call once per frame.
+    pub fn update(&mut self) {
+        #[cfg(not(target_arch = "wasm32"))]
+        self.reload_changed();
+
+        // A loader sends its requests before its result, so taking results first means the
+        // dependencies of every finished load have started and count as loading.
+        while let Ok(result) = receiver(&mut self.result_receiver).try_recv() {
+            self.finish(result);
+        }
+        while let Ok(request) = receiver(&mut self.request_receiver).try_recv() {
+            if request.handle.strong_count() > 0 {
+                self.start_load(request);
+            }
+        }
+        let mut dropped = false;
+        while let Ok(id) = receiver(&mut self.drop_receiver).try_recv() {
+            self.slots.remove(&id);
+            dropped = true;
+        }
+        if dropped {
+            self.handles.forget_dropped();
+        }
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn reload_changed(&mut self) {
+        let Some(watcher) = &self.watcher else {
+            return;
+        };
+        let changed = watcher.changed();
+        if changed.is_empty() {
+            return;
+        }
+        for (path, type_id, strong) in self.handles.loaded_paths() {
+            if changed.contains(&self.root.join(&path)) {
+                log::info!("reloading {}", path.display());
+                self.spawn_load(strong.id, path, type_id);
+            }
+        }
+    }
+
+    fn finish(&mut self, load: LoadResult) {
+        // The handle may have been dropped while the load was in flight.
+        if !self.slots.contains_key(&load.id) {
+            return;
+        }
+        if load.result.is_ok()
+            && let Some(cycle) = self.find_cycle(load.id, &load.dependencies)
+        {
+            self.fail_cycle(&cycle);
+            return;
+        }
+        if load.result.is_ok() {
+            for (strong, value) in load.added {
+                self.insert_loaded(strong.id, value);
+            }
+        }
+        let Some(slot) = self.slots.get_mut(&load.id) else {
+            return;
+        };
+        match load.result {
+            Ok(value) => {
+                slot.value = Some(value);
+                slot.state = LoadState::Loaded;
+                slot.version += 1;
+                slot.dependencies = load.dependencies;
+            }
+            // A broken reload should not take down an asset that was working.
+            Err(err) if slot.value.is_some() => {
+                log::error!("{err}, keeping the previous version");
+            }
+            Err(err) => {
+                slot.state = LoadState::Failed(err);
+            }
+        }
+    }
+
+    // The assets leading from `id` back to itself, if one of its new `dependencies`
+    // depends on it, directly or not.
+    fn find_cycle(
+        &self,
+        id: HandleId,
+        dependencies: &[Arc<StrongHandle>],
+    ) -> Option<Vec<HandleId>> {
+        let mut visited = HashSet::new();
+        let mut cycle = vec![id];
+        dependencies
+            .iter()
+            .any(|dep| self.find_path(dep.id, id, &mut visited, &mut cycle))
+            .then_some(cycle)
+    }
+
+    // Depth first search for `to`, pushing the assets on the way onto `path`. Assets in
+    // `visited` were already searched and did not lead there.
+    fn find_path(
+        &self,
+        from: HandleId,
+        to: HandleId,
+        visited: &mut HashSet<HandleId>,
+        path: &mut Vec<HandleId>,
+    ) -> bool {
+        if from == to {
+            return true;
+        }
+        if !visited.insert(from) {
+            return false;
+        }
+        path.push(from);
+        let found = self.slots.get(&from).is_some_and(|slot| {
+            slot.dependencies
+                .iter()
+                .any(|dep| self.find_path(dep.id, to, visited, path))
+        });
+        if !found {
+            path.pop();
+        }
+        found
+    }
+
+    // Reject the load that closed `cycle`, which starts at the asset being loaded. A
+    // reload keeps the previous version. Otherwise none of the assets in the loop can
+    // ever finish loading, so they all fail, and drop their dependencies so the strong
+    // handles they hold on each other are freed.
+    fn fail_cycle(&mut self, cycle: &[HandleId]) {
+        let paths: Vec<PathBuf> = cycle
+            .iter()
+            .map(|id| self.slots.get(id).and_then(|slot| slot.path.clone()))
+            .map(Option::unwrap_or_default)
+            .collect();
+        if self.slots[&cycle[0]].value.is_some() {
+            let err = AssetError::Cycle([&paths[..], &paths[..1]].concat());
+            log::error!("{err}, keeping the previous version");
+            return;
+        }
+        for (i, id) in cycle.iter().enumerate() {
+            let Some(slot) = self.slots.get_mut(id) else {
+                continue;
+            };
+            let path = [&paths[i..], &paths[..=i]].concat();
+            slot.value = None;
+            slot.dependencies.clear();
+            slot.state = LoadState::Failed(AssetError::Cycle(path));
+        }
+    }
+}
+
+// Exclusive access needs no locking.
+fn receiver<T>(mutex: &mut Mutex<Receiver<T>>) -> &mut Receiver<T> {
+    mutex.get_mut().unwrap_or_else(PoisonError::into_inner)
+}
+
+// Read the file behind a load, from disk on native and over HTTP on the web, where paths
+// are relative to the page.
+async fn read_file(path: &Path) -> std::io::Result<Vec<u8>> {
+    #[cfg(not(target_arch = "wasm32"))]
+    return std::fs::read(path);
+    #[cfg(target_arch = "wasm32")]
+    return crate::fetch::fetch(path).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_dir::TestDir;
+
+    struct Text(String);
+
+    impl Asset for Text {}
+
+    struct TextLoader;
+
+    impl AssetLoader for TextLoader {
+        type Asset = Text;
+        type Error = std::string::FromUtf8Error;
+
+        fn extensions(&self) -> &[&str] {
+            &["txt"]
+        }
+
+        async fn load(&self, bytes: &[u8], _ctx: &mut LoadContext) -> Result<Text, Self::Error> {
+            String::from_utf8(bytes.to_vec()).map(Text)
+        }
+    }
+
+    // Each line of a `.list` file is the path of a text file it depends on.
+    struct TextList(Vec<Handle<Text>>);
+
+    impl Asset for TextList {}
+
+    struct TextListLoader;
+
+    impl AssetLoader for TextListLoader {
+        type Asset = TextList;
+        type Error = std::str::Utf8Error;
+
+        fn extensions(&self) -> &[&str] {
+            &["list"]
+        }
+
+        async fn load(&self, bytes: &[u8], ctx: &mut LoadContext) -> Result<TextList, Self::Error> {
+            let text = std::str::from_utf8(bytes)?;
+            Ok(TextList(text.lines().map(|line| ctx.load(line)).collect()))
+        }
+    }
+
+    // Each line of a `.chain` file is the path of another chain it depends on.
+    struct Chain(Vec<Handle<Chain>>);
+
+    impl Asset for Chain {}
+
+    struct ChainLoader;
+
+    impl AssetLoader for ChainLoader {
+        type Asset = Chain;
+        type Error = std::str::Utf8Error;
+
+        fn extensions(&self) -> &[&str] {
+            &["chain"]
+        }
+
+        async fn load(&self, bytes: &[u8], ctx: &mut LoadContext) -> Result<Chain, Self::Error> {
+            let text = std::str::from_utf8(bytes)?;
+            Ok(Chain(text.lines().map(|line| ctx.load(line)).collect()))
+        }
+    }
+
+    fn wait_for<T>(server: &mut AssetServer, handle: &Handle<T>) -> LoadState {
+        for _ in 0..500 {
+            server.update();
+            let state = server.load_state(handle);
+            if state != LoadState::Loading {
+                return state;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(2));
+        }
+        panic!("asset never finished loading");
+    }
+
+    fn text_server(dir: &TestDir) -> AssetServer {
+        let mut server = AssetServer::with_root(&dir.0);
+        server.register_loader(TextLoader);
+        server.register_loader(TextListLoader);
+        server
+    }
+
+    #[test]
+    fn test_load_and_dedupe() {
+        let dir = TestDir::new("assets_dedupe");
+        let mut server = text_server(&dir);
+        std::fs::write(server.root().join("hello.txt"), "hello").unwrap();
+
+        let a = server.load::<Text>("hello.txt");
+        let b = server.load::<Text>("hello.txt");
+        assert_eq!(a, b);
+        assert_eq!(wait_for(&mut server, &a), LoadState::Loaded);
+        assert_eq!(server.get(&b).unwrap().0, "hello");
+    }
+
+    #[test]
+    fn test_missing_file_fails() {
+        let dir = TestDir::new("assets_missing");
+        let mut server = text_server(&dir);
+        let handle = server.load::<Text>("does_not_exist.txt");
+        assert!(matches!(
+            wait_for(&mut server, &handle),
+            LoadState::Failed(AssetError::Io { .. })
+        ));
+    }
+
+    #[test]
+    fn test_loader_picked_by_extension() {
+        let dir = TestDir::new("assets_extension");
+        let mut server = text_server(&dir);
+        std::fs::write(server.root().join("hello.md"), "hello").unwrap();
+        let handle = server.load::<Text>("hello.md");
+        assert!(matches!(
+            wait_for(&mut server, &handle),
+            LoadState::Failed(AssetError::NoLoader { .. })
+        ));
+    }
+
+    #[test]
+    fn test_dependencies_load() {
+        let dir = TestDir::new("assets_dependencies");
+        let mut server = text_server(&dir);
+        std::fs::write(server.root().join("a.txt"), "a").unwrap();
+        std::fs::write(server.root().join("b.txt"), "b").unwrap();
+        std::fs::write(server.root().join("all.list"), "a.txt\nb.txt").unwrap();
+
+        let list = server.load::<TextList>("all.list");
+        let direct = server.load::<Text>("a.txt");
+        assert_eq!(wait_for(&mut server, &list), LoadState::Loaded);
+        for _ in 0..500 {
+            server.update();
+            if server.is_loaded_with_dependencies(list.id()) {
+                break;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(2));
+        }
+        let deps = &server.get(&list).unwrap().0;
+        assert_eq!(deps[0], direct);
+        assert_eq!(server.get(&deps[1]).unwrap().0, "b");
+    }
+
+    #[test]
+    fn test_dependency_cycles_fail() {
+        let dir = TestDir::new("assets_cycles");
+        let mut server = text_server(&dir);
+        server.register_loader(ChainLoader);
+        for (name, text) in [
+            ("a.chain", "b.chain"),
+            ("b.chain", "a.chain"),
+            ("self.chain", "self.chain"),
+            ("diamond.chain", "left.chain\nright.chain"),
+            ("left.chain", "end.chain"),
+            ("right.chain", "end.chain"),
+            ("end.chain", ""),
+        ] {
+            std::fs::write(server.root().join(name), text).unwrap();
+        }
+
+        let looped = server.load::<Chain>("a.chain");
+        let itself = server.load::<Chain>("self.chain");
+        let diamond = server.load::<Chain>("diamond.chain");
+        for _ in 0..500 {
+            server.update();
+            if !server.is_loading() {
+                break;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(2));
+        }
+        let path = |names: &[&str]| names.iter().map(PathBuf::from).collect();
+        assert_eq!(
+            server.load_state(&looped),
+            LoadState::Failed(AssetError::Cycle(path(&["a.chain", "b.chain", "a.chain"])))
+        );
+        assert_eq!(
+            server.load_state(&itself),
+            LoadState::Failed(AssetError::Cycle(path(&["self.chain", "self.chain"])))
+        );
+        assert!(server.is_loaded_with_dependencies(diamond.id()));
+        assert_eq!(server.get(&diamond).unwrap().0.len(), 2);
+
+        // Nothing in the loop keeps the other alive any more.
+        let id = looped.id();
+        drop(looped);
+        server.update();
+        server.update();
+        assert!(!server.contains(id));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_ron_and_json_loaders() {
+        use crate::loader::{JsonLoader, RonLoader};
+
+        #[derive(serde::Deserialize, Debug, PartialEq)]
+        struct Enemy {
+            name: String,
+            health: u32,
+        }
+
+        impl Asset for Enemy {}
+
+        let dir = TestDir::new("assets_serde");
+        let mut server = AssetServer::with_root(&dir.0);
+        server.register_loader(RonLoader::<Enemy>::new());
+        server.register_loader(JsonLoader::<Enemy>::new());
+        std::fs::write(
+            server.root().join("goblin.ron"),
+            "(name: \"goblin\", health: 5)",
+        )
+        .unwrap();
+        std::fs::write(
+            server.root().join("orc.json"),
+            r#"{"name": "orc", "health": 20}"#,
+        )
+        .unwrap();
+
+        let goblin = server.load::<Enemy>("goblin.ron");
+        let orc = server.load::<Enemy>("orc.json");
+        assert_eq!(wait_for(&mut server, &goblin), LoadState::Loaded);
+        assert_eq!(wait_for(&mut server, &orc), LoadState::Loaded);
+        assert_eq!(server.get(&goblin).unwrap().health, 5);
+        assert_eq!(server.get(&orc).unwrap().name, "orc");
+    }
+
+    #[test]
+    fn test_reload_on_change() {
+        let dir = TestDir::new("assets_reload");
+        let mut server = text_server(&dir);
+        let path = server.root().join("reload.txt");
+        std::fs::write(&path, "before").unwrap();
+        server.watch_for_changes();
+
+        let handle = server.load::<Text>("reload.txt");
+        wait_for(&mut server, &handle);
+        assert_eq!(server.version(handle.id()), 1);
+
+        // Make sure the new mtime differs even on coarse-grained filesystems.
+        std::thread::sleep(std::time::Duration::from_millis(1100));
+        std::fs::write(&path, "after").unwrap();
+        for _ in 0..500 {
+            server.update();
+            if server.version(handle.id()) == 2 {
+                break;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+        assert_eq!(server.get(&handle).unwrap().0, "after");
+    }
+
+    #[test]
+    fn test_freed_when_last_handle_drops() {
+        let mut server = AssetServer::new();
+        let handle = server.add(Text("runtime".into()));
+        let weak = handle.downgrade();
+        let id = handle.id();
+        let clone = handle.clone();
+
+        drop(handle);
+        server.update();
+        assert!(server.contains(id));
+
+        drop(clone);
+        server.update();
+        assert!(!server.contains(id));
+        assert!(weak.upgrade().is_none());
+    }
+}
@@ -0,0 +1,17 @@
+use crate::Asset;
+
+/// WGSL shader source. Validation and compilation are left to the renderer.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Shader {
+    pub source: String,
+}
+
+impl Asset for Shader {}
+
+impl Shader {
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, std::str::Utf8Error> {
+        Ok(Self {
+            source: std::str::from_utf8(bytes)?.to_owned(),
+        })
+    }
+}
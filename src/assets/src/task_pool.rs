@@ -0,0 +1,84 @@
+// Small fixed-size thread pool used for loading. On wasm there are no threads,
+// so jobs are spawned on the browser's event loop instead.
+
+use std::future::Future;
+
+#[cfg(not(target_arch = "wasm32"))]
+type Job = Box<dyn FnOnce() + Send + 'static>;
+
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) struct TaskPool {
+    sender: Option<std::sync::mpsc::Sender<Job>>,
+    workers: Vec<std::thread::JoinHandle<()>>,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl TaskPool {
+    pub(crate) fn new() -> Self {
+        use std::sync::{Arc, Mutex, mpsc};
+
+        let threads = std::thread::available_parallelism()
+            .map(|n| n.get())
+            .unwrap_or(1)
+            .clamp(1, 4);
+        let (sender, receiver) = mpsc::channel::<Job>();
+        let receiver = Arc::new(Mutex::new(receiver));
+        let workers = (0..threads)
+            .map(|i| {
+                let receiver = receiver.clone();
+                std::thread::Builder::new()
+                    .name(format!("asset-loader-{i}"))
+                    .spawn(move || {
+                        loop {
+                            // Only hold the lock while waiting, not while running the job.
+                            let job = match receiver.lock() {
+                                Ok(receiver) => receiver.recv(),
+                                Err(_) => return,
+                            };
+                            match job {
+                                Ok(job) => job(),
+                                Err(_) => return,
+                            }
+                        }
+                    })
+                    .expect("failed to spawn asset loader thread")
+            })
+            .collect();
+        Self {
+            sender: Some(sender),
+            workers,
+        }
+    }
+
+    pub(crate) fn spawn<F: Future<Output = ()> + Send + 'static>(&self, job: F) {
+        if let Some(sender) = &self.sender {
+            // Native loads only wait on reading files, which blocks the worker anyway.
+            let _ = sender.send(Box::new(move || pollster::block_on(job)));
+        }
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Drop for TaskPool {
+    fn drop(&mut self) {
+        // Closing the channel lets the workers finish what is queued and exit.
+        self.sender.take();
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+pub(crate) struct TaskPool;
+
+#[cfg(target_arch = "wasm32")]
+impl TaskPool {
+    pub(crate) fn new() -> Self {
+        Self
+    }
+
+    pub(crate) fn spawn<F: Future<Output = ()> + 'static>(&self, job: F) {
+        wasm_bindgen_futures::spawn_local(job);
+    }
+}
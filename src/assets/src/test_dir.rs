@@ -0,0 +1,21 @@
+// A directory for one test, unique to the test process so concurrent runs do not share
+// files, and removed again when the test ends, even by panicking. The engine's tests
+// include this file too.
+
+use std::path::PathBuf;
+
+pub(crate) struct TestDir(pub(crate) PathBuf);
+
+impl TestDir {
+    pub(crate) fn new(name: &str) -> Self {
+        let dir = std::env::temp_dir().join(format!("rusty_engine_{name}_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        Self(dir)
+    }
+}
+
+impl Drop for TestDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
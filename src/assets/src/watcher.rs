@@ -0,0 +1,98 @@
+// Polls the modification time of every loaded file on a background thread and reports
+// the ones that changed. Polling keeps this dependency free and works the same everywhere
+// std::fs does; it is only compiled for native targets.
+
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, Receiver},
+    },
+    thread::JoinHandle,
+    time::{Duration, SystemTime},
+};
+
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+type Watched = Arc<Mutex<HashMap<PathBuf, Option<SystemTime>>>>;
+
+pub(crate) struct FileWatcher {
+    watched: Watched,
+    // In a mutex so the watcher, and the asset server holding it, are `Sync`
+    changes: Mutex<Receiver<PathBuf>>,
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+fn modified(path: &PathBuf) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+impl FileWatcher {
+    pub(crate) fn new() -> Self {
+        let watched: Watched = Arc::default();
+        let stop = Arc::new(AtomicBool::new(false));
+        let (sender, changes) = mpsc::channel();
+
+        let thread = {
+            let watched = watched.clone();
+            let stop = stop.clone();
+            std::thread::Builder::new()
+                .name("asset-watcher".into())
+                .spawn(move || {
+                    while !stop.load(Ordering::Relaxed) {
+                        std::thread::sleep(POLL_INTERVAL);
+                        let Ok(mut watched) = watched.lock() else {
+                            return;
+                        };
+                        for (path, last) in watched.iter_mut() {
+                            let now = modified(path);
+                            if now != *last {
+                                *last = now;
+                                // Deleted files are not reported, the next write will be.
+                                if now.is_some() && sender.send(path.clone()).is_err() {
+                                    return;
+                                }
+                            }
+                        }
+                    }
+                })
+                .expect("failed to spawn asset watcher thread")
+        };
+
+        Self {
+            watched,
+            changes: Mutex::new(changes),
+            stop,
+            thread: Some(thread),
+        }
+    }
+
+    pub(crate) fn watch(&self, path: PathBuf) {
+        if let Ok(mut watched) = self.watched.lock() {
+            watched.entry(path).or_insert_with_key(modified);
+        }
+    }
+
+    /// Files that changed since the last call.
+    pub(crate) fn changed(&self) -> Vec<PathBuf> {
+        let Ok(changes) = self.changes.lock() else {
+            return Vec::new();
+        };
+        let mut changed: Vec<PathBuf> = changes.try_iter().collect();
+        changed.sort();
+        changed.dedup();
+        changed
+    }
+}
+
+impl Drop for FileWatcher {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
@@ -1,20 +1,49 @@
+#[cfg(feature = "serde")]
+pub mod prefab;
 pub mod prelude;
+#[cfg(feature = "serde")]
+pub mod saves;
+#[cfg(feature = "serde")]
+pub mod scene;
+#[cfg(feature = "serde")]
+pub mod settings;
+#[cfg(all(test, feature = "serde"))]
+#[path = "../../assets/src/test_dir.rs"]
+mod test_dir;
+pub mod window;
 
+use assets::{AssetLoader, AssetServer, Shader};
 use jaren_ecs::system::World;
-use rendering::renderer::Renderer;
-use std::{ptr::with_exposed_provenance, sync::Arc};
-use web_sys::console::log_1;
+use rendering::{
+    animation::animate_sprites,
+    atlas::TextureAtlasLoader,
+    renderer::{Renderer, RendererError, SHADER_SOURCE_PATH},
+    screenshot::ScreenshotTarget,
+    surface::SurfaceOptions,
+};
+use std::{fmt, sync::Arc};
+use web_time::Instant;
+use window::{WindowConfig, window_attributes};
 use winit::{
-    application::ApplicationHandler,
-    event::ElementState,
-    event_loop::EventLoop,
-    keyboard::Key,
-    window::{Window, WindowAttributes},
+    application::ApplicationHandler, event::ElementState, event_loop::EventLoop, keyboard::Key,
+    window::Window,
 };
 
-#[derive(Default)]
+/// Everything about how the game starts. With the `serde` feature it can also be read from
+/// a settings file, see [`settings`].
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
 pub struct GameConfig {
     pub title: String,
+    pub window: WindowConfig,
+    /// Reload textures and `shader.wgsl` when they change on disk. Native only.
+    pub hot_reload: bool,
+    /// Present mode, MSAA, surface format and frame latency. Also stored as a world
+    /// resource, changes to it are applied before the next frame.
+    pub surface: SurfaceOptions,
+    /// Most verbose log level passed on to the logger, which may filter further.
+    pub log_level: Option<log::LevelFilter>,
 }
 
 pub enum FunctionMode {
@@ -22,27 +51,146 @@ pub enum FunctionMode {
     Update,
 }
 
+/// Frame timing, stored as a world resource.
+#[derive(Default)]
+pub struct Time {
+    delta: f32,
+    elapsed: f32,
+}
+
+impl Time {
+    /// Seconds since the previous frame.
+    pub fn delta(&self) -> f32 {
+        self.delta
+    }
+
+    /// Seconds since the first frame.
+    pub fn elapsed(&self) -> f32 {
+        self.elapsed
+    }
+}
+
+/// Screenshots requested by systems, taken from the next frame. Stored as a world resource.
+#[derive(Default)]
+pub struct Screenshots {
+    requests: Vec<ScreenshotTarget>,
+}
+
+impl Screenshots {
+    /// Save the next frame as PNG or pass it to a callback, see
+    /// [`Renderer::request_screenshot`].
+    pub fn take(&mut self, target: impl Into<ScreenshotTarget>) {
+        self.requests.push(target.into());
+    }
+}
+
+/// A failure that stops the app, passed to the handler set with [`App::on_error`].
+#[derive(Debug)]
+pub enum AppError {
+    CreateWindow(winit::error::OsError),
+    /// The canvas to draw into on the web could not be found or created.
+    Canvas(String),
+    Renderer(RendererError),
+}
+
+impl fmt::Display for AppError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AppError::CreateWindow(err) => write!(f, "failed to create window: {err}"),
+            AppError::Canvas(message) => write!(f, "{message}"),
+            AppError::Renderer(err) => write!(f, "renderer error: {err}"),
+        }
+    }
+}
+
+impl std::error::Error for AppError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            AppError::CreateWindow(err) => Some(err),
+            AppError::Renderer(err) => Some(err),
+            AppError::Canvas(_) => None,
+        }
+    }
+}
+
+impl From<RendererError> for AppError {
+    fn from(err: RendererError) -> Self {
+        AppError::Renderer(err)
+    }
+}
+
+type AppSystem = Box<dyn FnMut(&mut World)>;
+type ErrorHandler = Box<dyn FnMut(&AppError)>;
+
 pub struct App {
     window: Option<Arc<Window>>,
     renderer: Option<Renderer>,
     config: GameConfig,
-    ecs: System,
-    startup_systems: Vec<Box<dyn FnMut(&mut System)>>,
-    update_systems: Vec<Box<dyn FnMut(&mut System)>>,
+    world: World,
+    startup_systems: Vec<AppSystem>,
+    update_systems: Vec<AppSystem>,
+    // One per animation event type, see `add_animation_events`.
+    animation_systems: Vec<fn(&mut World, f32)>,
+    last_frame: Option<Instant>,
+    error_handler: ErrorHandler,
+    // Saves surface option changes made in-game to the settings file.
+    #[cfg(all(feature = "serde", not(target_arch = "wasm32")))]
+    settings_writer: Option<settings::SettingsWriter>,
+    // Components scenes and prefabs can hold, shared with the scene loader and spawner once running.
+    #[cfg(feature = "serde")]
+    registry: jaren_ecs::snapshot::ComponentRegistry,
 }
 
 impl App {
     pub fn new(config: GameConfig) -> Self {
+        if let Some(level) = config.log_level {
+            log::set_max_level(level);
+        }
+        let mut world = World::new();
+        let mut assets = AssetServer::new();
+        assets.register_loader(TextureAtlasLoader);
+        #[cfg(feature = "serde")]
+        assets.register_loader(assets::loader::RonLoader::<
+            rendering::animation::AnimationClip,
+        >::new());
+        #[cfg(not(target_arch = "wasm32"))]
+        if config.hot_reload {
+            assets.watch_for_changes();
+        }
+        world.insert_resource(assets);
+        world.insert_resource(Time::default());
+        world.insert_resource(Screenshots::default());
+        world.insert_resource(config.surface);
         Self {
             window: None,
             renderer: None,
             config,
-            ecs: System::new(),
+            world,
             startup_systems: Vec::new(),
             update_systems: Vec::new(),
+            animation_systems: vec![animate_sprites::<()>],
+            last_frame: None,
+            error_handler: Box::new(|err| log::error!("{err}")),
+            #[cfg(all(feature = "serde", not(target_arch = "wasm32")))]
+            settings_writer: None,
+            #[cfg(feature = "serde")]
+            registry: Default::default(),
         }
     }
     pub fn run(mut self) {
+        #[cfg(feature = "serde")]
+        {
+            let registry = Arc::new(std::mem::take(&mut self.registry));
+            if let Some(assets) = self.world.resource_mut::<AssetServer>() {
+                assets.register_loader(scene::SceneLoader::new(registry.clone()));
+                assets.register_loader(prefab::PrefabLoader::new(registry.clone()));
+            }
+            self.world
+                .insert_resource(scene::SceneSpawner::new(registry.clone()));
+            self.world
+                .insert_resource(prefab::PrefabSpawner::new(registry));
+        }
+
         let event_loop = EventLoop::new().unwrap();
 
         #[cfg(target_arch = "wasm32")]
@@ -56,10 +204,77 @@ impl App {
             .expect("Failed to run event loop");
     }
 
+    /// Register a loader for a custom asset type or file format.
+    pub fn add_asset_loader<L: AssetLoader>(mut self, loader: L) -> Self {
+        if let Some(assets) = self.world.resource_mut::<AssetServer>() {
+            assets.register_loader(loader);
+        }
+        self
+    }
+
+    /// Also animate entities with an `Animator<E>`, so their clips can send `E` events.
+    /// Animators without events (`Animator<()>`) are always updated.
+    pub fn add_animation_events<E: Clone + Send + Sync + 'static>(mut self) -> Self {
+        self.animation_systems.push(animate_sprites::<E>);
+        self
+    }
+
+    /// Let scenes and prefabs hold `T` under `name`, the name it is written as in their
+    /// files.
+    #[cfg(feature = "serde")]
+    pub fn register_component_as<T>(mut self, name: &str) -> Self
+    where
+        T: jaren_ecs::system::Component + Clone + serde::Serialize + serde::de::DeserializeOwned,
+    {
+        self.registry.register_as::<T>(name);
+        self
+    }
+
+    /// Let scenes and prefabs hold `T` under `name` like
+    /// [`register_component_as`](Self::register_component_as). `T` holds entity ids,
+    /// which are replaced by the spawned ones.
+    #[cfg(feature = "serde")]
+    pub fn register_component_with_entities_as<T>(mut self, name: &str) -> Self
+    where
+        T: jaren_ecs::system::Component
+            + Clone
+            + serde::Serialize
+            + serde::de::DeserializeOwned
+            + jaren_ecs::entity_map::MapEntities,
+    {
+        self.registry.register_with_entities_as::<T>(name);
+        self
+    }
+
+    /// Save the [`SurfaceOptions`] resource to the settings file at `path` whenever it
+    /// changes in-game, on a background thread. Only the `surface` settings are written,
+    /// see [`settings::save_surface_options`]. Overrides from the environment or command
+    /// line are not written back.
+    #[cfg(all(feature = "serde", not(target_arch = "wasm32")))]
+    pub fn persist_settings(mut self, path: impl Into<std::path::PathBuf>) -> Self {
+        self.settings_writer = Some(settings::SettingsWriter::new(path.into()));
+        self
+    }
+
+    /// Called with the error when the window or renderer fails, before the app exits.
+    /// By default the error is logged.
+    pub fn on_error<F>(mut self, handler: F) -> Self
+    where
+        F: FnMut(&AppError) + 'static,
+    {
+        self.error_handler = Box::new(handler);
+        self
+    }
+
+    fn fail(&mut self, event_loop: &winit::event_loop::ActiveEventLoop, err: AppError) {
+        (self.error_handler)(&err);
+        event_loop.exit();
+    }
+
     // rework this to use scheudler along with the proper ECS system.
     pub fn add_system<F>(mut self, mode: FunctionMode, func: F) -> Self
     where
-        F: FnMut(&mut System) + 'static,
+        F: FnMut(&mut World) + 'static,
     {
         match mode {
             FunctionMode::Startup => self.startup_systems.push(Box::new(func)),
@@ -72,70 +287,33 @@ impl App {
 impl ApplicationHandler for App {
     fn resumed(&mut self, event_loop: &winit::event_loop::ActiveEventLoop) {
         if self.window.is_none() {
-            let window = {
-                #[cfg(target_arch = "wasm32")]
-                {
-                    use wasm_bindgen::JsCast;
-                    use web_sys::{HtmlCanvasElement, window};
-                    use winit::platform::web::WindowAttributesExtWebSys;
-
-                    let canvas = {
-                        log_1(&"resumed: Getting window and document".into());
-                        let window = window().expect("Failed to get window");
-                        let document = window.document().expect("Failed to get document");
-                        let body = document.body().expect("Document should have a body");
-                        log_1(&"resumed: Got window, document, body".into());
-
-                        // Create canvas element
-                        log_1(&"resumed: Creating canvas element".into());
-                        let canvas_element = document
-                            .create_element("canvas")
-                            .expect("Failed to create canvas element")
-                            .dyn_into::<HtmlCanvasElement>()
-                            .map_err(|_| ())
-                            .expect("Failed to cast element to HtmlCanvasElement");
-                        log_1(&"resumed: Canvas element created".into());
-
-                        canvas_element.set_id("game-canvas-created-by-rust");
-
-                        // Append canvas to the document body
-                        log_1(&"resumed: Appending canvas to body".into());
-                        body.append_child(&canvas_element)
-                            .expect("Failed to append canvas to body");
-                        log_1(&"resumed: Canvas appended to body".into());
-
-                        canvas_element
-                    };
-
-                    log_1(&"resumed: Creating winit window with canvas".into());
-                    let mut window_attrs = WindowAttributes::default();
-                    window_attrs = window_attrs
-                        .with_title(&self.config.title)
-                        .with_fullscreen(Some(winit::window::Fullscreen::Borderless(None)))
-                        .with_canvas(Some(canvas)); // Use the dynamically created canvas
-                    log_1(&"resumed: Winit window created".into()); // Log before storing
-                    event_loop.create_window(window_attrs).unwrap()
-                }
-                #[cfg(not(target_arch = "wasm32"))]
-                {
-                    let window_attributes =
-                        WindowAttributes::default().with_title(&self.config.title);
+            let window = window_attributes(&self.config.title, &self.config.window, event_loop)
+                .and_then(|attributes| {
                     event_loop
-                        .create_window(window_attributes)
-                        .expect("failed to create window")
-                }
+                        .create_window(attributes)
+                        .map_err(AppError::CreateWindow)
+                });
+            let window = match window {
+                Ok(window) => window,
+                Err(err) => return self.fail(event_loop, err),
             };
 
             let window_arc = Arc::new(window); // Create Arc<Window>
             self.window = Some(window_arc.clone()); // Store the Arc
 
-            self.renderer = Some(pollster::block_on(Renderer::new(
-                window_arc.clone(),
-                "25010123242900.jpeg",
-            )));
+            match pollster::block_on(Renderer::new(window_arc.clone(), self.config.surface)) {
+                Ok(renderer) => self.renderer = Some(renderer),
+                Err(err) => return self.fail(event_loop, err.into()),
+            }
 
             if let Some(renderer) = self.renderer.as_mut() {
                 renderer.resize(renderer.size()); // Call resize with initial size
+                if let Some(assets) = self.world.resource_mut::<AssetServer>()
+                    && self.config.hot_reload
+                    && cfg!(not(target_arch = "wasm32"))
+                {
+                    renderer.watch_shader(assets.load::<Shader>(SHADER_SOURCE_PATH));
+                }
             }
 
             #[cfg(target_arch = "wasm32")]
@@ -143,9 +321,13 @@ impl ApplicationHandler for App {
                 use web_sys::console::log_1;
                 log_1(&"resumed: Renderer initialized and configured".into()); // Update log
             }
+
+            // Run all startup systems after ECS and renderer are ready
+            // todo: startup systems with scheduler..??
+            for system in &mut self.startup_systems {
+                (system)(&mut self.world);
+            }
         }
-        // Run all startup systems after ECS and renderer are ready
-        // todo: startup systems with scheduler..??
     }
     fn window_event(
         &mut self,
@@ -153,7 +335,7 @@ impl ApplicationHandler for App {
         window_id: winit::window::WindowId,
         event: winit::event::WindowEvent,
     ) {
-        if self.window.as_ref().map_or(false, |w| w.id() == window_id) {
+        if self.window.as_ref().is_some_and(|w| w.id() == window_id) {
             let renderer = match self.renderer.as_mut() {
                 Some(r) => r,
                 None => return,
@@ -161,29 +343,66 @@ impl ApplicationHandler for App {
 
             match event {
                 winit::event::WindowEvent::CloseRequested => {
+                    renderer.flush_screenshots();
                     event_loop.exit();
                 }
                 winit::event::WindowEvent::Resized(physical_size) => {
                     renderer.resize(physical_size);
                 }
                 winit::event::WindowEvent::RedrawRequested => {
+                    let now = Instant::now();
+                    let delta = self
+                        .last_frame
+                        .replace(now)
+                        .map_or(0.0, |last| (now - last).as_secs_f32());
+                    if let Some(time) = self.world.resource_mut::<Time>() {
+                        time.delta = delta;
+                        time.elapsed += delta;
+                    }
+                    for animate in &self.animation_systems {
+                        animate(&mut self.world, delta);
+                    }
                     // Run all update systems per frame
                     for system in &mut self.update_systems {
-                        (system)(&mut self.ecs);
+                        (system)(&mut self.world);
+                    }
+                    if let Some(assets) = self.world.resource_mut::<AssetServer>() {
+                        assets.update();
+                    }
+                    #[cfg(feature = "serde")]
+                    scene::spawn_scenes(&mut self.world);
+                    #[cfg(feature = "serde")]
+                    prefab::spawn_prefabs(&mut self.world);
+                    if let Some(assets) = self.world.resource::<AssetServer>() {
+                        renderer.prepare_assets(assets);
+                        renderer.prepare_cameras(&self.world, assets);
+                        renderer.prepare_sprites(&self.world, assets);
+                    }
+                    if let Some(screenshots) = self.world.resource_mut::<Screenshots>() {
+                        for target in screenshots.requests.drain(..) {
+                            renderer.request_screenshot(target);
+                        }
+                    }
+                    if let Some(options) = self.world.resource::<SurfaceOptions>()
+                        && options != renderer.surface_options()
+                    {
+                        renderer.set_surface_options(*options);
+                        #[cfg(all(feature = "serde", not(target_arch = "wasm32")))]
+                        if let Some(writer) = &self.settings_writer {
+                            writer.save(*options);
+                        }
                     }
                     renderer.update();
-                    match renderer.render() {
-                        Ok(_) => {}
-                        Err(wgpu::SurfaceError::Lost) => renderer.resize(renderer.size()), // Use getter method
-                        Err(wgpu::SurfaceError::OutOfMemory) => event_loop.exit(),
-                        Err(e) => eprintln!("Error rendering frame: {:?}", e),
+                    if let Err(err) = renderer.render() {
+                        renderer.flush_screenshots();
+                        return self.fail(event_loop, err.into());
                     }
                     if let Some(window) = self.window.as_ref() {
                         window.request_redraw();
                     }
                 }
                 winit::event::WindowEvent::KeyboardInput { event, .. } => {
-                    if let Key::Named(named_key) = event.logical_key {
+                    if let Key::Named(_named_key) = event.logical_key {
                         match event.state {
                             ElementState::Pressed => {}
                             ElementState::Released => {}
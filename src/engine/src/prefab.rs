@@ -0,0 +1,685 @@
+//! Reusable entity templates such as "Goblin" or "Chest", described in RON files.
+//!
+//! A prefab is one root entity with optional children. Any entity can be an instance of
+//! another prefab, with components added and fields overridden:
+//!
+//! ```ron
+//! (
+//!     prefab: "prefabs/goblin.ron",
+//!     components: { "Name": ("Goblin chief") },
+//!     overrides: { "Health.max": 40, "Behaviour": "Guard" },
+//!     children: [
+//!         (prefab: "prefabs/torch.ron"),
+//!         (id: 1, components: { "Follow": (target: 0) }),
+//!     ],
+//! )
+//! ```
+//!
+//! Overrides name a component and a path into it, see [`ComponentRegistry::set_field`],
+//! and their values are written the way JSON would hold them. `id`s are local to the file
+//! they appear in, the root is `0` unless given one, and entity ids in components
+//! registered with
+//! [`register_with_entities_as`](ComponentRegistry::register_with_entities_as) are
+//! replaced by the spawned entities. Overridden values are not.
+
+use assets::{Asset, AssetLoader, AssetServer, Handle, HandleId, LoadContext, LoadState};
+use jaren_ecs::{
+    entity_map::EntityMap,
+    hierarchy::Parent,
+    snapshot::{ComponentRegistry, ComponentsSeed, FieldName},
+    system::{Component, Entity, World},
+};
+use serde::{
+    Deserializer, Serialize,
+    de::{self, DeserializeSeed, MapAccess, SeqAccess, Visitor},
+};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum PrefabError {
+    /// The text is not a valid prefab, or holds a component that is not registered.
+    Parse(String),
+    /// A prefab this one includes has not finished loading yet.
+    Loading(PathBuf),
+    /// A prefab this one includes failed to load.
+    Load { path: PathBuf, message: String },
+    /// An override value cannot be represented as JSON, e.g. a map with non-string keys.
+    Value { key: String, message: String },
+    /// An override names a component the entity does not have, or a field or value that
+    /// does not fit the component.
+    Override {
+        prefab: PathBuf,
+        key: String,
+        message: String,
+    },
+}
+
+impl fmt::Display for PrefabError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PrefabError::Parse(message) => write!(f, "invalid prefab: {message}"),
+            PrefabError::Loading(path) => write!(f, "prefab {} is still loading", path.display()),
+            PrefabError::Load { path, message } => {
+                write!(f, "failed to load prefab {}: {message}", path.display())
+            }
+            PrefabError::Value { key, message } => {
+                write!(f, "invalid value for override `{key}`: {message}")
+            }
+            PrefabError::Override {
+                prefab,
+                key,
+                message,
+            } => write!(
+                f,
+                "invalid override `{key}` in {}: {message}",
+                prefab.display()
+            ),
+        }
+    }
+}
+
+impl std::error::Error for PrefabError {}
+
+/// Field overrides for one spawn of a prefab, applied to its root entity after the
+/// prefab's own.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PrefabOverrides {
+    values: Vec<(String, serde_json::Value)>,
+}
+
+impl PrefabOverrides {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Override the field at `key`, a registered component name optionally followed by a
+    /// path into it: `set("Health.max", 40)?`.
+    pub fn set(mut self, key: &str, value: impl Serialize) -> Result<Self, PrefabError> {
+        let value = serde_json::to_value(value).map_err(|err| PrefabError::Value {
+            key: key.to_string(),
+            message: err.to_string(),
+        })?;
+        self.values.push((key.to_string(), value));
+        Ok(self)
+    }
+}
+
+struct NestedPrefab {
+    path: PathBuf,
+    handle: Handle<Prefab>,
+}
+
+impl NestedPrefab {
+    fn get<'a>(&self, assets: &'a AssetServer) -> Result<&'a Prefab, PrefabError> {
+        assets
+            .get(&self.handle)
+            .ok_or_else(|| match assets.load_state(&self.handle) {
+                LoadState::Failed(err) => PrefabError::Load {
+                    path: self.path.clone(),
+                    message: err.to_string(),
+                },
+                _ => PrefabError::Loading(self.path.clone()),
+            })
+    }
+}
+
+struct PrefabNode {
+    id: Option<Entity>,
+    prefab: Option<NestedPrefab>,
+    components: Vec<Box<dyn Component>>,
+    overrides: Vec<(String, serde_json::Value)>,
+    children: Vec<PrefabNode>,
+}
+
+/// An entity template, loaded by the [`PrefabLoader`] along with the prefabs it includes.
+pub struct Prefab {
+    path: PathBuf,
+    root: PrefabNode,
+}
+
+impl Asset for Prefab {}
+
+impl Prefab {
+    /// Path of the prefab file, relative to the asset server root.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Check that every prefab this one includes, directly or not, has loaded. Prefabs
+    /// that include each other fail to load, see [`AssetError::Cycle`](assets::AssetError::Cycle).
+    pub fn validate(&self, assets: &AssetServer) -> Result<(), PrefabError> {
+        self.validate_with(assets, &mut HashSet::new())
+    }
+
+    // `validated` holds the prefabs already checked, so one included many times over is
+    // only checked once.
+    fn validate_with(
+        &self,
+        assets: &AssetServer,
+        validated: &mut HashSet<HandleId>,
+    ) -> Result<(), PrefabError> {
+        let mut nodes = vec![&self.root];
+        while let Some(node) = nodes.pop() {
+            if let Some(nested) = &node.prefab
+                && validated.insert(nested.handle.id())
+            {
+                nested.get(assets)?.validate_with(assets, validated)?;
+            }
+            nodes.extend(&node.children);
+        }
+        Ok(())
+    }
+
+    /// Spawn the prefab, with `overrides` applied to its root, and return the root
+    /// entity. Included prefabs are looked up in `assets`. Nothing is spawned on error.
+    pub fn spawn(
+        &self,
+        world: &mut World,
+        assets: &AssetServer,
+        registry: &ComponentRegistry,
+        overrides: &PrefabOverrides,
+    ) -> Result<Entity, PrefabError> {
+        self.validate(assets)?;
+        let root = world.alloc_entity();
+        let mut planner = Planner {
+            world,
+            assets,
+            registry,
+            planned: Vec::new(),
+        };
+        let index = planner.prefab(self, root, None)?;
+        for (key, value) in &overrides.values {
+            planner.set_field(index, &self.path, key, value)?;
+        }
+        for planned in planner.planned {
+            world.get_archetype(planned.entity, planned.components);
+        }
+        Ok(root)
+    }
+}
+
+// An entity to spawn once the whole prefab has been put together without errors.
+struct Planned {
+    entity: Entity,
+    components: Vec<Box<dyn Component>>,
+}
+
+struct Planner<'a> {
+    world: &'a mut World,
+    assets: &'a AssetServer,
+    registry: &'a ComponentRegistry,
+    planned: Vec<Planned>,
+}
+
+impl Planner<'_> {
+    // Plan `prefab`, which has been validated, with `root` as its root entity. Returns
+    // the root's index in `planned`.
+    fn prefab(
+        &mut self,
+        prefab: &Prefab,
+        root: Entity,
+        parent: Option<Entity>,
+    ) -> Result<usize, PrefabError> {
+        // Entities for every node, in the order `node` visits them.
+        let mut entities = vec![root];
+        let mut map = EntityMap::new();
+        map.insert(prefab.root.id.unwrap_or(0), root);
+        self.allocate(&prefab.root.children, &mut entities, &mut map);
+        let mut entities = entities.into_iter();
+        self.node(prefab, &prefab.root, &mut entities, parent, &map)
+    }
+
+    fn allocate(&mut self, nodes: &[PrefabNode], entities: &mut Vec<Entity>, map: &mut EntityMap) {
+        for node in nodes {
+            let entity = self.world.alloc_entity();
+            entities.push(entity);
+            if let Some(id) = node.id {
+                map.insert(id, entity);
+            }
+            self.allocate(&node.children, entities, map);
+        }
+    }
+
+    fn node(
+        &mut self,
+        prefab: &Prefab,
+        node: &PrefabNode,
+        entities: &mut impl Iterator<Item = Entity>,
+        parent: Option<Entity>,
+        map: &EntityMap,
+    ) -> Result<usize, PrefabError> {
+        let entity = entities
+            .next()
+            .expect("an entity was allocated for every node");
+        let index = match &node.prefab {
+            Some(nested) => self.prefab(nested.get(self.assets)?, entity, parent)?,
+            None => {
+                let mut components: Vec<Box<dyn Component>> = Vec::new();
+                if let Some(parent) = parent {
+                    components.push(Box::new(Parent(parent)));
+                }
+                self.planned.push(Planned { entity, components });
+                self.planned.len() - 1
+            }
+        };
+
+        for component in &node.components {
+            let Some(mut component) = self.registry.clone_component(component.as_ref()) else {
+                continue;
+            };
+            self.registry.map_entities(component.as_mut(), map);
+            let components = &mut self.planned[index].components;
+            let type_id = component.as_any().type_id();
+            match components
+                .iter_mut()
+                .find(|existing| existing.as_any().type_id() == type_id)
+            {
+                Some(existing) => *existing = component,
+                None => components.push(component),
+            }
+        }
+        for (key, value) in &node.overrides {
+            self.set_field(index, &prefab.path, key, value)?;
+        }
+        for child in &node.children {
+            self.node(prefab, child, entities, Some(entity), map)?;
+        }
+        Ok(index)
+    }
+
+    fn set_field(
+        &mut self,
+        index: usize,
+        prefab: &Path,
+        key: &str,
+        value: &serde_json::Value,
+    ) -> Result<(), PrefabError> {
+        let error = |message: String| PrefabError::Override {
+            prefab: prefab.into(),
+            key: key.to_string(),
+            message,
+        };
+        let (name, path) = key.split_once('.').unwrap_or((key, ""));
+        let registry = self.registry;
+        let component = self.planned[index]
+            .components
+            .iter_mut()
+            .find(|component| registry.component_name(component.as_ref()) == Some(name))
+            .ok_or_else(|| error(format!("the entity has no `{name}` component")))?;
+        registry
+            .set_field(component, path, value.clone())
+            .map_err(|err| error(err.to_string()))
+    }
+}
+
+// Reads a node, starting to load the prefabs it includes.
+struct NodeSeed<'a> {
+    registry: &'a ComponentRegistry,
+    ctx: &'a mut LoadContext,
+}
+
+impl<'de> DeserializeSeed<'de> for NodeSeed<'_> {
+    type Value = PrefabNode;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error> {
+        deserializer.deserialize_struct(
+            "Prefab",
+            &["id", "prefab", "components", "overrides", "children"],
+            self,
+        )
+    }
+}
+
+impl<'de> Visitor<'de> for NodeSeed<'_> {
+    type Value = PrefabNode;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("an entity with its components and children")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+        let mut node = PrefabNode {
+            id: None,
+            prefab: None,
+            components: Vec::new(),
+            overrides: Vec::new(),
+            children: Vec::new(),
+        };
+        while let Some(key) = map.next_key::<FieldName>()? {
+            match key.as_str() {
+                "id" => node.id = Some(map.next_value()?),
+                "prefab" => {
+                    let path: PathBuf = map.next_value()?;
+                    let handle = self.ctx.load(&path);
+                    node.prefab = Some(NestedPrefab { path, handle });
+                }
+                "components" => {
+                    node.components = map.next_value_seed(ComponentsSeed(self.registry))?
+                }
+                "overrides" => {
+                    let overrides: HashMap<String, serde_json::Value> = map.next_value()?;
+                    node.overrides = overrides.into_iter().collect();
+                    node.overrides.sort_by(|a, b| a.0.cmp(&b.0));
+                }
+                "children" => {
+                    node.children = map.next_value_seed(ChildrenSeed {
+                        registry: self.registry,
+                        ctx: &mut *self.ctx,
+                    })?
+                }
+                _ => {
+                    map.next_value::<de::IgnoredAny>()?;
+                }
+            }
+        }
+        Ok(node)
+    }
+}
+
+struct ChildrenSeed<'a> {
+    registry: &'a ComponentRegistry,
+    ctx: &'a mut LoadContext,
+}
+
+impl<'de> DeserializeSeed<'de> for ChildrenSeed<'_> {
+    type Value = Vec<PrefabNode>;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error> {
+        deserializer.deserialize_seq(self)
+    }
+}
+
+impl<'de> Visitor<'de> for ChildrenSeed<'_> {
+    type Value = Vec<PrefabNode>;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a list of entities")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+        let mut children = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+        loop {
+            let seed = NodeSeed {
+                registry: self.registry,
+                ctx: &mut *self.ctx,
+            };
+            match seq.next_element_seed(seed)? {
+                Some(child) => children.push(child),
+                None => return Ok(children),
+            }
+        }
+    }
+}
+
+/// Loads [`Prefab`]s from `.ron` files with the components in its registry.
+pub struct PrefabLoader {
+    registry: Arc<ComponentRegistry>,
+}
+
+impl PrefabLoader {
+    pub fn new(registry: Arc<ComponentRegistry>) -> Self {
+        Self { registry }
+    }
+}
+
+impl AssetLoader for PrefabLoader {
+    type Asset = Prefab;
+    type Error = PrefabError;
+
+    fn extensions(&self) -> &[&str] {
+        &["ron"]
+    }
+
+    async fn load(&self, bytes: &[u8], ctx: &mut LoadContext) -> Result<Prefab, PrefabError> {
+        let parse = |message: String| PrefabError::Parse(message);
+        let path = ctx.path().to_path_buf();
+        let mut deserializer =
+            ron::de::Deserializer::from_bytes(bytes).map_err(|err| parse(err.to_string()))?;
+        let seed = NodeSeed {
+            registry: &self.registry,
+            ctx,
+        };
+        let root = seed
+            .deserialize(&mut deserializer)
+            .map_err(|err| parse(deserializer.span_error(err).to_string()))?;
+        deserializer.end().map_err(|err| parse(err.to_string()))?;
+        Ok(Prefab { path, root })
+    }
+}
+
+/// Identifies one spawn of a prefab by the [`PrefabSpawner`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct PrefabInstance(u64);
+
+/// Spawns prefabs into the world once they and the prefabs they include have loaded.
+/// Stored as a world resource.
+pub struct PrefabSpawner {
+    registry: Arc<ComponentRegistry>,
+    next_instance: u64,
+    queued: Vec<(PrefabInstance, Handle<Prefab>, PrefabOverrides)>,
+    spawned: HashMap<PrefabInstance, Entity>,
+}
+
+impl PrefabSpawner {
+    pub fn new(registry: Arc<ComponentRegistry>) -> Self {
+        Self {
+            registry,
+            next_instance: 0,
+            queued: Vec::new(),
+            spawned: HashMap::new(),
+        }
+    }
+
+    /// Spawn `prefab` as soon as it has loaded.
+    pub fn spawn(&mut self, prefab: Handle<Prefab>) -> PrefabInstance {
+        self.spawn_with(prefab, PrefabOverrides::new())
+    }
+
+    /// Spawn `prefab` as soon as it has loaded, with `overrides` applied to its root.
+    pub fn spawn_with(
+        &mut self,
+        prefab: Handle<Prefab>,
+        overrides: PrefabOverrides,
+    ) -> PrefabInstance {
+        let instance = PrefabInstance(self.next_instance);
+        self.next_instance += 1;
+        self.queued.push((instance, prefab, overrides));
+        instance
+    }
+
+    /// The root entity of a spawned instance.
+    pub fn entity(&self, instance: PrefabInstance) -> Option<Entity> {
+        self.spawned.get(&instance).copied()
+    }
+}
+
+/// Spawn the queued prefabs that have finished loading. Prefabs that fail to load or
+/// spawn are logged and dropped.
+pub fn spawn_prefabs(world: &mut World) {
+    world.resource_scope::<PrefabSpawner, _>(|world, spawner| {
+        world.resource_scope::<AssetServer, _>(|world, assets| {
+            let queued = std::mem::take(&mut spawner.queued);
+            for (instance, handle, overrides) in queued {
+                let Some(prefab) = assets.get(&handle) else {
+                    match assets.load_state(&handle) {
+                        LoadState::Failed(err) => log::error!("failed to spawn prefab: {err}"),
+                        _ => spawner.queued.push((instance, handle, overrides)),
+                    }
+                    continue;
+                };
+                match prefab.spawn(world, assets, &spawner.registry, &overrides) {
+                    Ok(entity) => {
+                        spawner.spawned.insert(instance, entity);
+                    }
+                    Err(PrefabError::Loading(_)) => {
+                        spawner.queued.push((instance, handle, overrides));
+                    }
+                    Err(err) => log::error!("failed to spawn {}: {err}", prefab.path.display()),
+                }
+            }
+        });
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_dir::TestDir;
+    use assets::AssetError;
+    use jaren_ecs::entity_map::MapEntities;
+    use jaren_ecs_derive::Component;
+    use serde::Deserialize;
+
+    #[derive(Component, Clone, Debug, PartialEq, Serialize, Deserialize)]
+    struct Health {
+        max: u32,
+        current: u32,
+    }
+
+    #[derive(Component, Clone, Debug, PartialEq, Serialize, Deserialize)]
+    enum Behaviour {
+        Idle,
+        Patrol { speed: f32 },
+    }
+
+    #[derive(Component, Clone, Debug, PartialEq, Serialize, Deserialize)]
+    struct Light {
+        radius: f32,
+    }
+
+    #[derive(Component, Clone, Debug, PartialEq, Serialize, Deserialize)]
+    struct Follow {
+        target: Entity,
+    }
+
+    impl MapEntities for Follow {
+        fn map_entities(&mut self, map: &EntityMap) {
+            self.target = map.map(self.target);
+        }
+    }
+
+    const FILES: &[(&str, &str)] = &[
+        ("torch.ron", r#"(components: { "Light": (radius: 1.0) })"#),
+        (
+            "goblin.ron",
+            r#"(
+                components: { "Health": (max: 10, current: 10), "Behaviour": Idle },
+                children: [(prefab: "torch.ron", overrides: { "Light.radius": 4.0 })],
+            )"#,
+        ),
+        (
+            "chief.ron",
+            r#"(
+                prefab: "goblin.ron",
+                overrides: { "Health.max": 40, "Behaviour": { "Patrol": { "speed": 2.0 } } },
+                children: [(components: { "Follow": (target: 0) })],
+            )"#,
+        ),
+        ("loop_a.ron", r#"(prefab: "loop_b.ron")"#),
+        ("loop_b.ron", r#"(children: [(prefab: "loop_a.ron")])"#),
+    ];
+
+    fn load(assets: &mut AssetServer, path: &str) -> Handle<Prefab> {
+        let handle = assets.load::<Prefab>(path);
+        for _ in 0..500 {
+            assets.update();
+            if assets.is_loaded_with_dependencies(handle.id()) {
+                return handle;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(2));
+        }
+        panic!("{path} never finished loading");
+    }
+
+    #[test]
+    fn test_nested_prefabs_with_overrides() {
+        let dir = TestDir::new("prefabs_nested");
+        for (name, text) in FILES {
+            std::fs::write(dir.0.join(name), text).unwrap();
+        }
+        let mut registry = ComponentRegistry::new();
+        registry
+            .register_as::<Health>("Health")
+            .register_as::<Behaviour>("Behaviour")
+            .register_as::<Light>("Light")
+            .register_with_entities_as::<Follow>("Follow");
+        let registry = Arc::new(registry);
+        let mut assets = AssetServer::with_root(&dir.0);
+        assets.register_loader(PrefabLoader::new(registry.clone()));
+        let chief = load(&mut assets, "chief.ron");
+        let chief = assets.get(&chief).unwrap();
+
+        let mut world = World::new();
+        let overrides = PrefabOverrides::new().set("Health.current", 5).unwrap();
+        let first = chief
+            .spawn(&mut world, &assets, &registry, &overrides)
+            .unwrap();
+        let second = chief
+            .spawn(&mut world, &assets, &registry, &overrides)
+            .unwrap();
+        assert_ne!(first, second);
+        for root in [first, second] {
+            let health = world.query::<Health>();
+            let health = health.iter().find(|(entity, _)| *entity == root).unwrap();
+            assert_eq!(
+                health.1,
+                &Health {
+                    max: 40,
+                    current: 5
+                }
+            );
+            let behaviour = world.query::<Behaviour>();
+            let behaviour = behaviour.iter().find(|(entity, _)| *entity == root);
+            assert_eq!(behaviour.unwrap().1, &Behaviour::Patrol { speed: 2.0 });
+
+            let children = world.children(root);
+            assert_eq!(children.len(), 2);
+            let lights = world.query::<Light>();
+            assert!(lights.iter().any(|(entity, light)| {
+                children.contains(&entity) && *light == Light { radius: 4.0 }
+            }));
+            let follows = world.query::<Follow>();
+            assert!(
+                follows
+                    .iter()
+                    .any(|(entity, follow)| children.contains(&entity) && follow.target == root)
+            );
+        }
+
+        let spawned = world.query::<Health>().iter().count();
+        let invalid = PrefabOverrides::new().set("Health.armor", 1).unwrap();
+        assert!(matches!(
+            chief.spawn(&mut world, &assets, &registry, &invalid),
+            Err(PrefabError::Override { key, .. }) if key == "Health.armor"
+        ));
+        assert_eq!(world.query::<Health>().iter().count(), spawned);
+
+        let looped = assets.load::<Prefab>("loop_a.ron");
+        for _ in 0..500 {
+            assets.update();
+            if !assets.is_loading() {
+                break;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(2));
+        }
+        assert_eq!(
+            assets.load_state(&looped),
+            LoadState::Failed(AssetError::Cycle(vec![
+                "loop_a.ron".into(),
+                "loop_b.ron".into(),
+                "loop_a.ron".into()
+            ]))
+        );
+
+        let keys = HashMap::from([((1, 2), 3)]);
+        assert!(matches!(
+            PrefabOverrides::new().set("Health", keys),
+            Err(PrefabError::Value { key, .. }) if key == "Health"
+        ));
+    }
+}
@@ -1 +1,21 @@
+#[cfg(feature = "serde")]
+pub use crate::prefab::{Prefab, PrefabInstance, PrefabOverrides, PrefabSpawner};
+#[cfg(feature = "serde")]
+pub use crate::saves::SaveStorage;
+#[cfg(feature = "serde")]
+pub use crate::scene::{Scene, SceneInstance, SceneSpawner};
+pub use crate::window::{WindowConfig, WindowMode};
 pub use crate::*;
+pub use assets::{AssetLoader, AssetServer, Handle, Image, LoadContext, LoadState};
+pub use jaren_ecs::hierarchy::Parent;
+#[cfg(feature = "serde")]
+pub use jaren_ecs::save::SaveSchema;
+pub use rendering::animation::{
+    AnimationClip, AnimationEvents, AnimationFrame, Animator, PlaybackMode, TransitionCondition,
+};
+pub use rendering::atlas::{Rect, TextureAtlas, TextureAtlasBuilder};
+pub use rendering::camera::{Camera2d, RenderTarget, ScalingMode, Viewport};
+pub use rendering::renderer::RendererError;
+pub use rendering::screenshot::ScreenshotTarget;
+pub use rendering::sprite::{BlendMode, Crossfade, Sprite, SpriteSource, Transform2d};
+pub use rendering::surface::{PresentMode, SurfaceFormat, SurfaceOptions};
@@ -0,0 +1,309 @@
+//! Where save games are kept: files in the player's data directory natively,
+//! `localStorage` on the web. See [`jaren_ecs::save`] for what goes into a save.
+
+use jaren_ecs::{
+    entity_map::EntityMap,
+    save::{SaveError, SaveSchema},
+    system::World,
+};
+use std::fmt;
+#[cfg(not(target_arch = "wasm32"))]
+use std::path::{Path, PathBuf};
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum StorageError {
+    #[cfg(not(target_arch = "wasm32"))]
+    Io {
+        path: PathBuf,
+        message: String,
+    },
+    /// `localStorage` is disabled or full.
+    #[cfg(target_arch = "wasm32")]
+    Storage(String),
+    /// Slot names are used as file names, so they cannot contain path separators.
+    InvalidSlot(String),
+    /// Nothing is saved in this slot.
+    NotFound(String),
+    Save(SaveError),
+}
+
+impl fmt::Display for StorageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            #[cfg(not(target_arch = "wasm32"))]
+            StorageError::Io { path, message } => {
+                write!(f, "failed to access {}: {message}", path.display())
+            }
+            #[cfg(target_arch = "wasm32")]
+            StorageError::Storage(message) => write!(f, "local storage: {message}"),
+            StorageError::InvalidSlot(slot) => write!(f, "invalid save slot `{slot}`"),
+            StorageError::NotFound(slot) => write!(f, "no save in slot `{slot}`"),
+            StorageError::Save(err) => write!(f, "{err}"),
+        }
+    }
+}
+
+impl std::error::Error for StorageError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            StorageError::Save(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<SaveError> for StorageError {
+    fn from(err: SaveError) -> Self {
+        StorageError::Save(err)
+    }
+}
+
+// Where each platform keeps application data for the current user.
+#[cfg(not(target_arch = "wasm32"))]
+fn data_dir() -> Option<PathBuf> {
+    let home = || std::env::var_os("HOME").map(PathBuf::from);
+    if cfg!(target_os = "windows") {
+        std::env::var_os("APPDATA").map(PathBuf::from)
+    } else if cfg!(target_os = "macos") {
+        Some(home()?.join("Library/Application Support"))
+    } else {
+        std::env::var_os("XDG_DATA_HOME")
+            .map(PathBuf::from)
+            .filter(|dir| dir.is_absolute())
+            .or_else(|| Some(home()?.join(".local/share")))
+    }
+}
+
+/// Save slots of one game.
+pub struct SaveStorage {
+    #[cfg(not(target_arch = "wasm32"))]
+    dir: PathBuf,
+    #[cfg(target_arch = "wasm32")]
+    prefix: String,
+}
+
+impl SaveStorage {
+    /// Saves of the game called `game`, in `%APPDATA%\<game>\saves` on Windows,
+    /// `~/Library/Application Support/<game>/saves` on macOS, `$XDG_DATA_HOME/<game>/saves`
+    /// (`~/.local/share` by default) on other systems and under `localStorage` keys
+    /// starting with `<game>/saves/` on the web.
+    pub fn new(game: &str) -> Self {
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            let dir = data_dir().unwrap_or_else(|| {
+                log::warn!("no data directory found, saving to the working directory");
+                PathBuf::new()
+            });
+            Self::in_dir(dir.join(game).join("saves"))
+        }
+        #[cfg(target_arch = "wasm32")]
+        {
+            Self {
+                prefix: format!("{game}/saves/"),
+            }
+        }
+    }
+
+    /// Saves in `dir`, one `<slot>.json` file each.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn in_dir(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    fn check_slot(slot: &str) -> Result<(), StorageError> {
+        if slot.is_empty() || slot == "." || slot == ".." || slot.contains(['/', '\\']) {
+            return Err(StorageError::InvalidSlot(slot.to_string()));
+        }
+        Ok(())
+    }
+
+    /// Save `world` to `slot` with the components and version of `schema`.
+    pub fn save_world(
+        &self,
+        slot: &str,
+        world: &World,
+        schema: &SaveSchema,
+    ) -> Result<(), StorageError> {
+        self.write(slot, &world.save_game(schema)?)
+    }
+
+    /// Spawn the entities saved in `slot`, migrated to `schema`'s version.
+    pub fn load_world(
+        &self,
+        slot: &str,
+        world: &mut World,
+        schema: &SaveSchema,
+    ) -> Result<EntityMap, StorageError> {
+        Ok(world.load_game(schema, &self.read(slot)?)?)
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl SaveStorage {
+    fn path(&self, slot: &str) -> Result<PathBuf, StorageError> {
+        Self::check_slot(slot)?;
+        Ok(self.dir.join(format!("{slot}.json")))
+    }
+
+    /// Replace the save in `slot`. The new data is written to a temporary file first and
+    /// moved over the old save in one step, so a crash never leaves a half-written save.
+    pub fn write(&self, slot: &str, data: &[u8]) -> Result<(), StorageError> {
+        use std::io::Write;
+
+        let path = self.path(slot)?;
+        let io = |path: &Path, err: std::io::Error| StorageError::Io {
+            path: path.into(),
+            message: err.to_string(),
+        };
+        std::fs::create_dir_all(&self.dir).map_err(|err| io(&self.dir, err))?;
+        let temporary = path.with_extension("json.tmp");
+        let mut file = std::fs::File::create(&temporary).map_err(|err| io(&temporary, err))?;
+        file.write_all(data)
+            .and_then(|_| file.sync_all())
+            .map_err(|err| io(&temporary, err))?;
+        drop(file);
+        std::fs::rename(&temporary, &path).map_err(|err| io(&path, err))
+    }
+
+    pub fn read(&self, slot: &str) -> Result<Vec<u8>, StorageError> {
+        let path = self.path(slot)?;
+        std::fs::read(&path).map_err(|err| match err.kind() {
+            std::io::ErrorKind::NotFound => StorageError::NotFound(slot.to_string()),
+            _ => StorageError::Io {
+                path,
+                message: err.to_string(),
+            },
+        })
+    }
+
+    /// Delete the save in `slot`, if there is one.
+    pub fn remove(&self, slot: &str) -> Result<(), StorageError> {
+        let path = self.path(slot)?;
+        match std::fs::remove_file(&path) {
+            Err(err) if err.kind() != std::io::ErrorKind::NotFound => Err(StorageError::Io {
+                path,
+                message: err.to_string(),
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Names of the slots holding a save, in alphabetical order.
+    pub fn slots(&self) -> Vec<String> {
+        let Ok(entries) = std::fs::read_dir(&self.dir) else {
+            return Vec::new();
+        };
+        let mut slots: Vec<_> = entries
+            .filter_map(|entry| {
+                let name = entry.ok()?.file_name().into_string().ok()?;
+                name.strip_suffix(".json").map(str::to_string)
+            })
+            .collect();
+        slots.sort();
+        slots
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+impl SaveStorage {
+    fn storage() -> Result<web_sys::Storage, StorageError> {
+        web_sys::window()
+            .and_then(|window| window.local_storage().ok().flatten())
+            .ok_or_else(|| StorageError::Storage("not available".into()))
+    }
+
+    fn key(&self, slot: &str) -> Result<String, StorageError> {
+        Self::check_slot(slot)?;
+        Ok(format!("{}{slot}", self.prefix))
+    }
+
+    /// Replace the save in `slot`. `localStorage` writes each key in one step, so a
+    /// failed write leaves the old save in place.
+    pub fn write(&self, slot: &str, data: &[u8]) -> Result<(), StorageError> {
+        let key = self.key(slot)?;
+        let text = std::str::from_utf8(data)
+            .map_err(|_| StorageError::Storage("saves must be text".into()))?;
+        Self::storage()?
+            .set_item(&key, text)
+            .map_err(|err| StorageError::Storage(format!("{err:?}")))
+    }
+
+    pub fn read(&self, slot: &str) -> Result<Vec<u8>, StorageError> {
+        let key = self.key(slot)?;
+        Self::storage()?
+            .get_item(&key)
+            .map_err(|err| StorageError::Storage(format!("{err:?}")))?
+            .map(String::into_bytes)
+            .ok_or_else(|| StorageError::NotFound(slot.to_string()))
+    }
+
+    /// Delete the save in `slot`, if there is one.
+    pub fn remove(&self, slot: &str) -> Result<(), StorageError> {
+        let key = self.key(slot)?;
+        Self::storage()?
+            .remove_item(&key)
+            .map_err(|err| StorageError::Storage(format!("{err:?}")))
+    }
+
+    /// Names of the slots holding a save, in alphabetical order.
+    pub fn slots(&self) -> Vec<String> {
+        let Ok(storage) = Self::storage() else {
+            return Vec::new();
+        };
+        let count = storage.length().unwrap_or(0);
+        let mut slots: Vec<_> = (0..count)
+            .filter_map(|index| storage.key(index).ok().flatten())
+            .filter_map(|key| key.strip_prefix(&self.prefix).map(str::to_string))
+            .collect();
+        slots.sort();
+        slots
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_dir::TestDir;
+    use jaren_ecs::spawn;
+    use jaren_ecs_derive::Component;
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Component, Clone, Debug, PartialEq, Serialize, Deserialize)]
+    struct Checkpoint(u32);
+
+    #[test]
+    fn test_save_slots_round_trip() {
+        let dir = TestDir::new("saves");
+        let storage = SaveStorage::in_dir(&dir.0);
+        let mut schema = SaveSchema::new(1);
+        schema.persist_as::<Checkpoint>("Checkpoint");
+
+        let mut world = World::new();
+        spawn!(world, Checkpoint(3));
+        storage.save_world("slot1", &world, &schema).unwrap();
+        storage.save_world("slot1", &world, &schema).unwrap();
+        assert_eq!(storage.slots(), vec!["slot1"]);
+        assert!(!dir.0.join("slot1.json.tmp").exists());
+
+        let mut loaded = World::new();
+        storage.load_world("slot1", &mut loaded, &schema).unwrap();
+        let checkpoints = loaded.query::<Checkpoint>();
+        assert_eq!(checkpoints.iter().next().unwrap().1, &Checkpoint(3));
+
+        assert_eq!(
+            storage.read("slot2"),
+            Err(StorageError::NotFound("slot2".into()))
+        );
+        assert_eq!(
+            storage.write("../escape", b"{}"),
+            Err(StorageError::InvalidSlot("../escape".into()))
+        );
+        storage.remove("slot1").unwrap();
+        assert!(storage.slots().is_empty());
+    }
+}
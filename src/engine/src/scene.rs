@@ -0,0 +1,623 @@
+//! Levels and other groups of entities described in RON files instead of `spawn!` calls.
+//!
+//! A scene lists entities by an id local to the file, each with its components keyed by
+//! the names they are registered under in a [`ComponentRegistry`], and the entities
+//! nested under it:
+//!
+//! ```ron
+//! (
+//!     entities: [
+//!         (
+//!             id: 0,
+//!             components: {
+//!                 "Position": (x: 0.0, y: 0.0),
+//!             },
+//!             children: [
+//!                 (id: 1, components: { "Follow": (target: 0) }),
+//!             ],
+//!         ),
+//!     ],
+//! )
+//! ```
+//!
+//! Scenes are spawned with new entity ids every time. Children get a [`Parent`] and
+//! components registered with
+//! [`register_with_entities_as`](ComponentRegistry::register_with_entities_as) have
+//! ids of entities in the scene replaced by the spawned ones.
+
+use assets::{Asset, AssetLoader, AssetServer, Handle, LoadContext, LoadState};
+use jaren_ecs::{
+    entity_map::EntityMap,
+    hierarchy::Parent,
+    snapshot::{ComponentRegistry, ComponentsSeed, FieldName},
+    system::{Component, Entity, World},
+};
+use serde::{
+    Deserializer, Serialize, Serializer,
+    de::{self, DeserializeSeed, MapAccess, SeqAccess, Visitor},
+    ser::SerializeStruct,
+};
+use std::{
+    any::TypeId,
+    collections::{HashMap, HashSet},
+    fmt,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum SceneError {
+    Io {
+        path: PathBuf,
+        message: String,
+    },
+    /// The text is not a valid scene, or holds a component that is not registered.
+    Parse(String),
+    /// Two entities in the scene have the same id.
+    DuplicateId(Entity),
+    /// A component failed to serialize.
+    Serialize(String),
+}
+
+impl fmt::Display for SceneError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SceneError::Io { path, message } => {
+                write!(f, "failed to access {}: {message}", path.display())
+            }
+            SceneError::Parse(message) => write!(f, "invalid scene: {message}"),
+            SceneError::DuplicateId(id) => write!(f, "entity id {id} is used more than once"),
+            SceneError::Serialize(message) => write!(f, "failed to write scene: {message}"),
+        }
+    }
+}
+
+impl std::error::Error for SceneError {}
+
+/// An entity in a [`Scene`].
+pub struct SceneEntity {
+    /// Identifies the entity within the scene only.
+    pub id: Entity,
+    pub parent: Option<Entity>,
+    pub components: Vec<Box<dyn Component>>,
+}
+
+/// Entities and their components, ready to be spawned any number of times.
+#[derive(Default)]
+pub struct Scene {
+    // Parents always come before their children.
+    entities: Vec<SceneEntity>,
+}
+
+impl Asset for Scene {}
+
+impl Scene {
+    pub fn entities(&self) -> &[SceneEntity] {
+        &self.entities
+    }
+
+    /// Parse a scene, looking its components up in `registry`.
+    pub fn from_ron(registry: &ComponentRegistry, text: &str) -> Result<Self, SceneError> {
+        let parse = |message: String| SceneError::Parse(message);
+        let mut deserializer =
+            ron::de::Deserializer::from_str(text).map_err(|err| parse(err.to_string()))?;
+        let nodes = SceneSeed(registry)
+            .deserialize(&mut deserializer)
+            .map_err(|err| parse(deserializer.span_error(err).to_string()))?;
+        deserializer.end().map_err(|err| parse(err.to_string()))?;
+
+        let mut scene = Scene::default();
+        let mut ids = HashSet::new();
+        let mut stack: Vec<_> = nodes.into_iter().rev().map(|node| (None, node)).collect();
+        while let Some((parent, node)) = stack.pop() {
+            if !ids.insert(node.id) {
+                return Err(SceneError::DuplicateId(node.id));
+            }
+            stack.extend(
+                node.children
+                    .into_iter()
+                    .rev()
+                    .map(|child| (Some(node.id), child)),
+            );
+            scene.entities.push(SceneEntity {
+                id: node.id,
+                parent,
+                components: node.components,
+            });
+        }
+        Ok(scene)
+    }
+
+    pub fn to_ron(&self, registry: &ComponentRegistry) -> Result<String, SceneError> {
+        let mut children: HashMap<Option<Entity>, Vec<&SceneEntity>> = HashMap::new();
+        for entity in &self.entities {
+            children.entry(entity.parent).or_default().push(entity);
+        }
+        let scene = SceneRef {
+            registry,
+            children: &children,
+        };
+        ron::ser::to_string_pretty(&scene, ron::ser::PrettyConfig::default())
+            .map_err(|err| SceneError::Serialize(err.to_string()))
+    }
+
+    /// Read a scene from a RON file outside the asset server, e.g. in a level editor.
+    pub fn load(registry: &ComponentRegistry, path: impl AsRef<Path>) -> Result<Self, SceneError> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path).map_err(|err| SceneError::Io {
+            path: path.into(),
+            message: err.to_string(),
+        })?;
+        Self::from_ron(registry, &text)
+    }
+
+    pub fn save(
+        &self,
+        registry: &ComponentRegistry,
+        path: impl AsRef<Path>,
+    ) -> Result<(), SceneError> {
+        let path = path.as_ref();
+        std::fs::write(path, self.to_ron(registry)?).map_err(|err| SceneError::Io {
+            path: path.into(),
+            message: err.to_string(),
+        })
+    }
+
+    /// Copy `entities` out of `world` with their registered components, e.g. to save part
+    /// of a level. An entity whose parent is also copied stays its child, the others
+    /// become roots of the scene. Entity ids keep their values in the scene, so components
+    /// referring to entities that were left out still point at them when spawned.
+    pub fn from_world(world: &World, registry: &ComponentRegistry, entities: &[Entity]) -> Self {
+        let included: HashSet<_> = entities.iter().copied().collect();
+        let mut copied: HashMap<Entity, SceneEntity> = HashMap::new();
+        for &entity in entities {
+            let components = world
+                .components(entity)
+                .into_iter()
+                .filter(|component| component.as_any().type_id() != TypeId::of::<Parent>())
+                .filter_map(|component| registry.clone_component(component))
+                .collect();
+            let parent = world
+                .parent(entity)
+                .filter(|parent| included.contains(parent));
+            copied.insert(
+                entity,
+                SceneEntity {
+                    id: entity,
+                    parent,
+                    components,
+                },
+            );
+        }
+
+        // Walk down from the roots so parents come first.
+        let mut children: HashMap<Option<Entity>, Vec<Entity>> = HashMap::new();
+        for entity in copied.values() {
+            children.entry(entity.parent).or_default().push(entity.id);
+        }
+        let mut scene = Scene::default();
+        let mut stack = vec![None];
+        while let Some(parent) = stack.pop() {
+            let mut ids = children.remove(&parent).unwrap_or_default();
+            ids.sort_unstable();
+            for id in ids {
+                stack.push(Some(id));
+                scene.entities.extend(copied.remove(&id));
+            }
+        }
+        scene
+    }
+
+    /// Spawn a copy of every entity in the scene and return the scene ids mapped to the
+    /// spawned entities. Components missing from `registry` are left out.
+    pub fn spawn(&self, world: &mut World, registry: &ComponentRegistry) -> EntityMap {
+        self.instantiate(world, registry, None)
+    }
+
+    /// Like [`spawn`](Self::spawn), with the scene's root entities made children of
+    /// `parent`.
+    pub fn spawn_as_child(
+        &self,
+        world: &mut World,
+        registry: &ComponentRegistry,
+        parent: Entity,
+    ) -> EntityMap {
+        self.instantiate(world, registry, Some(parent))
+    }
+
+    fn instantiate(
+        &self,
+        world: &mut World,
+        registry: &ComponentRegistry,
+        root_parent: Option<Entity>,
+    ) -> EntityMap {
+        let mut map = EntityMap::new();
+        for entity in &self.entities {
+            map.insert(entity.id, world.alloc_entity());
+        }
+        for entity in &self.entities {
+            let mut components: Vec<_> = entity
+                .components
+                .iter()
+                .filter_map(|component| registry.clone_component(component.as_ref()))
+                .collect();
+            for component in &mut components {
+                registry.map_entities(component.as_mut(), &map);
+            }
+            if let Some(parent) = entity.parent.map(|parent| map.map(parent)).or(root_parent) {
+                components.push(Box::new(Parent(parent)));
+            }
+            world.get_archetype(map.map(entity.id), components);
+        }
+        map
+    }
+}
+
+// An entity as written in the file, with its children nested.
+struct SceneNode {
+    id: Entity,
+    components: Vec<Box<dyn Component>>,
+    children: Vec<SceneNode>,
+}
+
+struct SceneRef<'a> {
+    registry: &'a ComponentRegistry,
+    children: &'a HashMap<Option<Entity>, Vec<&'a SceneEntity>>,
+}
+
+struct NodesRef<'a> {
+    scene: &'a SceneRef<'a>,
+    parent: Option<Entity>,
+}
+
+struct NodeRef<'a> {
+    scene: &'a SceneRef<'a>,
+    entity: &'a SceneEntity,
+}
+
+impl Serialize for SceneRef<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("Scene", 1)?;
+        let roots = NodesRef {
+            scene: self,
+            parent: None,
+        };
+        state.serialize_field("entities", &roots)?;
+        state.end()
+    }
+}
+
+impl Serialize for NodesRef<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let entities = self.scene.children.get(&self.parent);
+        serializer.collect_seq(entities.into_iter().flatten().map(|entity| NodeRef {
+            scene: self.scene,
+            entity,
+        }))
+    }
+}
+
+impl Serialize for NodeRef<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let has_children = self.scene.children.contains_key(&Some(self.entity.id));
+        let mut state = serializer.serialize_struct("Entity", 2 + has_children as usize)?;
+        state.serialize_field("id", &self.entity.id)?;
+        let components = self
+            .scene
+            .registry
+            .serialize_components(self.entity.components.iter().map(|c| c.as_ref()));
+        state.serialize_field("components", &components)?;
+        if has_children {
+            let children = NodesRef {
+                scene: self.scene,
+                parent: Some(self.entity.id),
+            };
+            state.serialize_field("children", &children)?;
+        }
+        state.end()
+    }
+}
+
+#[derive(Clone, Copy)]
+struct SceneSeed<'a>(&'a ComponentRegistry);
+
+impl<'de> DeserializeSeed<'de> for SceneSeed<'_> {
+    type Value = Vec<SceneNode>;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error> {
+        deserializer.deserialize_struct("Scene", &["entities"], self)
+    }
+}
+
+impl<'de> Visitor<'de> for SceneSeed<'_> {
+    type Value = Vec<SceneNode>;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a scene")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+        let mut entities = None;
+        while let Some(key) = map.next_key::<FieldName>()? {
+            match key.as_str() {
+                "entities" => entities = Some(map.next_value_seed(NodesSeed(self.0))?),
+                _ => {
+                    map.next_value::<de::IgnoredAny>()?;
+                }
+            }
+        }
+        entities.ok_or_else(|| de::Error::missing_field("entities"))
+    }
+}
+
+#[derive(Clone, Copy)]
+struct NodesSeed<'a>(&'a ComponentRegistry);
+
+impl<'de> DeserializeSeed<'de> for NodesSeed<'_> {
+    type Value = Vec<SceneNode>;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error> {
+        deserializer.deserialize_seq(self)
+    }
+}
+
+impl<'de> Visitor<'de> for NodesSeed<'_> {
+    type Value = Vec<SceneNode>;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a list of entities")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+        let mut nodes = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+        while let Some(node) = seq.next_element_seed(NodeSeed(self.0))? {
+            nodes.push(node);
+        }
+        Ok(nodes)
+    }
+}
+
+#[derive(Clone, Copy)]
+struct NodeSeed<'a>(&'a ComponentRegistry);
+
+impl<'de> DeserializeSeed<'de> for NodeSeed<'_> {
+    type Value = SceneNode;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error> {
+        deserializer.deserialize_struct("Entity", &["id", "components", "children"], self)
+    }
+}
+
+impl<'de> Visitor<'de> for NodeSeed<'_> {
+    type Value = SceneNode;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("an entity with its components and children")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+        let (mut id, mut components, mut children) = (None, None, None);
+        while let Some(key) = map.next_key::<FieldName>()? {
+            match key.as_str() {
+                "id" => id = Some(map.next_value()?),
+                "components" => components = Some(map.next_value_seed(ComponentsSeed(self.0))?),
+                "children" => children = Some(map.next_value_seed(NodesSeed(self.0))?),
+                _ => {
+                    map.next_value::<de::IgnoredAny>()?;
+                }
+            }
+        }
+        Ok(SceneNode {
+            id: id.ok_or_else(|| de::Error::missing_field("id"))?,
+            components: components.unwrap_or_default(),
+            children: children.unwrap_or_default(),
+        })
+    }
+}
+
+/// Loads [`Scene`]s from `.ron` files with the components in its registry.
+pub struct SceneLoader {
+    registry: Arc<ComponentRegistry>,
+}
+
+impl SceneLoader {
+    pub fn new(registry: Arc<ComponentRegistry>) -> Self {
+        Self { registry }
+    }
+}
+
+impl AssetLoader for SceneLoader {
+    type Asset = Scene;
+    type Error = SceneError;
+
+    fn extensions(&self) -> &[&str] {
+        &["ron"]
+    }
+
+    async fn load(&self, bytes: &[u8], _ctx: &mut LoadContext) -> Result<Scene, SceneError> {
+        let text = std::str::from_utf8(bytes).map_err(|err| SceneError::Parse(err.to_string()))?;
+        Scene::from_ron(&self.registry, text)
+    }
+}
+
+/// Identifies one spawn of a scene by the [`SceneSpawner`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct SceneInstance(u64);
+
+/// Spawns scenes into the world once they have loaded. Stored as a world resource.
+pub struct SceneSpawner {
+    registry: Arc<ComponentRegistry>,
+    next_instance: u64,
+    queued: Vec<(SceneInstance, Handle<Scene>, Option<Entity>)>,
+    spawned: HashMap<SceneInstance, EntityMap>,
+}
+
+impl SceneSpawner {
+    pub fn new(registry: Arc<ComponentRegistry>) -> Self {
+        Self {
+            registry,
+            next_instance: 0,
+            queued: Vec::new(),
+            spawned: HashMap::new(),
+        }
+    }
+
+    /// The components scenes are loaded and spawned with.
+    pub fn registry(&self) -> &ComponentRegistry {
+        &self.registry
+    }
+
+    /// Spawn `scene` as soon as it has loaded. Spawning the same scene again creates
+    /// another copy.
+    pub fn spawn(&mut self, scene: Handle<Scene>) -> SceneInstance {
+        self.queue(scene, None)
+    }
+
+    /// Like [`spawn`](Self::spawn), with the scene's root entities made children of
+    /// `parent`.
+    pub fn spawn_as_child(&mut self, scene: Handle<Scene>, parent: Entity) -> SceneInstance {
+        self.queue(scene, Some(parent))
+    }
+
+    fn queue(&mut self, scene: Handle<Scene>, parent: Option<Entity>) -> SceneInstance {
+        let instance = SceneInstance(self.next_instance);
+        self.next_instance += 1;
+        self.queued.push((instance, scene, parent));
+        instance
+    }
+
+    /// Whether `instance` has been spawned.
+    pub fn is_spawned(&self, instance: SceneInstance) -> bool {
+        self.spawned.contains_key(&instance)
+    }
+
+    /// The scene ids of a spawned instance mapped to its entities.
+    pub fn entities(&self, instance: SceneInstance) -> Option<&EntityMap> {
+        self.spawned.get(&instance)
+    }
+}
+
+/// Spawn the queued scenes that have finished loading. Scenes that failed to load are
+/// logged and dropped.
+pub fn spawn_scenes(world: &mut World) {
+    world.resource_scope::<SceneSpawner, _>(|world, spawner| {
+        world.resource_scope::<AssetServer, _>(|world, assets| {
+            let queued = std::mem::take(&mut spawner.queued);
+            for (instance, handle, parent) in queued {
+                let Some(scene) = assets.get(&handle) else {
+                    match assets.load_state(&handle) {
+                        LoadState::Failed(err) => log::error!("failed to spawn scene: {err}"),
+                        _ => spawner.queued.push((instance, handle, parent)),
+                    }
+                    continue;
+                };
+                let map = scene.instantiate(world, &spawner.registry, parent);
+                spawner.spawned.insert(instance, map);
+            }
+        });
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use jaren_ecs::entity_map::MapEntities;
+    use jaren_ecs_derive::Component;
+    use serde::Deserialize;
+
+    #[derive(Component, Clone, Debug, PartialEq, Serialize, Deserialize)]
+    struct Position {
+        x: f32,
+        y: f32,
+    }
+
+    #[derive(Component, Clone, Debug, PartialEq, Serialize, Deserialize)]
+    enum Behaviour {
+        Idle,
+        Patrol { speed: f32 },
+    }
+
+    #[derive(Component, Clone, Debug, PartialEq, Serialize, Deserialize)]
+    struct Follow {
+        target: Entity,
+    }
+
+    impl MapEntities for Follow {
+        fn map_entities(&mut self, map: &EntityMap) {
+            self.target = map.map(self.target);
+        }
+    }
+
+    const LEVEL: &str = r#"(
+        entities: [
+            (
+                id: 10,
+                components: {
+                    "Position": (x: 1.0, y: 2.0),
+                    "Behaviour": Patrol(speed: 3.0),
+                },
+                children: [
+                    (id: 11, components: { "Follow": (target: 10), "Behaviour": Idle }),
+                ],
+            ),
+        ],
+    )"#;
+
+    fn registry() -> ComponentRegistry {
+        let mut registry = ComponentRegistry::new();
+        registry
+            .register_as::<Position>("Position")
+            .register_as::<Behaviour>("Behaviour")
+            .register_with_entities_as::<Follow>("Follow");
+        registry
+    }
+
+    #[test]
+    fn test_scene_spawns_remapped_and_exports() {
+        let registry = registry();
+        let scene = Scene::from_ron(&registry, LEVEL).unwrap();
+
+        let mut world = World::new();
+        let first = scene.spawn(&mut world, &registry);
+        let second = scene.spawn(&mut world, &registry);
+        for map in [&first, &second] {
+            let (root, child) = (map.map(10), map.map(11));
+            assert_eq!(world.children(root), vec![child]);
+            assert_eq!(world.parent(child), Some(root));
+            let follows = world.query::<Follow>();
+            assert!(
+                follows
+                    .iter()
+                    .any(|(entity, follow)| entity == child && follow.target == root)
+            );
+        }
+        assert_ne!(first.map(10), second.map(10));
+        let behaviours = world.query::<Behaviour>();
+        let patrols = behaviours
+            .iter()
+            .filter(|(_, b)| **b == Behaviour::Patrol { speed: 3.0 })
+            .count();
+        assert_eq!(patrols, 2);
+
+        // Export the second copy and load it back.
+        let entities: Vec<_> = second.iter().map(|(_, entity)| entity).collect();
+        let exported = Scene::from_world(&world, &registry, &entities);
+        let text = exported.to_ron(&registry).unwrap();
+        let reloaded = Scene::from_ron(&registry, &text).unwrap();
+        assert_eq!(reloaded.entities().len(), 2);
+        assert_eq!(reloaded.entities()[0].id, second.map(10));
+        assert_eq!(reloaded.entities()[1].parent, Some(second.map(10)));
+
+        let mut other = World::new();
+        let map = reloaded.spawn_as_child(&mut other, &registry, 99);
+        let root = map.map(second.map(10));
+        assert_eq!(other.parent(root), Some(99));
+        assert_eq!(other.children(root), vec![map.map(second.map(11))]);
+
+        let duplicate = LEVEL.replace("id: 11", "id: 10");
+        assert!(matches!(
+            Scene::from_ron(&registry, &duplicate),
+            Err(SceneError::DuplicateId(10))
+        ));
+    }
+}
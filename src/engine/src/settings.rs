@@ -0,0 +1,452 @@
+//! Reading a [`GameConfig`] from a settings file, overriding it from the environment and
+//! the command line, and writing it back.
+
+use crate::GameConfig;
+use rendering::surface::SurfaceOptions;
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+};
+
+/// Settings file formats, picked by file extension.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SettingsFormat {
+    Toml,
+    Ron,
+}
+
+impl SettingsFormat {
+    /// `.toml` or `.ron`.
+    pub fn from_path(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()? {
+            "toml" => Some(Self::Toml),
+            "ron" => Some(Self::Ron),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum SettingsError {
+    Io {
+        path: PathBuf,
+        message: String,
+    },
+    /// The path has no extension [`SettingsFormat`] knows.
+    UnknownFormat(PathBuf),
+    /// The settings text is not a valid [`GameConfig`].
+    Parse {
+        message: String,
+    },
+    /// An override names a setting [`GameConfig`] does not have.
+    UnknownKey(String),
+    /// An override's value does not fit the setting.
+    InvalidValue {
+        key: String,
+        message: String,
+    },
+}
+
+impl fmt::Display for SettingsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SettingsError::Io { path, message } => {
+                write!(f, "failed to access {}: {message}", path.display())
+            }
+            SettingsError::UnknownFormat(path) => write!(
+                f,
+                "unknown settings format for {}, expected .toml or .ron",
+                path.display()
+            ),
+            SettingsError::Parse { message } => write!(f, "invalid settings: {message}"),
+            SettingsError::UnknownKey(key) => write!(f, "unknown setting `{key}`"),
+            SettingsError::InvalidValue { key, message } => {
+                write!(f, "invalid value for `{key}`: {message}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for SettingsError {}
+
+/// Prefix of environment variables read by [`GameConfig::apply_env`] in
+/// [`GameConfig::load_layered`].
+pub const ENV_PREFIX: &str = "GAME_";
+
+// A setting's value from the command line or environment: anything TOML understands
+// (`true`, `4`, `[1280, 720]`), otherwise taken as a string (`Mailbox`, `debug`).
+fn parse_value(value: &str) -> toml::Value {
+    toml::from_str::<toml::Table>(&format!("value = {value}"))
+        .ok()
+        .and_then(|mut table| table.remove("value"))
+        .unwrap_or_else(|| toml::Value::String(value.to_string()))
+}
+
+fn lookup<'a>(table: &'a toml::Table, key: &str) -> Option<&'a toml::Value> {
+    let mut parts = key.split('.');
+    let mut value = table.get(parts.next()?)?;
+    for part in parts {
+        value = value.as_table()?.get(part)?;
+    }
+    Some(value)
+}
+
+// Replace the file at `path` in one step, so a crash while saving leaves the previous
+// settings intact.
+fn write_atomically(path: &Path, text: &str) -> Result<(), SettingsError> {
+    let io = |err: std::io::Error| SettingsError::Io {
+        path: path.into(),
+        message: err.to_string(),
+    };
+    if let Some(parent) = path
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+    {
+        std::fs::create_dir_all(parent).map_err(io)?;
+    }
+    // Keeps the extension, so `settings.toml` and `settings.ron` do not share one.
+    let extension = path.extension().unwrap_or_default().to_string_lossy();
+    let temporary = path.with_extension(format!("{extension}.tmp"));
+    std::fs::write(&temporary, text).map_err(io)?;
+    std::fs::rename(&temporary, path).map_err(io)
+}
+
+/// Write `options` as the `surface` settings of the file at `path`, creating it if needed.
+///
+/// TOML files are edited in place: every other setting, unknown keys and comments are kept
+/// as they are. RON files are rewritten from the settings they parse to, which keeps the
+/// other settings' values but drops comments and keys [`GameConfig`] does not know.
+pub fn save_surface_options(path: &Path, options: &SurfaceOptions) -> Result<(), SettingsError> {
+    let format =
+        SettingsFormat::from_path(path).ok_or_else(|| SettingsError::UnknownFormat(path.into()))?;
+    let text = if path.exists() {
+        std::fs::read_to_string(path).map_err(|err| SettingsError::Io {
+            path: path.into(),
+            message: err.to_string(),
+        })?
+    } else {
+        String::new()
+    };
+    let parse = |message: String| SettingsError::Parse { message };
+    let text = match format {
+        SettingsFormat::Toml => {
+            let mut document: toml_edit::DocumentMut = text
+                .parse()
+                .map_err(|err: toml_edit::TomlError| parse(err.to_string()))?;
+            let surface: toml_edit::DocumentMut = toml::to_string(options)
+                .map_err(|err| parse(err.to_string()))?
+                .parse()
+                .map_err(|err: toml_edit::TomlError| parse(err.to_string()))?;
+            let table = document
+                .entry("surface")
+                .or_insert(toml_edit::table())
+                .as_table_like_mut()
+                .ok_or_else(|| parse("`surface` is not a table".into()))?;
+            for (key, item) in surface.iter() {
+                let Some(mut value) = item.as_value().cloned() else {
+                    continue;
+                };
+                // Keep the comments around the old value
+                if let Some(old) = table.get(key).and_then(toml_edit::Item::as_value) {
+                    *value.decor_mut() = old.decor().clone();
+                }
+                table.insert(key, toml_edit::Item::Value(value));
+            }
+            document.to_string()
+        }
+        SettingsFormat::Ron => {
+            let mut config = if text.is_empty() {
+                GameConfig::default()
+            } else {
+                GameConfig::parse(&text, format)?
+            };
+            config.surface = *options;
+            config.to_string(format)?
+        }
+    };
+    write_atomically(path, &text)
+}
+
+/// Saves surface options to a settings file on a background thread, so the frame that
+/// changed them does not wait for the disk. Changes made while a save is running are
+/// saved together, as the latest options.
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) struct SettingsWriter {
+    sender: Option<std::sync::mpsc::Sender<SurfaceOptions>>,
+    thread: Option<std::thread::JoinHandle<()>>,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl SettingsWriter {
+    pub(crate) fn new(path: PathBuf) -> Self {
+        let (sender, receiver) = std::sync::mpsc::channel::<SurfaceOptions>();
+        let thread = std::thread::Builder::new()
+            .name("settings-writer".into())
+            .spawn(move || {
+                while let Ok(options) = receiver.recv() {
+                    let options = receiver.try_iter().last().unwrap_or(options);
+                    if let Err(err) = save_surface_options(&path, &options) {
+                        log::error!("failed to save settings: {err}");
+                    }
+                }
+            })
+            .expect("failed to spawn settings writer thread");
+        Self {
+            sender: Some(sender),
+            thread: Some(thread),
+        }
+    }
+
+    pub(crate) fn save(&self, options: SurfaceOptions) {
+        if let Some(sender) = &self.sender {
+            let _ = sender.send(options);
+        }
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Drop for SettingsWriter {
+    fn drop(&mut self) {
+        // Closing the channel lets the thread finish the last save and exit.
+        self.sender.take();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl GameConfig {
+    /// Parse settings, leaving out whatever the text does not mention at its default.
+    pub fn parse(text: &str, format: SettingsFormat) -> Result<Self, SettingsError> {
+        let parse = |message: String| SettingsError::Parse { message };
+        match format {
+            SettingsFormat::Toml => toml::from_str(text).map_err(|err| parse(err.to_string())),
+            SettingsFormat::Ron => ron::from_str(text).map_err(|err| parse(err.to_string())),
+        }
+    }
+
+    pub fn to_string(&self, format: SettingsFormat) -> Result<String, SettingsError> {
+        let parse = |message: String| SettingsError::Parse { message };
+        match format {
+            SettingsFormat::Toml => {
+                toml::to_string_pretty(self).map_err(|err| parse(err.to_string()))
+            }
+            SettingsFormat::Ron => {
+                ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())
+                    .map_err(|err| parse(err.to_string()))
+            }
+        }
+    }
+
+    /// Read settings from a `.toml` or `.ron` file.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, SettingsError> {
+        let path = path.as_ref();
+        let format = SettingsFormat::from_path(path)
+            .ok_or_else(|| SettingsError::UnknownFormat(path.into()))?;
+        let text = std::fs::read_to_string(path).map_err(|err| SettingsError::Io {
+            path: path.into(),
+            message: err.to_string(),
+        })?;
+        Self::parse(&text, format)
+    }
+
+    /// Write the settings to a `.toml` or `.ron` file. The file is replaced in one step,
+    /// so a crash while saving leaves the previous settings intact.
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), SettingsError> {
+        let path = path.as_ref();
+        let format = SettingsFormat::from_path(path)
+            .ok_or_else(|| SettingsError::UnknownFormat(path.into()))?;
+        write_atomically(path, &self.to_string(format)?)
+    }
+
+    /// Change one setting by its dotted path, e.g. `set("window.size", "[1280, 720]")`
+    /// or `set("surface.present_mode", "Immediate")`.
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), SettingsError> {
+        let invalid = |message: String| SettingsError::InvalidValue {
+            key: key.to_string(),
+            message,
+        };
+        let mut root = toml::Table::try_from(&*self).map_err(|err| invalid(err.to_string()))?;
+        let (parents, last) = match key.rsplit_once('.') {
+            Some((parents, last)) => (Some(parents), last),
+            None => (None, key),
+        };
+        let mut table = &mut root;
+        for part in parents.into_iter().flat_map(|parents| parents.split('.')) {
+            table = table
+                .entry(part)
+                .or_insert_with(|| toml::Table::new().into())
+                .as_table_mut()
+                .ok_or_else(|| SettingsError::UnknownKey(key.to_string()))?;
+        }
+        table.insert(last.to_string(), parse_value(value));
+
+        let updated: GameConfig = toml::Value::Table(root)
+            .try_into()
+            .map_err(|err: toml::de::Error| invalid(err.message().to_string()))?;
+        // Unknown fields are ignored when deserializing, so a typo would vanish here.
+        let check = toml::Table::try_from(&updated).map_err(|err| invalid(err.to_string()))?;
+        if lookup(&check, key).is_none() {
+            return Err(SettingsError::UnknownKey(key.to_string()));
+        }
+        *self = updated;
+        Ok(())
+    }
+
+    /// Apply every environment variable named `prefix` followed by a setting, with `__`
+    /// between nested names: `GAME_SURFACE__MSAA_SAMPLES=4`. Names are case-insensitive.
+    /// Variables that do not name a setting, like `GAME_HOME`, are skipped with a warning.
+    pub fn apply_env(&mut self, prefix: &str) -> Result<(), SettingsError> {
+        self.apply_vars(prefix, std::env::vars())
+    }
+
+    fn apply_vars(
+        &mut self,
+        prefix: &str,
+        vars: impl IntoIterator<Item = (String, String)>,
+    ) -> Result<(), SettingsError> {
+        for (name, value) in vars {
+            let Some(key) = name.strip_prefix(prefix) else {
+                continue;
+            };
+            match self.set(&key.to_lowercase().replace("__", "."), &value) {
+                Err(SettingsError::UnknownKey(key)) => {
+                    log::warn!("ignoring {name}, there is no setting `{key}`")
+                }
+                result => result?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Apply `--set <key>=<value>` (or `--set=<key>=<value>`) command line arguments,
+    /// ignoring all others.
+    pub fn apply_args(
+        &mut self,
+        args: impl IntoIterator<Item = String>,
+    ) -> Result<(), SettingsError> {
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let assignment = match arg.strip_prefix("--set") {
+                Some("") => args.next(),
+                Some(rest) => rest.strip_prefix('=').map(str::to_string),
+                None => None,
+            };
+            if let Some(assignment) = assignment {
+                let (key, value) =
+                    assignment
+                        .split_once('=')
+                        .ok_or_else(|| SettingsError::InvalidValue {
+                            key: assignment.clone(),
+                            message: "expected `--set <key>=<value>`".into(),
+                        })?;
+                self.set(key.trim(), value.trim())?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Settings from `path` (defaults if it does not exist yet), then environment
+    /// variables starting with [`ENV_PREFIX`], then `--set` arguments.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn load_layered(path: impl AsRef<Path>) -> Result<Self, SettingsError> {
+        let path = path.as_ref();
+        let mut config = if path.exists() {
+            Self::load(path)?
+        } else {
+            Self::default()
+        };
+        config.apply_env(ENV_PREFIX)?;
+        config.apply_args(std::env::args().skip(1))?;
+        Ok(config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_dir::TestDir;
+    use rendering::surface::PresentMode;
+
+    #[test]
+    fn test_settings_round_trip_and_overrides() {
+        let mut config = GameConfig::parse(
+            "title = \"QA build\"\n[window]\nsize = [800, 600]\n",
+            SettingsFormat::Toml,
+        )
+        .unwrap();
+        assert_eq!(config.title, "QA build");
+        assert_eq!(config.window.size, Some([800, 600]));
+        assert!(config.window.resizable);
+
+        config
+            .apply_args(
+                [
+                    "game",
+                    "--set",
+                    "surface.present_mode=Immediate",
+                    "--verbose",
+                ]
+                .map(String::from),
+            )
+            .unwrap();
+        config
+            .apply_vars(
+                "GAME_",
+                [
+                    ("GAME_WINDOW__SIZE".into(), "[1920, 1080]".into()),
+                    ("GAME_HOME".into(), "/home/game".into()),
+                ],
+            )
+            .unwrap();
+        config.set("log_level", "debug").unwrap();
+        assert_eq!(config.surface.present_mode, PresentMode::Immediate);
+        assert_eq!(config.window.size, Some([1920, 1080]));
+        assert_eq!(config.log_level, Some(log::LevelFilter::Debug));
+
+        assert_eq!(
+            config.set("window.sise", "[1, 1]"),
+            Err(SettingsError::UnknownKey("window.sise".into()))
+        );
+        assert_eq!(
+            config.apply_args(["--set=window.sise=[1, 1]".to_string()]),
+            Err(SettingsError::UnknownKey("window.sise".into()))
+        );
+        assert!(matches!(
+            config.set("surface.msaa_samples", "many"),
+            Err(SettingsError::InvalidValue { .. })
+        ));
+
+        for format in [SettingsFormat::Toml, SettingsFormat::Ron] {
+            let text = config.to_string(format).unwrap();
+            assert_eq!(GameConfig::parse(&text, format).unwrap(), config);
+        }
+    }
+
+    #[test]
+    fn test_save_surface_options_keeps_the_rest_of_the_file() {
+        let dir = TestDir::new("settings");
+        let path = dir.0.join("settings.toml");
+        let text = "# Tuned for the handheld\ntitle = \"Game\"\nmods = [\"hd\"]\n\n\
+                    [surface]\nmsaa_samples = 1 # 4 is too slow\n";
+        std::fs::write(&path, text).unwrap();
+
+        let options = SurfaceOptions {
+            present_mode: PresentMode::Mailbox,
+            msaa_samples: 2,
+            ..SurfaceOptions::default()
+        };
+        save_surface_options(&path, &options).unwrap();
+        let saved = std::fs::read_to_string(&path).unwrap();
+        assert!(saved.starts_with("# Tuned for the handheld\ntitle = \"Game\"\nmods = [\"hd\"]\n"));
+        assert!(saved.contains("msaa_samples = 2 # 4 is too slow\n"));
+        assert!(!saved.contains("hot_reload"));
+        let config = GameConfig::load(&path).unwrap();
+        assert_eq!(config.title, "Game");
+        assert_eq!(config.surface, options);
+
+        let path = dir.0.join("new.ron");
+        save_surface_options(&path, &options).unwrap();
+        assert_eq!(GameConfig::load(&path).unwrap().surface, options);
+    }
+}
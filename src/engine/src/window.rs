@@ -0,0 +1,276 @@
+use crate::AppError;
+use assets::Image;
+use std::{
+    cmp::Reverse,
+    path::{Path, PathBuf},
+};
+use winit::{
+    dpi::LogicalSize,
+    event_loop::ActiveEventLoop,
+    monitor::MonitorHandle,
+    window::{Fullscreen, Icon, WindowAttributes},
+};
+
+/// Whether the window covers the screen. Defaults to
+/// [`BorderlessFullscreen`](Self::BorderlessFullscreen) on the web and
+/// [`Windowed`](Self::Windowed) everywhere else.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum WindowMode {
+    Windowed,
+    /// A borderless window the size of the monitor. On the web, the page goes fullscreen.
+    BorderlessFullscreen,
+    /// Switches the monitor to the video mode closest to the window size, or its largest
+    /// one without a size. Falls back to borderless where that is not possible.
+    ExclusiveFullscreen,
+}
+
+impl Default for WindowMode {
+    fn default() -> Self {
+        if cfg!(target_arch = "wasm32") {
+            WindowMode::BorderlessFullscreen
+        } else {
+            WindowMode::Windowed
+        }
+    }
+}
+
+/// How the window is created. Sizes are in logical pixels.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct WindowConfig {
+    /// `None` leaves the initial size to the platform.
+    pub size: Option<[u32; 2]>,
+    pub min_size: Option<[u32; 2]>,
+    pub max_size: Option<[u32; 2]>,
+    pub resizable: bool,
+    /// Title bar and borders.
+    pub decorations: bool,
+    pub mode: WindowMode,
+    /// Image file shown as the window icon, where the platform has one. Native only.
+    pub icon: Option<PathBuf>,
+    /// CSS selector of an existing canvas to draw into. Without one a canvas is created
+    /// and appended to the page body. Web only.
+    pub canvas: Option<String>,
+    /// Size the canvas to its parent element and follow it when it resizes. Web only.
+    pub fit_canvas_to_parent: bool,
+}
+
+impl Default for WindowConfig {
+    fn default() -> Self {
+        Self {
+            size: None,
+            min_size: None,
+            max_size: None,
+            resizable: true,
+            decorations: true,
+            mode: WindowMode::default(),
+            icon: None,
+            canvas: None,
+            fit_canvas_to_parent: false,
+        }
+    }
+}
+
+fn logical([width, height]: [u32; 2]) -> LogicalSize<u32> {
+    LogicalSize::new(width, height)
+}
+
+// `primary_monitor` is only asked for exclusive fullscreen.
+fn fullscreen(
+    config: &WindowConfig,
+    primary_monitor: impl FnOnce() -> Option<MonitorHandle>,
+) -> Option<Fullscreen> {
+    match config.mode {
+        WindowMode::Windowed => None,
+        WindowMode::BorderlessFullscreen => Some(Fullscreen::Borderless(None)),
+        WindowMode::ExclusiveFullscreen => {
+            let Some(monitor) = primary_monitor() else {
+                log::warn!("no monitor for exclusive fullscreen, using borderless");
+                return Some(Fullscreen::Borderless(None));
+            };
+            let wanted = config.size.map(|size| {
+                let size = logical(size).to_physical::<u32>(monitor.scale_factor());
+                (size.width, size.height)
+            });
+            // Closest to the wanted size, or the largest, then the highest refresh rate.
+            let mode = monitor.video_modes().min_by_key(|mode| {
+                let size = mode.size();
+                let distance = wanted.map_or(0, |(width, height)| {
+                    size.width.abs_diff(width) + size.height.abs_diff(height)
+                });
+                (
+                    distance,
+                    Reverse(size.width * size.height),
+                    Reverse(mode.refresh_rate_millihertz()),
+                )
+            });
+            match mode {
+                Some(mode) => Some(Fullscreen::Exclusive(mode)),
+                None => {
+                    log::warn!("no video modes for exclusive fullscreen, using borderless");
+                    Some(Fullscreen::Borderless(Some(monitor)))
+                }
+            }
+        }
+    }
+}
+
+fn load_icon(path: &Path) -> Option<Icon> {
+    let result = std::fs::read(path)
+        .map_err(|err| err.to_string())
+        .and_then(|bytes| Image::from_bytes(&bytes).map_err(|err| err.to_string()))
+        .and_then(|image| {
+            Icon::from_rgba(image.data, image.width, image.height).map_err(|err| err.to_string())
+        });
+    match result {
+        Ok(icon) => Some(icon),
+        Err(err) => {
+            log::warn!("failed to load window icon {}: {err}", path.display());
+            None
+        }
+    }
+}
+
+/// Attributes for the window described by `config`. On the web this also finds or
+/// creates the canvas.
+pub(crate) fn window_attributes(
+    title: &str,
+    config: &WindowConfig,
+    event_loop: &ActiveEventLoop,
+) -> Result<WindowAttributes, AppError> {
+    let attributes = base_attributes(title, config, || event_loop.primary_monitor());
+
+    #[cfg(target_arch = "wasm32")]
+    let attributes = {
+        use winit::platform::web::WindowAttributesExtWebSys;
+
+        let mut attributes = attributes;
+        let canvas = web::canvas(config)?;
+        if config.fit_canvas_to_parent
+            && let Some(size) = web::fit_to_parent(&canvas)
+        {
+            attributes = attributes.with_inner_size(size);
+        }
+        attributes.with_canvas(Some(canvas))
+    };
+
+    Ok(attributes)
+}
+
+// Everything but the web canvas.
+fn base_attributes(
+    title: &str,
+    config: &WindowConfig,
+    primary_monitor: impl FnOnce() -> Option<MonitorHandle>,
+) -> WindowAttributes {
+    let mut attributes = WindowAttributes::default()
+        .with_title(title)
+        .with_resizable(config.resizable)
+        .with_decorations(config.decorations)
+        .with_fullscreen(fullscreen(config, primary_monitor));
+    if let Some(size) = config.size {
+        attributes = attributes.with_inner_size(logical(size));
+    }
+    if let Some(size) = config.min_size {
+        attributes = attributes.with_min_inner_size(logical(size));
+    }
+    if let Some(size) = config.max_size {
+        attributes = attributes.with_max_inner_size(logical(size));
+    }
+    if let Some(path) = &config.icon
+        && cfg!(not(target_arch = "wasm32"))
+    {
+        attributes = attributes.with_window_icon(load_icon(path));
+    }
+    attributes
+}
+
+#[cfg(target_arch = "wasm32")]
+mod web {
+    use super::WindowConfig;
+    use crate::AppError;
+    use wasm_bindgen::JsCast;
+    use web_sys::HtmlCanvasElement;
+    use winit::dpi::LogicalSize;
+
+    fn document() -> Result<web_sys::Document, AppError> {
+        web_sys::window()
+            .and_then(|window| window.document())
+            .ok_or_else(|| AppError::Canvas("no document to put a canvas in".into()))
+    }
+
+    // The canvas `config.canvas` selects, or a new one appended to the body.
+    pub(super) fn canvas(config: &WindowConfig) -> Result<HtmlCanvasElement, AppError> {
+        let document = document()?;
+        if let Some(selector) = &config.canvas {
+            return document
+                .query_selector(selector)
+                .ok()
+                .flatten()
+                .and_then(|element| element.dyn_into::<HtmlCanvasElement>().ok())
+                .ok_or_else(|| AppError::Canvas(format!("no canvas matches `{selector}`")));
+        }
+        let canvas = document
+            .create_element("canvas")
+            .ok()
+            .and_then(|element| element.dyn_into::<HtmlCanvasElement>().ok())
+            .ok_or_else(|| AppError::Canvas("failed to create a canvas".into()))?;
+        document
+            .body()
+            .and_then(|body| body.append_child(&canvas).ok())
+            .ok_or_else(|| AppError::Canvas("failed to append the canvas to the body".into()))?;
+        Ok(canvas)
+    }
+
+    // Stretch the canvas over its parent with CSS, leaving its other styles alone; winit
+    // follows the canvas size from then on. Returns the parent's current size.
+    pub(super) fn fit_to_parent(canvas: &HtmlCanvasElement) -> Option<LogicalSize<u32>> {
+        let style = canvas.style();
+        for (property, value) in [("display", "block"), ("width", "100%"), ("height", "100%")] {
+            let _ = style.set_property(property, value);
+        }
+        let parent = canvas.parent_element()?;
+        let (width, height) = (parent.client_width(), parent.client_height());
+        (width > 0 && height > 0).then(|| LogicalSize::new(width as u32, height as u32))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use winit::dpi::Size;
+
+    #[test]
+    fn test_window_attributes_for_each_mode() {
+        let config = WindowConfig {
+            size: Some([640, 360]),
+            resizable: false,
+            ..Default::default()
+        };
+        let attributes = base_attributes("Game", &config, || unreachable!());
+        assert_eq!(attributes.title, "Game");
+        assert!(!attributes.resizable && attributes.decorations);
+        assert_eq!(
+            attributes.inner_size,
+            Some(Size::Logical(winit::dpi::LogicalSize::new(640.0, 360.0)))
+        );
+        assert_eq!(attributes.fullscreen, None);
+
+        let borderless = WindowConfig {
+            mode: WindowMode::BorderlessFullscreen,
+            ..config.clone()
+        };
+        let attributes = base_attributes("Game", &borderless, || unreachable!());
+        assert_eq!(attributes.fullscreen, Some(Fullscreen::Borderless(None)));
+
+        // Without a monitor to pick a video mode from, exclusive falls back to borderless.
+        let exclusive = WindowConfig {
+            mode: WindowMode::ExclusiveFullscreen,
+            ..config
+        };
+        let attributes = base_attributes("Game", &exclusive, || None);
+        assert_eq!(attributes.fullscreen, Some(Fullscreen::Borderless(None)));
+    }
+}
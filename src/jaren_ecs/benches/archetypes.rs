@@ -0,0 +1,79 @@
+//! Queries and spawning in a world with 1024 archetypes, ten entities each, of which 16
+//! have a velocity. Run with `cargo bench -p jaren_ecs --bench archetypes`.
+
+use criterion::{Criterion, criterion_group, criterion_main};
+use jaren_ecs::{
+    query::QueryState,
+    system::{Entity, World},
+};
+use jaren_ecs_derive::Component;
+use std::hint::black_box;
+
+#[derive(Component)]
+struct Position(f32);
+
+#[derive(Component)]
+struct Velocity(f32);
+
+#[derive(Component)]
+struct Marker<const N: usize>;
+
+// Give `entity` the markers of the bits set in `bits`, for 2^10 archetypes.
+fn mark(world: &mut World, entity: Entity, bits: usize) {
+    macro_rules! bit {
+        ($($n:literal),*) => {$(
+            if bits & (1 << $n) != 0 {
+                world.insert_bundle(entity, Marker::<$n>);
+            }
+        )*};
+    }
+    bit!(0, 1, 2, 3, 4, 5, 6, 7, 8, 9);
+}
+
+const ARCHETYPES: usize = 1024;
+
+fn world() -> World {
+    let mut world = World::new();
+    for bits in 0..ARCHETYPES {
+        for _ in 0..10 {
+            let entity = world.spawn(Position(1.0));
+            mark(&mut world, entity, bits);
+            if bits % 64 == 0 {
+                world.insert_bundle(entity, Velocity(1.0));
+            }
+        }
+    }
+    world
+}
+
+fn archetypes(c: &mut Criterion) {
+    let mut world = world();
+    c.bench_function("query/iter", |b| {
+        b.iter(|| {
+            world
+                .query::<Velocity>()
+                .iter()
+                .map(|(_, v)| v.0)
+                .sum::<f32>()
+        })
+    });
+    let mut velocities = QueryState::<Velocity>::new(&world);
+    c.bench_function("query_state/iter", |b| {
+        b.iter(|| velocities.iter(&world).map(|(_, v)| v.0).sum::<f32>())
+    });
+    let mut moving = QueryState::<(Position, Velocity)>::new(&world);
+    c.bench_function("query_state/iter_pair", |b| {
+        b.iter(|| {
+            moving
+                .iter(&world)
+                .map(|(_, (p, v))| p.0 + v.0)
+                .sum::<f32>()
+        })
+    });
+    c.bench_function("spawn/existing_archetype", |b| {
+        b.iter(|| black_box(world.spawn((Position(0.0), Marker::<3>, Marker::<7>))))
+    });
+}
+
+criterion_group!(benches, archetypes);
+criterion_main!(benches);
@@ -0,0 +1,81 @@
+//! Table vs sparse-set storage for a tag added to and removed from many entities, and for
+//! iterating it. Run with `cargo bench -p jaren_ecs --bench storage`.
+
+use criterion::{BenchmarkId, Criterion, criterion_group, criterion_main};
+use jaren_ecs::system::{Component, Entity, World};
+use jaren_ecs_derive::Component;
+use std::hint::black_box;
+
+#[derive(Component)]
+struct Position(f32);
+
+#[derive(Component)]
+struct Velocity;
+
+#[derive(Component)]
+struct TableTag(u32);
+
+#[derive(Component)]
+#[component(storage = "sparse_set")]
+struct SparseTag(u32);
+
+const ENTITIES: usize = 10_000;
+
+fn world() -> (World, Vec<Entity>) {
+    let mut world = World::new();
+    let entities = (0..ENTITIES)
+        .map(|i| world.spawn((Position(i as f32), Velocity)))
+        .collect();
+    (world, entities)
+}
+
+fn add_remove<T: Component>(c: &mut Criterion, name: &str, tag: fn(u32) -> T) {
+    let (mut world, entities) = world();
+    c.bench_function(&format!("add_remove/{name}"), |b| {
+        b.iter(|| {
+            for (i, entity) in entities.iter().enumerate() {
+                world.insert_bundle(*entity, tag(i as u32));
+            }
+            for entity in &entities {
+                black_box(world.remove_bundle::<T>(*entity));
+            }
+        })
+    });
+}
+
+fn iterate<T: Component>(c: &mut Criterion, name: &str, tag: fn(u32) -> T, value: fn(&T) -> u32) {
+    let (mut world, entities) = world();
+    for (i, entity) in entities.iter().enumerate() {
+        world.insert_bundle(*entity, tag(i as u32));
+    }
+    let mut group = c.benchmark_group("iterate");
+    group.bench_function(BenchmarkId::new("tag", name), |b| {
+        b.iter(|| {
+            world
+                .query::<T>()
+                .iter()
+                .map(|(_, t)| value(t))
+                .sum::<u32>()
+        })
+    });
+    group.bench_function(BenchmarkId::new("position_and_tag", name), |b| {
+        b.iter(|| {
+            world
+                .query::<(Position, T)>()
+                .iter()
+                .map(|(_, (p, t))| p.0 + value(t) as f32)
+                .sum::<f32>()
+        })
+    });
+    group.finish();
+}
+
+fn storage(c: &mut Criterion) {
+    add_remove(c, "table", TableTag);
+    add_remove(c, "sparse_set", SparseTag);
+    iterate(c, "table", TableTag, |t| t.0);
+    iterate(c, "sparse_set", SparseTag, |t| t.0);
+}
+
+criterion_group!(benches, storage);
+criterion_main!(benches);
@@ -0,0 +1,60 @@
+use crate::system::Entity;
+use std::collections::HashMap;
+
+/// Old entity ids to the ids they were given when loaded into a world.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct EntityMap {
+    map: HashMap<Entity, Entity>,
+}
+
+impl EntityMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn insert(&mut self, from: Entity, to: Entity) {
+        self.map.insert(from, to);
+    }
+
+    /// The new id of `entity`, `None` if it was not part of what was loaded.
+    pub fn get(&self, entity: Entity) -> Option<Entity> {
+        self.map.get(&entity).copied()
+    }
+
+    /// The new id of `entity`, or `entity` itself if it was not part of what was loaded,
+    /// e.g. a reference to an entity that already existed in the world.
+    pub fn map(&self, entity: Entity) -> Entity {
+        self.get(entity).unwrap_or(entity)
+    }
+
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (Entity, Entity)> + '_ {
+        self.map.iter().map(|(from, to)| (*from, *to))
+    }
+}
+
+/// Implemented by components that hold [`Entity`] ids, so the ids can be updated when
+/// entities are loaded under new ids.
+///
+/// ```
+/// # use jaren_ecs::{entity_map::{EntityMap, MapEntities}, system::Entity};
+/// struct Follow {
+///     target: Entity,
+/// }
+///
+/// impl MapEntities for Follow {
+///     fn map_entities(&mut self, map: &EntityMap) {
+///         self.target = map.map(self.target);
+///     }
+/// }
+/// ```
+pub trait MapEntities {
+    fn map_entities(&mut self, map: &EntityMap);
+}
@@ -0,0 +1,35 @@
+use crate::{
+    entity_map::{EntityMap, MapEntities},
+    system::{Entity, World},
+};
+use jaren_ecs_derive::Component;
+
+/// Makes an entity the child of another one.
+#[derive(Component, Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Parent(pub Entity);
+
+impl MapEntities for Parent {
+    fn map_entities(&mut self, map: &EntityMap) {
+        self.0 = map.map(self.0);
+    }
+}
+
+impl World {
+    /// The parent of `entity`, if it has a [`Parent`].
+    pub fn parent(&self, entity: Entity) -> Option<Entity> {
+        self.component::<Parent>(entity).map(|parent| parent.0)
+    }
+
+    /// The entities whose [`Parent`] is `entity`, in ascending order.
+    pub fn children(&self, entity: Entity) -> Vec<Entity> {
+        let mut children: Vec<_> = self
+            .query::<Parent>()
+            .iter()
+            .filter(|(_, parent)| parent.0 == entity)
+            .map(|(child, _)| child)
+            .collect();
+        children.sort();
+        children
+    }
+}
@@ -1 +1,14 @@
+// Lets the derive macros name `::jaren_ecs` from inside this crate too.
+extern crate self as jaren_ecs;
+
+pub mod entity_map;
+pub mod hierarchy;
+pub mod param;
+pub mod query;
+pub mod reflect;
+#[cfg(feature = "serde")]
+pub mod save;
+#[cfg(feature = "serde")]
+pub mod snapshot;
+pub mod storage;
 pub mod system;
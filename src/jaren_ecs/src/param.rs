@@ -0,0 +1,366 @@
+//! System parameters besides queries, and the [`Access`] sets used to check that
+//! parameters do not clash.
+//!
+//! ```
+//! # use jaren_ecs::{param::{Commands, Res, ResMut}, spawn, system::{Query, QueryMut, Scheduler, System, World}};
+//! # use jaren_ecs_derive::{Component, SystemParam};
+//! #[derive(Component)]
+//! struct Position(f32);
+//! #[derive(Component)]
+//! struct Speed(f32);
+//! struct Step(f32);
+//! #[derive(Default)]
+//! struct Moved(usize);
+//!
+//! #[derive(SystemParam)]
+//! struct Movement<'w> {
+//!     positions: QueryMut<'w, Position>,
+//!     step: Res<'w, Step>,
+//!     moved: ResMut<'w, Moved>,
+//! }
+//!
+//! fn movement(mut movement: Movement) {
+//!     let step = movement.step.0;
+//!     let mut moved = 0;
+//!     movement.positions.for_each_mut(|_, position| {
+//!         position.0 += step;
+//!         moved += 1;
+//!     });
+//!     movement.moved.0 += moved;
+//! }
+//!
+//! fn spawner(mut commands: Commands) {
+//!     commands.spawn((Position(0.0), Speed(1.0)));
+//! }
+//!
+//! fn report(speeds: Query<Speed>) {
+//!     assert!(speeds.iter().count() <= 1);
+//! }
+//!
+//! let mut world = World::new();
+//! world.insert_resource(Step(0.5));
+//! world.insert_resource(Moved::default());
+//! spawn!(world, Position(1.0));
+//!
+//! let mut scheduler = Scheduler::new();
+//! scheduler.add_system(System::new(spawner));
+//! scheduler.add_system(System::new(movement));
+//! scheduler.add_system(System::new(report));
+//! scheduler.run(&mut world);
+//! assert_eq!(world.resource::<Moved>().unwrap().0, 2);
+//! assert!(scheduler.conflicts().is_empty());
+//! ```
+
+use crate::system::{Bundle, Component, Entity, SystemParam, World};
+use std::{
+    any::TypeId,
+    collections::HashMap,
+    ops::{Deref, DerefMut},
+};
+
+#[derive(Clone, Copy, Debug)]
+struct Borrow {
+    name: &'static str,
+    write: bool,
+}
+
+/// The component and resource types a system reads and writes.
+#[derive(Clone, Debug, Default)]
+pub struct Access {
+    components: HashMap<TypeId, Borrow>,
+    resources: HashMap<TypeId, Borrow>,
+}
+
+// Record a borrow, panicking if it clashes with one already recorded.
+fn add(borrows: &mut HashMap<TypeId, Borrow>, type_id: TypeId, name: &'static str, write: bool) {
+    if let Some(borrow) = borrows.get(&type_id) {
+        assert!(
+            !write && !borrow.write,
+            "system parameters borrow `{name}` mutably more than once or while reading it"
+        );
+    }
+    borrows.insert(type_id, Borrow { name, write });
+}
+
+// Names of the types one side writes and the other uses.
+fn clashes(a: &HashMap<TypeId, Borrow>, b: &HashMap<TypeId, Borrow>) -> Vec<&'static str> {
+    a.iter()
+        .filter_map(|(type_id, borrow)| {
+            let other = b.get(type_id)?;
+            (borrow.write || other.write).then_some(borrow.name)
+        })
+        .collect()
+}
+
+impl Access {
+    /// # Panics
+    /// If `T` is already written. This and the other methods panic on clashes within one
+    /// system, which could otherwise see a component or resource changing under it.
+    pub fn read_component<T: Component>(&mut self) {
+        add(
+            &mut self.components,
+            TypeId::of::<T>(),
+            std::any::type_name::<T>(),
+            false,
+        );
+    }
+
+    pub fn write_component<T: Component>(&mut self) {
+        add(
+            &mut self.components,
+            TypeId::of::<T>(),
+            std::any::type_name::<T>(),
+            true,
+        );
+    }
+
+    pub fn read_resource<R: 'static>(&mut self) {
+        add(
+            &mut self.resources,
+            TypeId::of::<R>(),
+            std::any::type_name::<R>(),
+            false,
+        );
+    }
+
+    pub fn write_resource<R: 'static>(&mut self) {
+        add(
+            &mut self.resources,
+            TypeId::of::<R>(),
+            std::any::type_name::<R>(),
+            true,
+        );
+    }
+
+    /// Names of the types one of `self` and `other` writes and the other uses, sorted.
+    /// Empty if the two can run in either order.
+    pub fn conflicts(&self, other: &Access) -> Vec<&'static str> {
+        let mut types = clashes(&self.components, &other.components);
+        types.extend(clashes(&self.resources, &other.resources));
+        types.sort_unstable();
+        types
+    }
+}
+
+/// Shared access to the resource `R`.
+///
+/// # Panics
+/// Fetching it panics if the world has no `R`.
+pub struct Res<'a, R> {
+    value: &'a R,
+}
+
+impl<R> Deref for Res<'_, R> {
+    type Target = R;
+
+    fn deref(&self) -> &R {
+        self.value
+    }
+}
+
+impl<R: Send + Sync + 'static> SystemParam for Res<'_, R> {
+    type Param<'a> = Res<'a, R>;
+    type Taken = ();
+
+    fn access(access: &mut Access) {
+        access.read_resource::<R>();
+    }
+
+    fn take(_world: &mut World) {}
+
+    fn fetch<'a>(world: &'a World, _taken: &'a mut ()) -> Res<'a, R> {
+        let value = world
+            .resource::<R>()
+            .unwrap_or_else(|| panic!("system needs resource `{}`", std::any::type_name::<R>()));
+        Res { value }
+    }
+
+    fn restore(_world: &mut World, _taken: ()) {}
+}
+
+/// Mutable access to the resource `R`, which is taken out of the world while the system
+/// runs.
+///
+/// # Panics
+/// Fetching it panics if the world has no `R`.
+pub struct ResMut<'a, R> {
+    value: &'a mut R,
+}
+
+impl<R> Deref for ResMut<'_, R> {
+    type Target = R;
+
+    fn deref(&self) -> &R {
+        self.value
+    }
+}
+
+impl<R> DerefMut for ResMut<'_, R> {
+    fn deref_mut(&mut self) -> &mut R {
+        self.value
+    }
+}
+
+impl<R: Send + Sync + 'static> SystemParam for ResMut<'_, R> {
+    type Param<'a> = ResMut<'a, R>;
+    type Taken = R;
+
+    fn access(access: &mut Access) {
+        access.write_resource::<R>();
+    }
+
+    fn take(world: &mut World) -> R {
+        world
+            .remove_resource::<R>()
+            .unwrap_or_else(|| panic!("system needs resource `{}`", std::any::type_name::<R>()))
+    }
+
+    fn fetch<'a>(_world: &'a World, taken: &'a mut R) -> ResMut<'a, R> {
+        ResMut { value: taken }
+    }
+
+    fn restore(world: &mut World, taken: R) {
+        world.insert_resource(taken);
+    }
+}
+
+/// Changes to the world that are applied after the system has run, when nothing else
+/// borrows it. Commands do not count towards a system's [`Access`].
+pub struct Commands<'a> {
+    queue: &'a mut Vec<crate::system::Command>,
+}
+
+impl Commands<'_> {
+    pub fn spawn<B: Bundle>(&mut self, bundle: B) {
+        self.add(move |world| {
+            world.spawn(bundle);
+        });
+    }
+
+    pub fn insert_bundle<B: Bundle>(&mut self, entity: Entity, bundle: B) {
+        self.add(move |world| world.insert_bundle(entity, bundle));
+    }
+
+    pub fn remove_bundle<B: Bundle>(&mut self, entity: Entity) {
+        self.add(move |world| {
+            world.remove_bundle::<B>(entity);
+        });
+    }
+
+    /// Queue any change.
+    pub fn add(&mut self, command: impl FnOnce(&mut World) + Send + Sync + 'static) {
+        self.queue.push(Box::new(command));
+    }
+}
+
+impl SystemParam for Commands<'_> {
+    type Param<'a> = Commands<'a>;
+    type Taken = Vec<crate::system::Command>;
+
+    fn access(_access: &mut Access) {}
+
+    fn take(_world: &mut World) -> Self::Taken {
+        Vec::new()
+    }
+
+    fn fetch<'a>(_world: &'a World, taken: &'a mut Self::Taken) -> Commands<'a> {
+        Commands { queue: taken }
+    }
+
+    fn restore(world: &mut World, taken: Self::Taken) {
+        world.commands.extend(taken);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        spawn,
+        system::{Query, QueryMut, Scheduler, System},
+    };
+    use jaren_ecs_derive::{Component, SystemParam};
+
+    #[derive(Component, Debug, PartialEq)]
+    struct Health(u32);
+
+    #[derive(Component)]
+    struct Poisoned;
+
+    struct Tick(u32);
+
+    #[derive(SystemParam)]
+    struct Damage<'w, T: Component> {
+        health: QueryMut<'w, (Health, T)>,
+        tick: Res<'w, Tick>,
+        commands: Commands<'w>,
+    }
+
+    #[derive(SystemParam)]
+    struct Ticks<'w>(ResMut<'w, Tick>);
+
+    fn poison(mut damage: Damage<Poisoned>) {
+        let amount = damage.tick.0;
+        let mut dead = Vec::new();
+        damage.health.for_each_mut(|entity, (health, _)| {
+            health.0 = health.0.saturating_sub(amount);
+            if health.0 == 0 {
+                dead.push(entity);
+            }
+        });
+        for entity in dead {
+            damage.commands.remove_bundle::<(Health, Poisoned)>(entity);
+        }
+    }
+
+    fn tick(mut ticks: Ticks) {
+        ticks.0.0 += 1;
+    }
+
+    fn count(health: Query<Health>) {
+        assert!(health.iter().count() <= 2);
+    }
+
+    #[test]
+    fn test_derived_params_and_conflicts() {
+        let mut world = World::new();
+        world.insert_resource(Tick(1));
+        let weak = spawn!(world, Health(2), Poisoned);
+        let strong = spawn!(world, Health(10), Poisoned);
+
+        let mut scheduler = Scheduler::new();
+        scheduler.add_system(System::new(poison));
+        scheduler.add_system(System::new(tick));
+        scheduler.add_system(System::new(count));
+        scheduler.run(&mut world);
+        scheduler.run(&mut world);
+
+        let health = world.query::<Health>();
+        assert_eq!(
+            health.iter().collect::<Vec<_>>(),
+            vec![(strong, &Health(7))]
+        );
+        assert!(world.components(weak).is_empty());
+        assert_eq!(world.resource::<Tick>().unwrap().0, 3);
+
+        let conflicts = scheduler.conflicts();
+        assert_eq!(conflicts.len(), 2);
+        assert!(conflicts[0].first.ends_with("poison") && conflicts[0].second.ends_with("tick"));
+        assert_eq!(conflicts[0].types, vec![std::any::type_name::<Tick>()]);
+        assert!(conflicts[1].second.ends_with("count"));
+        assert_eq!(conflicts[1].types, vec![std::any::type_name::<Health>()]);
+    }
+
+    #[derive(SystemParam)]
+    struct Clashing<'w> {
+        _read: Query<'w, Health>,
+        _write: QueryMut<'w, Health>,
+    }
+
+    #[test]
+    #[should_panic(expected = "mutably more than once or while reading it")]
+    fn test_clashing_params_panic() {
+        fn clashing(_: Clashing) {}
+        System::new(clashing);
+    }
+}
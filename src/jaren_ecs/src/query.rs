@@ -0,0 +1,191 @@
+//! Queries that remember which archetypes they match.
+//!
+//! A [`Query`](crate::system::Query) checks every archetype each time it is iterated. A
+//! [`QueryState`] keeps the indices of the matching archetypes and, since archetypes are
+//! never removed, only checks the ones created since it last ran:
+//!
+//! ```
+//! # use jaren_ecs::{query::QueryState, spawn, system::World};
+//! # use jaren_ecs_derive::Component;
+//! #[derive(Component)]
+//! struct Position(f32);
+//! #[derive(Component)]
+//! struct Velocity(f32);
+//!
+//! let mut world = World::new();
+//! let mut moving = QueryState::<(Position, Velocity)>::new(&world);
+//! spawn!(world, Position(0.0), Velocity(2.0));
+//! spawn!(world, Position(5.0));
+//!
+//! moving.for_each_mut(&mut world, |_, (position, velocity)| position.0 += velocity.0);
+//! let positions: Vec<f32> = moving.iter(&world).map(|(_, (p, _))| p.0).collect();
+//! assert_eq!(positions, vec![2.0]);
+//! ```
+
+use crate::system::{
+    Component, Entity, StorageType, World, WorldId, iter_archetypes, iter_archetypes_pair,
+};
+use std::{any::TypeId, marker::PhantomData};
+
+/// The archetypes matching the query `T`, a component or a pair of components.
+pub struct QueryState<T> {
+    world: WorldId,
+    // Table-stored types a matching archetype has columns for
+    table: Vec<TypeId>,
+    archetypes: Vec<usize>,
+    // Archetypes checked so far
+    seen: usize,
+    _marker: PhantomData<fn() -> T>,
+}
+
+fn table_types(types: &[(TypeId, StorageType)]) -> Vec<TypeId> {
+    types
+        .iter()
+        .filter(|(_, storage)| *storage == StorageType::Table)
+        .map(|(type_id, _)| *type_id)
+        .collect()
+}
+
+impl<T> QueryState<T> {
+    fn with_types(world: &World, types: &[(TypeId, StorageType)]) -> Self {
+        let mut state = Self {
+            world: world.id,
+            table: table_types(types),
+            archetypes: Vec::new(),
+            seen: 0,
+            _marker: PhantomData,
+        };
+        state.update(world);
+        state
+    }
+
+    /// Match the archetypes created since the last update. Iterating updates first.
+    ///
+    /// # Panics
+    /// If `world` is not the world the state was made for.
+    pub fn update(&mut self, world: &World) {
+        assert_eq!(self.world, world.id, "QueryState used with another world");
+        // Queries of sparse-set stored components alone do not use archetypes
+        if !self.table.is_empty() {
+            for (index, archetype) in world.archetypes.iter().enumerate().skip(self.seen) {
+                if archetype.contains_all(&self.table) {
+                    self.archetypes.push(index);
+                }
+            }
+        }
+        self.seen = world.archetypes.len();
+    }
+
+    /// Indices of the matching archetypes.
+    pub fn archetypes(&self) -> &[usize] {
+        &self.archetypes
+    }
+}
+
+impl<T: Component> QueryState<T> {
+    pub fn new(world: &World) -> Self {
+        Self::with_types(world, &[(TypeId::of::<T>(), T::storage())])
+    }
+
+    pub fn iter<'w>(
+        &mut self,
+        world: &'w World,
+    ) -> impl Iterator<Item = (Entity, &'w T)> + use<'_, 'w, T> {
+        self.update(world);
+        iter_archetypes(world, self.archetypes.iter().copied())
+    }
+
+    pub fn for_each_mut(&mut self, world: &mut World, f: impl FnMut(Entity, &mut T)) {
+        self.update(world);
+        world
+            .query_mut_archetypes::<T>(self.archetypes.iter().copied())
+            .for_each_mut(f);
+    }
+}
+
+impl<A: Component, B: Component> QueryState<(A, B)> {
+    pub fn new(world: &World) -> Self {
+        Self::with_types(
+            world,
+            &[
+                (TypeId::of::<A>(), A::storage()),
+                (TypeId::of::<B>(), B::storage()),
+            ],
+        )
+    }
+
+    pub fn iter<'s, 'w: 's>(
+        &'s mut self,
+        world: &'w World,
+    ) -> Box<dyn Iterator<Item = (Entity, (&'w A, &'w B))> + 's> {
+        self.update(world);
+        iter_archetypes_pair(world, self.archetypes.iter().copied())
+    }
+
+    /// `A` and `B` must be different types.
+    pub fn for_each_mut(&mut self, world: &mut World, f: impl FnMut(Entity, (&mut A, &mut B))) {
+        self.update(world);
+        world
+            .query_mut_archetypes::<(A, B)>(self.archetypes.iter().copied())
+            .for_each_mut(f);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::QueryState;
+    use crate::{spawn, system::World};
+    use jaren_ecs_derive::Component;
+
+    #[derive(Component, Debug, PartialEq)]
+    struct Position(i32);
+
+    #[derive(Component, Debug, PartialEq)]
+    struct Velocity(i32);
+
+    #[derive(Component, Debug, PartialEq)]
+    #[component(storage = "sparse_set")]
+    struct Stunned(i32);
+
+    #[derive(Component, Debug, PartialEq)]
+    struct Player;
+
+    #[test]
+    fn test_query_state_matches_new_archetypes() {
+        let mut world = World::new();
+        let a = spawn!(world, Position(1), Velocity(1));
+        let mut positions = QueryState::<Position>::new(&world);
+        let mut moving = QueryState::<(Position, Velocity)>::new(&world);
+        let mut stunned = QueryState::<(Position, Stunned)>::new(&world);
+        assert_eq!(moving.archetypes().len(), 1);
+
+        let b = spawn!(world, Position(2));
+        let c = spawn!(world, Player, Velocity(3), Position(3), Stunned(2));
+        let d = spawn!(world, Stunned(4));
+        assert_eq!(moving.archetypes().len(), 1);
+        moving.for_each_mut(&mut world, |_, (p, v)| p.0 += v.0);
+        assert_eq!(moving.archetypes().len(), 2);
+        stunned.for_each_mut(&mut world, |_, (p, s)| p.0 *= s.0);
+
+        let mut found: Vec<_> = positions.iter(&world).map(|(e, p)| (e, p.0)).collect();
+        found.sort();
+        assert_eq!(found, vec![(a, 2), (b, 2), (c, 12)]);
+        assert_eq!(positions.archetypes().len(), 3);
+        let found: Vec<_> = stunned.iter(&world).map(|(e, (_, s))| (e, s.0)).collect();
+        assert_eq!(found, vec![(c, 2)]);
+
+        let mut sparse = QueryState::<(Stunned, Stunned)>::new(&world);
+        let found: Vec<_> = sparse.iter(&world).map(|(e, _)| e).collect();
+        assert_eq!(found.len(), 2);
+        assert!(found.contains(&d));
+        assert!(sparse.archetypes().is_empty());
+    }
+
+    #[test]
+    #[should_panic(expected = "QueryState used with another world")]
+    fn test_query_state_belongs_to_one_world() {
+        let world = World::new();
+        let mut positions = QueryState::<Position>::new(&world);
+        positions.update(&World::new());
+    }
+}
@@ -0,0 +1,412 @@
+//! Reading and writing fields without knowing the type, for inspectors, editors and
+//! scripting.
+//!
+//! ```
+//! # use jaren_ecs::reflect::Reflect;
+//! # use jaren_ecs_derive::Reflect;
+//! #[derive(Reflect)]
+//! struct Stats {
+//!     speed: f32,
+//!     jumps: u32,
+//! }
+//!
+//! #[derive(Reflect)]
+//! struct Player {
+//!     name: String,
+//!     stats: Stats,
+//!     spawn: [f32; 2],
+//! }
+//!
+//! let mut player = Player {
+//!     name: "Ada".into(),
+//!     stats: Stats { speed: 2.0, jumps: 1 },
+//!     spawn: [0.0, 0.0],
+//! };
+//! let player: &mut dyn Reflect = &mut player;
+//! assert_eq!(player.get::<f32>("stats.speed"), Some(&2.0));
+//! player.set("spawn.1", 5.0f32).unwrap();
+//! assert_eq!(player.get::<f32>("spawn.1"), Some(&5.0));
+//! assert!(player.set("stats.jumps", 2.0f32).is_err());
+//! ```
+
+use crate::system::{Component, Entity, World};
+use std::{
+    any::{Any, TypeId},
+    collections::HashMap,
+    fmt,
+};
+
+/// A value whose fields can be listed and reached by name. Derive it with
+/// `#[derive(Reflect)]` from `jaren_ecs_derive`.
+pub trait Reflect: Any + Send + Sync {
+    /// Names of the fields, in declaration order: `0`, `1`, … for tuple structs. Enums
+    /// have the fields of their current variant, and `Option` has `0` when it is `Some`.
+    /// Lists and arrays reach their items by index but do not list them.
+    fn field_names(&self) -> &'static [&'static str] {
+        &[]
+    }
+
+    fn field(&self, _name: &str) -> Option<&dyn Reflect> {
+        None
+    }
+
+    fn field_mut(&mut self, _name: &str) -> Option<&mut dyn Reflect> {
+        None
+    }
+
+    fn type_name(&self) -> &'static str {
+        std::any::type_name::<Self>()
+    }
+
+    /// Name of the current variant of an enum, `None` for anything else.
+    fn variant_name(&self) -> Option<&'static str> {
+        None
+    }
+}
+
+/// A field of a [`Reflect`] value.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FieldInfo {
+    pub name: &'static str,
+    pub type_name: &'static str,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ReflectError {
+    /// Nothing at this path.
+    NoField(String),
+    /// The value does not have the type of the field.
+    TypeMismatch {
+        path: String,
+        expected: &'static str,
+        found: &'static str,
+    },
+}
+
+impl fmt::Display for ReflectError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReflectError::NoField(path) => write!(f, "no field `{path}`"),
+            ReflectError::TypeMismatch {
+                path,
+                expected,
+                found,
+            } => write!(f, "field `{path}` is a {expected}, not a {found}"),
+        }
+    }
+}
+
+impl std::error::Error for ReflectError {}
+
+impl dyn Reflect {
+    /// Name and type of every field.
+    pub fn fields(&self) -> Vec<FieldInfo> {
+        self.field_names()
+            .iter()
+            .filter_map(|name| {
+                Some(FieldInfo {
+                    name,
+                    type_name: self.field(name)?.type_name(),
+                })
+            })
+            .collect()
+    }
+
+    /// The value at `path`, field names and indices separated by dots. An empty path is
+    /// the value itself.
+    pub fn path(&self, path: &str) -> Option<&dyn Reflect> {
+        path.split('.')
+            .filter(|part| !part.is_empty())
+            .try_fold(self, |value, part| value.field(part))
+    }
+
+    pub fn path_mut(&mut self, path: &str) -> Option<&mut dyn Reflect> {
+        path.split('.')
+            .filter(|part| !part.is_empty())
+            .try_fold(self, |value, part| value.field_mut(part))
+    }
+
+    pub fn downcast_ref<T: Reflect>(&self) -> Option<&T> {
+        (self as &dyn Any).downcast_ref()
+    }
+
+    pub fn downcast_mut<T: Reflect>(&mut self) -> Option<&mut T> {
+        (self as &mut dyn Any).downcast_mut()
+    }
+
+    /// The value at `path` if it is a `T`.
+    pub fn get<T: Reflect>(&self, path: &str) -> Option<&T> {
+        self.path(path)?.downcast_ref()
+    }
+
+    pub fn get_mut<T: Reflect>(&mut self, path: &str) -> Option<&mut T> {
+        self.path_mut(path)?.downcast_mut()
+    }
+
+    /// Replace the value at `path`, which must be a `T`.
+    pub fn set<T: Reflect>(&mut self, path: &str, value: T) -> Result<(), ReflectError> {
+        let field = self
+            .path_mut(path)
+            .ok_or_else(|| ReflectError::NoField(path.to_string()))?;
+        let expected = field.type_name();
+        let field = field
+            .downcast_mut::<T>()
+            .ok_or_else(|| ReflectError::TypeMismatch {
+                path: path.to_string(),
+                expected,
+                found: std::any::type_name::<T>(),
+            })?;
+        *field = value;
+        Ok(())
+    }
+}
+
+macro_rules! impl_reflect_value {
+    ($($ty:ty),*) => {
+        $(impl Reflect for $ty {})*
+    };
+}
+
+impl_reflect_value!(
+    bool, char, u8, u16, u32, u64, u128, usize, i8, i16, i32, i64, i128, isize, f32, f64, String
+);
+
+impl<T: Reflect> Reflect for Vec<T> {
+    fn field(&self, name: &str) -> Option<&dyn Reflect> {
+        Some(self.get(name.parse::<usize>().ok()?)?)
+    }
+
+    fn field_mut(&mut self, name: &str) -> Option<&mut dyn Reflect> {
+        Some(self.get_mut(name.parse::<usize>().ok()?)?)
+    }
+}
+
+impl<T: Reflect> Reflect for Option<T> {
+    fn field_names(&self) -> &'static [&'static str] {
+        match self {
+            Some(_) => &["0"],
+            None => &[],
+        }
+    }
+
+    fn field(&self, name: &str) -> Option<&dyn Reflect> {
+        match (self, name) {
+            (Some(value), "0") => Some(value),
+            _ => None,
+        }
+    }
+
+    fn field_mut(&mut self, name: &str) -> Option<&mut dyn Reflect> {
+        match (self, name) {
+            (Some(value), "0") => Some(value),
+            _ => None,
+        }
+    }
+
+    fn variant_name(&self) -> Option<&'static str> {
+        Some(if self.is_some() { "Some" } else { "None" })
+    }
+}
+
+impl<T: Reflect, const N: usize> Reflect for [T; N] {
+    fn field(&self, name: &str) -> Option<&dyn Reflect> {
+        Some(self.get(name.parse::<usize>().ok()?)?)
+    }
+
+    fn field_mut(&mut self, name: &str) -> Option<&mut dyn Reflect> {
+        Some(self.get_mut(name.parse::<usize>().ok()?)?)
+    }
+}
+
+type AsReflect = fn(&dyn Component) -> Option<&dyn Reflect>;
+type AsReflectMut = fn(&mut dyn Component) -> Option<&mut dyn Reflect>;
+
+fn as_reflect<T: Component + Reflect>(component: &dyn Component) -> Option<&dyn Reflect> {
+    Some(component.as_any().downcast_ref::<T>()?)
+}
+
+fn as_reflect_mut<T: Component + Reflect>(
+    component: &mut dyn Component,
+) -> Option<&mut dyn Reflect> {
+    Some(component.as_any_mut().downcast_mut::<T>()?)
+}
+
+/// The component types that can be reflected, so an entity's components can be
+/// inspected without knowing their types.
+#[derive(Default)]
+pub struct ReflectRegistry {
+    by_type: HashMap<TypeId, (AsReflect, AsReflectMut)>,
+}
+
+impl ReflectRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn register<T: Component + Reflect>(&mut self) -> &mut Self {
+        self.by_type.insert(
+            TypeId::of::<T>(),
+            (
+                as_reflect::<T> as AsReflect,
+                as_reflect_mut::<T> as AsReflectMut,
+            ),
+        );
+        self
+    }
+
+    /// `component` as a [`Reflect`], `None` if its type is not registered.
+    pub fn reflect<'a>(&self, component: &'a dyn Component) -> Option<&'a dyn Reflect> {
+        let (reflect, _) = self.by_type.get(&component.as_any().type_id())?;
+        reflect(component)
+    }
+
+    pub fn reflect_mut<'a>(&self, component: &'a mut dyn Component) -> Option<&'a mut dyn Reflect> {
+        let (_, reflect_mut) = self.by_type.get(&component.as_any().type_id())?;
+        reflect_mut(component)
+    }
+
+    /// The registered components of `entity`.
+    pub fn components<'a>(&self, world: &'a World, entity: Entity) -> Vec<&'a dyn Reflect> {
+        world
+            .components(entity)
+            .into_iter()
+            .filter_map(|component| self.reflect(component))
+            .collect()
+    }
+
+    pub fn components_mut<'a>(
+        &self,
+        world: &'a mut World,
+        entity: Entity,
+    ) -> Vec<&'a mut dyn Reflect> {
+        world
+            .components_mut(entity)
+            .into_iter()
+            .filter_map(|component| self.reflect_mut(component))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::spawn;
+    use jaren_ecs_derive::{Component, Reflect};
+    use std::marker::PhantomData;
+
+    #[derive(Component, Reflect, Debug, PartialEq)]
+    struct Position(f32, f32);
+
+    #[derive(Reflect, Debug, PartialEq)]
+    struct Wrapper<T> {
+        value: T,
+        #[reflect(ignore)]
+        _marker: PhantomData<fn() -> T>,
+    }
+
+    #[derive(Component, Reflect)]
+    struct Inventory {
+        items: Vec<String>,
+        weight: Wrapper<f64>,
+    }
+
+    #[test]
+    fn test_reflect_components_by_path() {
+        let mut registry = ReflectRegistry::new();
+        registry.register::<Position>().register::<Inventory>();
+
+        let mut world = World::new();
+        let entity = spawn!(
+            world,
+            Position(1.0, 2.0),
+            Inventory {
+                items: vec!["rope".into()],
+                weight: Wrapper {
+                    value: 3.5,
+                    _marker: PhantomData
+                },
+            }
+        );
+
+        let components = registry.components(&world, entity);
+        assert_eq!(components.len(), 2);
+        let inventory = components
+            .iter()
+            .find(|component| component.type_name().ends_with("Inventory"))
+            .unwrap();
+        assert_eq!(
+            inventory.fields(),
+            vec![
+                FieldInfo {
+                    name: "items",
+                    type_name: std::any::type_name::<Vec<String>>()
+                },
+                FieldInfo {
+                    name: "weight",
+                    type_name: std::any::type_name::<Wrapper<f64>>()
+                },
+            ]
+        );
+        assert_eq!(inventory.path("weight").unwrap().field_names(), ["value"]);
+        assert_eq!(inventory.get::<String>("items.0").unwrap(), "rope");
+
+        for component in registry.components_mut(&mut world, entity) {
+            if let Some(position) = component.downcast_mut::<Position>() {
+                position.1 = 4.0;
+            } else {
+                component.set("weight.value", 1.25f64).unwrap();
+                assert_eq!(
+                    component.set("items.3", String::new()),
+                    Err(ReflectError::NoField("items.3".into()))
+                );
+            }
+        }
+        let positions = world.query::<Position>();
+        assert_eq!(positions.iter().next().unwrap().1, &Position(1.0, 4.0));
+        let inventories = world.query::<Inventory>();
+        assert_eq!(inventories.iter().next().unwrap().1.weight.value, 1.25);
+    }
+
+    #[derive(Reflect, Debug, PartialEq)]
+    enum Behaviour {
+        Idle,
+        Patrol(f32, #[reflect(ignore)] u32),
+        Chase { target: Option<String>, speed: f32 },
+    }
+
+    #[test]
+    fn test_reflect_enums_and_options() {
+        let mut behaviour = Behaviour::Chase {
+            target: Some("player".into()),
+            speed: 2.0,
+        };
+        let reflect: &mut dyn Reflect = &mut behaviour;
+        assert_eq!(reflect.variant_name(), Some("Chase"));
+        assert_eq!(reflect.field_names(), ["target", "speed"]);
+        assert_eq!(reflect.path("target").unwrap().variant_name(), Some("Some"));
+        assert_eq!(reflect.get::<String>("target.0").unwrap(), "player");
+        reflect.set("speed", 3.0f32).unwrap();
+        reflect.set("target", None::<String>).unwrap();
+        assert!(reflect.path("target.0").is_none());
+        assert_eq!(
+            behaviour,
+            Behaviour::Chase {
+                target: None,
+                speed: 3.0
+            }
+        );
+
+        let mut behaviour = Behaviour::Patrol(1.0, 7);
+        let reflect: &mut dyn Reflect = &mut behaviour;
+        assert_eq!(reflect.field_names(), ["0"]);
+        assert!(reflect.field("1").is_none());
+        reflect.set("0", 4.0f32).unwrap();
+        assert!(reflect.field("speed").is_none());
+        assert_eq!(behaviour, Behaviour::Patrol(4.0, 7));
+
+        let idle: &dyn Reflect = &Behaviour::Idle;
+        assert_eq!(idle.variant_name(), Some("Idle"));
+        assert!(idle.field_names().is_empty());
+        assert_eq!((&1u32 as &dyn Reflect).variant_name(), None);
+    }
+}
@@ -0,0 +1,259 @@
+//! Save games that keep loading after the game changes.
+//!
+//! A [`SaveSchema`] lists the persistent components and carries a version number, which
+//! is written into every save. Bump it whenever saved components change shape and
+//! register a migration that upgrades data from the previous version. Saves are JSON, and
+//! migrations edit that JSON before any component is read:
+//!
+//! ```
+//! # use jaren_ecs::{save::SaveSchema, spawn, system::{Component, World}};
+//! # use jaren_ecs_derive::Component;
+//! #[derive(Component, Clone, serde::Serialize, serde::Deserialize)]
+//! struct Health {
+//!     current: u32,
+//!     max: u32,
+//! }
+//!
+//! // Version 1 saved `Health` without `max`.
+//! fn add_max_health(world: &mut serde_json::Value) -> Result<(), String> {
+//!     for entity in world["entities"].as_array_mut().ok_or("no entities")? {
+//!         if let Some(health) = entity["components"].get_mut("Health") {
+//!             health["max"] = health["current"].clone();
+//!         }
+//!     }
+//!     Ok(())
+//! }
+//!
+//! let mut schema = SaveSchema::new(2);
+//! schema.persist_as::<Health>("Health").migration(1, add_max_health);
+//!
+//! let old = br#"{"version": 1, "world": {"entities": [
+//!     {"entity": 0, "components": {"Health": {"current": 7}}}
+//! ]}}"#;
+//! let mut world = World::new();
+//! world.load_game(&schema, old).unwrap();
+//! assert_eq!(world.query::<Health>().iter().next().unwrap().1.max, 7);
+//! ```
+
+use crate::{
+    entity_map::{EntityMap, MapEntities},
+    snapshot::ComponentRegistry,
+    system::{Component, World},
+};
+use serde::{Serialize, de::DeserializeOwned};
+use std::{collections::BTreeMap, fmt};
+
+/// Upgrades the saved world, as JSON, by one version. Errors are reported as
+/// [`SaveError::Migration`].
+pub type Migration = fn(&mut serde_json::Value) -> Result<(), String>;
+
+/// The persistent components and the migrations from older save versions.
+pub struct SaveSchema {
+    version: u32,
+    registry: ComponentRegistry,
+    migrations: BTreeMap<u32, Migration>,
+}
+
+impl SaveSchema {
+    /// A schema writing saves as `version`, without any persistent components yet.
+    pub fn new(version: u32) -> Self {
+        Self {
+            version,
+            registry: ComponentRegistry::new(),
+            migrations: BTreeMap::new(),
+        }
+    }
+
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+
+    /// The persistent components.
+    pub fn registry(&self) -> &ComponentRegistry {
+        &self.registry
+    }
+
+    /// Save `T` under `name`, which then must not change between versions unless a
+    /// migration renames it.
+    pub fn persist_as<T: Component + Clone + Serialize + DeserializeOwned>(
+        &mut self,
+        name: &str,
+    ) -> &mut Self {
+        self.registry.register_as::<T>(name);
+        self
+    }
+
+    /// Save `T` under `name` like [`persist_as`](Self::persist_as). `T` holds entity
+    /// ids, which are remapped on load.
+    pub fn persist_with_entities_as<T>(&mut self, name: &str) -> &mut Self
+    where
+        T: Component + Clone + Serialize + DeserializeOwned + MapEntities,
+    {
+        self.registry.register_with_entities_as::<T>(name);
+        self
+    }
+
+    /// Upgrade saves written as version `from` to `from + 1`.
+    pub fn migration(&mut self, from: u32, migrate: Migration) -> &mut Self {
+        self.migrations.insert(from, migrate);
+        self
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum SaveError {
+    /// A component failed to serialize.
+    Serialize(String),
+    /// The save is malformed or holds a component that is not persistent.
+    Deserialize(String),
+    /// The save was written by a newer version of the game.
+    TooNew { version: u32, supported: u32 },
+    /// No migration upgrades saves from this version.
+    MissingMigration { from: u32 },
+    /// A migration failed.
+    Migration { from: u32, message: String },
+}
+
+impl fmt::Display for SaveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SaveError::Serialize(message) => write!(f, "failed to save game: {message}"),
+            SaveError::Deserialize(message) => write!(f, "failed to load game: {message}"),
+            SaveError::TooNew { version, supported } => write!(
+                f,
+                "save version {version} is newer than the supported version {supported}"
+            ),
+            SaveError::MissingMigration { from } => {
+                write!(f, "no migration from save version {from}")
+            }
+            SaveError::Migration { from, message } => {
+                write!(f, "failed to migrate save version {from}: {message}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for SaveError {}
+
+impl World {
+    /// The entities with persistent components, with those components and the schema
+    /// version, as JSON.
+    pub fn save_game(&self, schema: &SaveSchema) -> Result<Vec<u8>, SaveError> {
+        let error = |err: serde_json::Error| SaveError::Serialize(err.to_string());
+        let world = self
+            .serialize(&schema.registry, serde_json::value::Serializer)
+            .map_err(error)?;
+        let save = serde_json::json!({ "version": schema.version, "world": world });
+        serde_json::to_vec_pretty(&save).map_err(error)
+    }
+
+    /// Spawn the entities of a save written by [`save_game`](Self::save_game), after
+    /// migrating it to the schema's version. Returns the saved entity ids mapped to the
+    /// new ones. Nothing is spawned on error.
+    pub fn load_game(&mut self, schema: &SaveSchema, data: &[u8]) -> Result<EntityMap, SaveError> {
+        let error = |message: String| SaveError::Deserialize(message);
+        let mut save: serde_json::Value =
+            serde_json::from_slice(data).map_err(|err| error(err.to_string()))?;
+        let version = save["version"]
+            .as_u64()
+            .and_then(|version| u32::try_from(version).ok())
+            .ok_or_else(|| error("missing save version".into()))?;
+        if version > schema.version {
+            return Err(SaveError::TooNew {
+                version,
+                supported: schema.version,
+            });
+        }
+        let mut world = save["world"].take();
+        for from in version..schema.version {
+            let migrate = schema
+                .migrations
+                .get(&from)
+                .ok_or(SaveError::MissingMigration { from })?;
+            migrate(&mut world).map_err(|message| SaveError::Migration { from, message })?;
+        }
+        self.deserialize(&schema.registry, world)
+            .map_err(|err| error(err.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::spawn;
+    use jaren_ecs_derive::Component;
+    use serde::Deserialize;
+
+    #[derive(Component, Serialize, Deserialize, PartialEq, Debug, Clone)]
+    struct Inventory {
+        gold: u32,
+    }
+
+    #[derive(Component, Clone)]
+    struct Particles;
+
+    fn rename_purse(world: &mut serde_json::Value) -> Result<(), String> {
+        for entity in world["entities"].as_array_mut().ok_or("no entities")? {
+            let components = entity["components"]
+                .as_object_mut()
+                .ok_or("no components")?;
+            if let Some(purse) = components.remove("Purse") {
+                components.insert("Inventory".into(), purse);
+            }
+        }
+        Ok(())
+    }
+
+    fn double_gold(world: &mut serde_json::Value) -> Result<(), String> {
+        for entity in world["entities"].as_array_mut().ok_or("no entities")? {
+            if let Some(gold) = entity["components"]["Inventory"].get_mut("gold") {
+                *gold = (gold.as_u64().ok_or("gold is not a number")? * 2).into();
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_save_game_persists_and_migrates() {
+        let mut schema = SaveSchema::new(3);
+        schema
+            .persist_as::<Inventory>("Inventory")
+            .migration(1, rename_purse)
+            .migration(2, double_gold);
+
+        let mut world = World::new();
+        spawn!(world, Inventory { gold: 5 }, Particles);
+        spawn!(world, Particles);
+        let saved = world.save_game(&schema).unwrap();
+        let text = String::from_utf8(saved.clone()).unwrap();
+        assert!(text.contains("\"version\": 3"));
+
+        let mut loaded = World::new();
+        assert_eq!(loaded.load_game(&schema, &saved).unwrap().len(), 1);
+        assert_eq!(loaded.query::<Particles>().iter().count(), 0);
+        let inventories = loaded.query::<Inventory>();
+        assert_eq!(inventories.iter().next().unwrap().1.gold, 5);
+
+        let old = br#"{"version": 1, "world": {"entities": [
+            {"entity": 4, "components": {"Purse": {"gold": 10}}}
+        ]}}"#;
+        let mut migrated = World::new();
+        migrated.load_game(&schema, old).unwrap();
+        let inventories = migrated.query::<Inventory>();
+        assert_eq!(inventories.iter().next().unwrap().1.gold, 20);
+
+        let newer = text.replace("\"version\": 3", "\"version\": 4");
+        assert_eq!(
+            World::new().load_game(&schema, newer.as_bytes()),
+            Err(SaveError::TooNew {
+                version: 4,
+                supported: 3
+            })
+        );
+        let unmigrated = text.replace("\"version\": 3", "\"version\": 0");
+        assert_eq!(
+            World::new().load_game(&schema, unmigrated.as_bytes()),
+            Err(SaveError::MissingMigration { from: 0 })
+        );
+    }
+}
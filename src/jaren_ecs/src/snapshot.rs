@@ -0,0 +1,734 @@
+//! Saving the entities of a [`World`] and loading them back into another one.
+//!
+//! Only components registered in a [`ComponentRegistry`] are saved. Snapshots can be
+//! written with any serde format through [`World::serialize`] and [`World::deserialize`],
+//! or as RON, JSON or compact binary through [`World::save_snapshot`] and
+//! [`World::load_snapshot`]:
+//!
+//! ```
+//! # use jaren_ecs::{snapshot::{ComponentRegistry, SnapshotFormat}, spawn, system::{Component, World}};
+//! # use jaren_ecs_derive::Component;
+//! #[derive(Component, Clone, serde::Serialize, serde::Deserialize)]
+//! struct Position(f32, f32);
+//!
+//! let mut registry = ComponentRegistry::new();
+//! registry.register_as::<Position>("Position");
+//!
+//! let mut world = World::new();
+//! spawn!(world, Position(1.0, 2.0));
+//! let saved = world.save_snapshot(&registry, SnapshotFormat::Ron).unwrap();
+//!
+//! let mut loaded = World::new();
+//! loaded.load_snapshot(&registry, &saved, SnapshotFormat::Ron).unwrap();
+//! assert_eq!(loaded.query::<Position>().iter().count(), 1);
+//! ```
+
+use crate::{
+    entity_map::{EntityMap, MapEntities},
+    system::{Component, Entity, World},
+};
+use serde::{
+    Deserializer, Serialize, Serializer,
+    de::{self, DeserializeOwned, DeserializeSeed, MapAccess, SeqAccess, Visitor},
+    ser::{SerializeMap, SerializeStruct},
+};
+use std::{any::TypeId, collections::HashMap, fmt};
+
+type SerializeFn = fn(&dyn Component) -> &dyn erased_serde::Serialize;
+type DeserializeFn = for<'de> fn(
+    &mut dyn erased_serde::Deserializer<'de>,
+) -> Result<Box<dyn Component>, erased_serde::Error>;
+type CloneFn = fn(&dyn Component) -> Box<dyn Component>;
+type MapEntitiesFn = fn(&mut dyn Component, &EntityMap);
+
+struct Registration {
+    name: String,
+    serialize: SerializeFn,
+    deserialize: DeserializeFn,
+    clone: CloneFn,
+    map_entities: Option<MapEntitiesFn>,
+}
+
+fn serialize_as<T: Component + Serialize>(
+    component: &dyn Component,
+) -> &dyn erased_serde::Serialize {
+    component
+        .as_any()
+        .downcast_ref::<T>()
+        .expect("components are registered under their own TypeId")
+}
+
+fn deserialize_as<T: Component + DeserializeOwned>(
+    deserializer: &mut dyn erased_serde::Deserializer<'_>,
+) -> Result<Box<dyn Component>, erased_serde::Error> {
+    Ok(Box::new(erased_serde::deserialize::<T>(deserializer)?))
+}
+
+fn clone_as<T: Component + Clone>(component: &dyn Component) -> Box<dyn Component> {
+    Box::new(
+        component
+            .as_any()
+            .downcast_ref::<T>()
+            .expect("components are registered under their own TypeId")
+            .clone(),
+    )
+}
+
+fn map_entities_as<T: Component + MapEntities>(component: &mut dyn Component, map: &EntityMap) {
+    if let Some(component) = component.as_any_mut().downcast_mut::<T>() {
+        component.map_entities(map);
+    }
+}
+
+/// The component types that can be saved, each under a name that identifies it in saved
+/// data. Registered components are `Clone` so loaded data such as scenes can be spawned
+/// more than once.
+#[derive(Default)]
+pub struct ComponentRegistry {
+    by_type: HashMap<TypeId, Registration>,
+    by_name: HashMap<String, TypeId>,
+}
+
+impl ComponentRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register `T` under `name`. Saved data refers to `T` by this name only, so it
+    /// keeps loading after the type is renamed or moved.
+    ///
+    /// # Panics
+    /// If another type is already registered under `name`.
+    pub fn register_as<T: Component + Clone + Serialize + DeserializeOwned>(
+        &mut self,
+        name: &str,
+    ) -> &mut Self {
+        self.insert::<T>(name, None)
+    }
+
+    /// Register `T` under `name` like [`register_as`](Self::register_as). `T` holds
+    /// entity ids, which are remapped on load.
+    ///
+    /// # Panics
+    /// If another type is already registered under `name`.
+    pub fn register_with_entities_as<T>(&mut self, name: &str) -> &mut Self
+    where
+        T: Component + Clone + Serialize + DeserializeOwned + MapEntities,
+    {
+        self.insert::<T>(name, Some(map_entities_as::<T>))
+    }
+
+    fn insert<T: Component + Clone + Serialize + DeserializeOwned>(
+        &mut self,
+        name: &str,
+        map_entities: Option<MapEntitiesFn>,
+    ) -> &mut Self {
+        let type_id = TypeId::of::<T>();
+        if let Some(existing) = self.by_name.get(name) {
+            assert!(
+                *existing == type_id,
+                "component name `{name}` is already registered for another type"
+            );
+        }
+        if let Some(previous) = self.by_type.get(&type_id) {
+            self.by_name.remove(&previous.name);
+        }
+        self.by_name.insert(name.to_string(), type_id);
+        self.by_type.insert(
+            type_id,
+            Registration {
+                name: name.to_string(),
+                serialize: serialize_as::<T>,
+                deserialize: deserialize_as::<T>,
+                clone: clone_as::<T>,
+                map_entities,
+            },
+        );
+        self
+    }
+
+    pub fn contains<T: Component>(&self) -> bool {
+        self.by_type.contains_key(&TypeId::of::<T>())
+    }
+
+    /// The name `T` is saved under.
+    pub fn name<T: Component>(&self) -> Option<&str> {
+        self.by_type
+            .get(&TypeId::of::<T>())
+            .map(|registration| registration.name.as_str())
+    }
+
+    /// The name `component` is saved under, `None` if its type is not registered.
+    pub fn component_name(&self, component: &dyn Component) -> Option<&str> {
+        self.get(component.as_any().type_id())
+            .map(|registration| registration.name.as_str())
+    }
+
+    /// Replace the field of `component` at `path`, field names and list indices separated
+    /// by dots (`stats.speed`, `waypoints.2`), with `value`. An empty path replaces the
+    /// whole component. The component goes through JSON on the way, so `value` is written
+    /// the way JSON holds it, e.g. enum variants as `"Idle"` or `{"Patrol": {"speed": 2.0}}`.
+    pub fn set_field(
+        &self,
+        component: &mut Box<dyn Component>,
+        path: &str,
+        value: serde_json::Value,
+    ) -> Result<(), SnapshotError> {
+        let registration = self
+            .get(component.as_any().type_id())
+            .ok_or_else(|| SnapshotError::Serialize("component is not registered".into()))?;
+        let mut json = serde_json::to_value((registration.serialize)(component.as_ref()))
+            .map_err(|err| SnapshotError::Serialize(err.to_string()))?;
+        let mut field = &mut json;
+        for part in path.split('.').filter(|part| !part.is_empty()) {
+            field = match field {
+                serde_json::Value::Object(fields) => fields.get_mut(part),
+                serde_json::Value::Array(items) => part
+                    .parse::<usize>()
+                    .ok()
+                    .and_then(|index| items.get_mut(index)),
+                _ => None,
+            }
+            .ok_or_else(|| SnapshotError::Deserialize(format!("no field `{path}`")))?;
+        }
+        *field = value;
+        let mut erased = <dyn erased_serde::Deserializer>::erase(json);
+        *component = (registration.deserialize)(&mut erased)
+            .map_err(|err| SnapshotError::Deserialize(err.to_string()))?;
+        Ok(())
+    }
+
+    /// A copy of `component`, `None` if its type is not registered.
+    pub fn clone_component(&self, component: &dyn Component) -> Option<Box<dyn Component>> {
+        let registration = self.get(component.as_any().type_id())?;
+        Some((registration.clone)(component))
+    }
+
+    /// Update the entity ids held by `component` if its type was registered with
+    /// [`register_with_entities_as`](Self::register_with_entities_as).
+    pub fn map_entities(&self, component: &mut dyn Component, map: &EntityMap) {
+        let type_id = component.as_any().type_id();
+        if let Some(map_entities) = self.get(type_id).and_then(|r| r.map_entities) {
+            map_entities(component, map);
+        }
+    }
+
+    /// Serializes the registered ones among `components` as a map from their names to
+    /// their data, ordered by name. Read back with [`ComponentsSeed`].
+    pub fn serialize_components<'a>(
+        &'a self,
+        components: impl IntoIterator<Item = &'a dyn Component>,
+    ) -> ComponentsRef<'a> {
+        let mut components: Vec<_> = components
+            .into_iter()
+            .filter_map(|component| Some((self.get(component.as_any().type_id())?, component)))
+            .collect();
+        components.sort_by(|a, b| a.0.name.cmp(&b.0.name));
+        ComponentsRef(components)
+    }
+
+    fn get(&self, type_id: TypeId) -> Option<&Registration> {
+        self.by_type.get(&type_id)
+    }
+
+    fn get_by_name(&self, name: &str) -> Option<&Registration> {
+        self.by_type.get(self.by_name.get(name)?)
+    }
+}
+
+/// Formats [`World::save_snapshot`] can write.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SnapshotFormat {
+    /// Human readable, for inspecting and hand editing.
+    Ron,
+    Json,
+    /// Compact, for saving often or sending over the network.
+    Binary,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum SnapshotError {
+    /// A component failed to serialize.
+    Serialize(String),
+    /// The data is malformed or holds a component that is not registered.
+    Deserialize(String),
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SnapshotError::Serialize(message) => write!(f, "failed to save world: {message}"),
+            SnapshotError::Deserialize(message) => write!(f, "failed to load world: {message}"),
+        }
+    }
+}
+
+impl std::error::Error for SnapshotError {}
+
+// Borrowed view of the saved entities, ordered by id and their components by name so
+// the same world always produces the same output.
+struct WorldRef<'a> {
+    entities: Vec<EntityRef<'a>>,
+}
+
+struct EntityRef<'a> {
+    entity: Entity,
+    components: ComponentsRef<'a>,
+}
+
+/// Components to serialize, see [`ComponentRegistry::serialize_components`].
+pub struct ComponentsRef<'a>(Vec<(&'a Registration, &'a dyn Component)>);
+
+impl Serialize for WorldRef<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("World", 1)?;
+        state.serialize_field("entities", &self.entities)?;
+        state.end()
+    }
+}
+
+impl Serialize for EntityRef<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("Entity", 2)?;
+        state.serialize_field("entity", &self.entity)?;
+        state.serialize_field("components", &self.components)?;
+        state.end()
+    }
+}
+
+impl Serialize for ComponentsRef<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(self.0.len()))?;
+        for (registration, component) in &self.0 {
+            map.serialize_entry(&registration.name, (registration.serialize)(*component))?;
+        }
+        map.end()
+    }
+}
+
+/// A struct field name, for visitors that read structs as maps by hand. It is read as
+/// an identifier, which formats like RON tell apart from strings.
+pub struct FieldName(pub String);
+
+impl FieldName {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for FieldName {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_identifier(FieldNameVisitor)
+    }
+}
+
+struct FieldNameVisitor;
+
+impl Visitor<'_> for FieldNameVisitor {
+    type Value = FieldName;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a field name")
+    }
+
+    fn visit_str<E: de::Error>(self, name: &str) -> Result<Self::Value, E> {
+        Ok(FieldName(name.to_string()))
+    }
+
+    fn visit_string<E: de::Error>(self, name: String) -> Result<Self::Value, E> {
+        Ok(FieldName(name))
+    }
+}
+
+// An entity read from a snapshot, not yet spawned.
+struct LoadedEntity {
+    entity: Entity,
+    components: Vec<Box<dyn Component>>,
+}
+
+#[derive(Clone, Copy)]
+struct WorldSeed<'a>(&'a ComponentRegistry);
+
+impl<'de> DeserializeSeed<'de> for WorldSeed<'_> {
+    type Value = Vec<LoadedEntity>;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error> {
+        deserializer.deserialize_struct("World", &["entities"], self)
+    }
+}
+
+impl<'de> Visitor<'de> for WorldSeed<'_> {
+    type Value = Vec<LoadedEntity>;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a world snapshot")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+        seq.next_element_seed(EntitiesSeed(self.0))?
+            .ok_or_else(|| de::Error::invalid_length(0, &self))
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+        let mut entities = None;
+        while let Some(key) = map.next_key::<FieldName>()? {
+            match key.as_str() {
+                "entities" => entities = Some(map.next_value_seed(EntitiesSeed(self.0))?),
+                _ => {
+                    map.next_value::<de::IgnoredAny>()?;
+                }
+            }
+        }
+        entities.ok_or_else(|| de::Error::missing_field("entities"))
+    }
+}
+
+#[derive(Clone, Copy)]
+struct EntitiesSeed<'a>(&'a ComponentRegistry);
+
+impl<'de> DeserializeSeed<'de> for EntitiesSeed<'_> {
+    type Value = Vec<LoadedEntity>;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error> {
+        deserializer.deserialize_seq(self)
+    }
+}
+
+impl<'de> Visitor<'de> for EntitiesSeed<'_> {
+    type Value = Vec<LoadedEntity>;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a list of entities")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+        let mut entities = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+        while let Some(entity) = seq.next_element_seed(EntitySeed(self.0))? {
+            entities.push(entity);
+        }
+        Ok(entities)
+    }
+}
+
+#[derive(Clone, Copy)]
+struct EntitySeed<'a>(&'a ComponentRegistry);
+
+impl<'de> DeserializeSeed<'de> for EntitySeed<'_> {
+    type Value = LoadedEntity;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error> {
+        deserializer.deserialize_struct("Entity", &["entity", "components"], self)
+    }
+}
+
+impl<'de> Visitor<'de> for EntitySeed<'_> {
+    type Value = LoadedEntity;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("an entity with its components")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+        let entity = seq
+            .next_element()?
+            .ok_or_else(|| de::Error::invalid_length(0, &self))?;
+        let components = seq
+            .next_element_seed(ComponentsSeed(self.0))?
+            .ok_or_else(|| de::Error::invalid_length(1, &self))?;
+        Ok(LoadedEntity { entity, components })
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+        let (mut entity, mut components) = (None, None);
+        while let Some(key) = map.next_key::<FieldName>()? {
+            match key.as_str() {
+                "entity" => entity = Some(map.next_value()?),
+                "components" => components = Some(map.next_value_seed(ComponentsSeed(self.0))?),
+                _ => {
+                    map.next_value::<de::IgnoredAny>()?;
+                }
+            }
+        }
+        Ok(LoadedEntity {
+            entity: entity.ok_or_else(|| de::Error::missing_field("entity"))?,
+            components: components.ok_or_else(|| de::Error::missing_field("components"))?,
+        })
+    }
+}
+
+/// Reads a map from registered component names to component data, as written by
+/// [`ComponentRegistry::serialize_components`].
+#[derive(Clone, Copy)]
+pub struct ComponentsSeed<'a>(pub &'a ComponentRegistry);
+
+impl<'de> DeserializeSeed<'de> for ComponentsSeed<'_> {
+    type Value = Vec<Box<dyn Component>>;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error> {
+        deserializer.deserialize_map(self)
+    }
+}
+
+impl<'de> Visitor<'de> for ComponentsSeed<'_> {
+    type Value = Vec<Box<dyn Component>>;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a map of component names to components")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+        let mut components = Vec::with_capacity(map.size_hint().unwrap_or(0));
+        while let Some(name) = map.next_key::<String>()? {
+            let registration = self
+                .0
+                .get_by_name(&name)
+                .ok_or_else(|| de::Error::custom(format!("unknown component `{name}`")))?;
+            components.push(map.next_value_seed(ComponentSeed(registration))?);
+        }
+        Ok(components)
+    }
+}
+
+struct ComponentSeed<'a>(&'a Registration);
+
+impl<'de> DeserializeSeed<'de> for ComponentSeed<'_> {
+    type Value = Box<dyn Component>;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error> {
+        let mut erased = <dyn erased_serde::Deserializer>::erase(deserializer);
+        (self.0.deserialize)(&mut erased).map_err(de::Error::custom)
+    }
+}
+
+impl World {
+    fn snapshot<'a>(&'a self, registry: &'a ComponentRegistry) -> WorldRef<'a> {
+        let mut entities = Vec::new();
+        let registered = |type_id: TypeId| registry.get(type_id).is_some();
+        let sparse_registered = self.sparse_sets.contains_any(registered);
+        for archetype in &self.archetypes {
+            if !sparse_registered && !archetype.components.keys().any(|t| registered(*t)) {
+                continue;
+            }
+            for (row, entity) in archetype.entities.iter().enumerate() {
+                let components = archetype
+                    .components
+                    .values()
+                    .map(|column| column.get(row))
+                    .chain(self.sparse_sets.components(*entity));
+                let components = registry.serialize_components(components);
+                if components.0.is_empty() {
+                    continue;
+                }
+                entities.push(EntityRef {
+                    entity: *entity,
+                    components,
+                });
+            }
+        }
+        entities.sort_by_key(|entity| entity.entity);
+        WorldRef { entities }
+    }
+
+    /// Write every entity that has at least one component in `registry`, with those
+    /// components, to `serializer`.
+    pub fn serialize<S: Serializer>(
+        &self,
+        registry: &ComponentRegistry,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        self.snapshot(registry).serialize(serializer)
+    }
+
+    /// Spawn the entities written by [`serialize`](Self::serialize) as new entities.
+    /// Components registered with
+    /// [`register_with_entities_as`](ComponentRegistry::register_with_entities_as) have
+    /// their entity ids updated to the new ones. Returns the old ids mapped to the new.
+    /// Nothing is spawned if the data fails to load.
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        &mut self,
+        registry: &ComponentRegistry,
+        deserializer: D,
+    ) -> Result<EntityMap, D::Error> {
+        let entities = WorldSeed(registry).deserialize(deserializer)?;
+        Ok(self.spawn_loaded(registry, entities))
+    }
+
+    fn spawn_loaded(
+        &mut self,
+        registry: &ComponentRegistry,
+        entities: Vec<LoadedEntity>,
+    ) -> EntityMap {
+        let mut map = EntityMap::new();
+        for loaded in &entities {
+            let entity = self.alloc_entity();
+            map.insert(loaded.entity, entity);
+        }
+        for loaded in entities {
+            let components = loaded
+                .components
+                .into_iter()
+                .map(|mut component| {
+                    registry.map_entities(component.as_mut(), &map);
+                    component
+                })
+                .collect();
+            self.get_archetype(map.map(loaded.entity), components);
+        }
+        map
+    }
+
+    /// [`serialize`](Self::serialize) into `format`.
+    pub fn save_snapshot(
+        &self,
+        registry: &ComponentRegistry,
+        format: SnapshotFormat,
+    ) -> Result<Vec<u8>, SnapshotError> {
+        use bincode::Options;
+        let snapshot = self.snapshot(registry);
+        let error = |message: String| SnapshotError::Serialize(message);
+        match format {
+            SnapshotFormat::Ron => {
+                ron::ser::to_string_pretty(&snapshot, ron::ser::PrettyConfig::default())
+                    .map(String::into_bytes)
+                    .map_err(|err| error(err.to_string()))
+            }
+            SnapshotFormat::Json => {
+                serde_json::to_vec_pretty(&snapshot).map_err(|err| error(err.to_string()))
+            }
+            SnapshotFormat::Binary => bincode::options()
+                .serialize(&snapshot)
+                .map_err(|err| error(err.to_string())),
+        }
+    }
+
+    /// [`deserialize`](Self::deserialize) from data written by
+    /// [`save_snapshot`](Self::save_snapshot).
+    pub fn load_snapshot(
+        &mut self,
+        registry: &ComponentRegistry,
+        data: &[u8],
+        format: SnapshotFormat,
+    ) -> Result<EntityMap, SnapshotError> {
+        let entities = read_snapshot(registry, data, format)?;
+        Ok(self.spawn_loaded(registry, entities))
+    }
+}
+
+fn read_snapshot(
+    registry: &ComponentRegistry,
+    data: &[u8],
+    format: SnapshotFormat,
+) -> Result<Vec<LoadedEntity>, SnapshotError> {
+    let error = |message: String| SnapshotError::Deserialize(message);
+    let seed = WorldSeed(registry);
+    match format {
+        SnapshotFormat::Ron => {
+            let mut deserializer =
+                ron::de::Deserializer::from_bytes(data).map_err(|err| error(err.to_string()))?;
+            let entities = seed
+                .deserialize(&mut deserializer)
+                .map_err(|err| error(deserializer.span_error(err).to_string()))?;
+            deserializer.end().map_err(|err| error(err.to_string()))?;
+            Ok(entities)
+        }
+        SnapshotFormat::Json => {
+            let mut deserializer = serde_json::Deserializer::from_slice(data);
+            let entities = seed
+                .deserialize(&mut deserializer)
+                .map_err(|err| error(err.to_string()))?;
+            deserializer.end().map_err(|err| error(err.to_string()))?;
+            Ok(entities)
+        }
+        SnapshotFormat::Binary => {
+            let mut deserializer = bincode::de::Deserializer::from_slice(data, bincode::options());
+            seed.deserialize(&mut deserializer)
+                .map_err(|err| error(err.to_string()))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::spawn;
+    use jaren_ecs_derive::Component;
+    use serde::Deserialize;
+
+    #[derive(Component, Serialize, Deserialize, PartialEq, Debug, Clone, Copy)]
+    struct Position(f32, f32);
+
+    #[derive(Component, Serialize, Deserialize, PartialEq, Debug, Clone, Copy)]
+    struct Follow {
+        target: Entity,
+    }
+
+    impl MapEntities for Follow {
+        fn map_entities(&mut self, map: &EntityMap) {
+            self.target = map.map(self.target);
+        }
+    }
+
+    #[derive(Component)]
+    struct Unsaved;
+
+    #[test]
+    fn test_snapshot_round_trip_remaps_entities() {
+        let mut registry = ComponentRegistry::new();
+        registry
+            .register_as::<Position>("Position")
+            .register_with_entities_as::<Follow>("Follow");
+
+        let mut world = World::new();
+        let leader = spawn!(world, Position(1.0, 2.0));
+        let follower = spawn!(world, Position(0.0, 0.0), Follow { target: leader });
+        spawn!(world, Unsaved);
+
+        for format in [
+            SnapshotFormat::Ron,
+            SnapshotFormat::Json,
+            SnapshotFormat::Binary,
+        ] {
+            let saved = world.save_snapshot(&registry, format).unwrap();
+            // Entities already in the world push the loaded ones to new ids.
+            let mut loaded = World::new();
+            spawn!(loaded, Unsaved);
+            spawn!(loaded, Unsaved);
+            let map = loaded.load_snapshot(&registry, &saved, format).unwrap();
+
+            assert_eq!(map.len(), 2, "{format:?}");
+            let (new_leader, new_follower) = (map.map(leader), map.map(follower));
+            assert_ne!(new_leader, leader);
+            let follows = loaded.query::<Follow>();
+            let follows: Vec<_> = follows.iter().collect();
+            assert_eq!(
+                follows,
+                vec![(new_follower, &Follow { target: new_leader })]
+            );
+            let positions = loaded.query::<Position>();
+            let mut positions: Vec<_> = positions.iter().collect();
+            positions.sort_by_key(|(entity, _)| *entity);
+            assert_eq!(
+                positions,
+                vec![
+                    (new_leader, &Position(1.0, 2.0)),
+                    (new_follower, &Position(0.0, 0.0))
+                ]
+            );
+
+            // Saving the loaded world again gives the same data, up to the new ids.
+            assert_eq!(
+                loaded.save_snapshot(&registry, format).unwrap().len(),
+                saved.len(),
+                "{format:?}"
+            );
+        }
+
+        let text = world.save_snapshot(&registry, SnapshotFormat::Ron).unwrap();
+        let text = String::from_utf8(text).unwrap();
+        let unknown = text.replace("\"Position\"", "\"Rotation\"");
+        assert!(matches!(
+            World::new().load_snapshot(&registry, unknown.as_bytes(), SnapshotFormat::Ron),
+            Err(SnapshotError::Deserialize(message)) if message.contains("unknown component `Rotation`")
+        ));
+    }
+}
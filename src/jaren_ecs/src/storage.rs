@@ -0,0 +1,244 @@
+//! Sparse-set storage, for components that are added and removed too often to move their
+//! entities between archetypes each time. See [`StorageType`].
+
+use crate::system::{Component, Entity, StorageType};
+use std::{any::Any, any::TypeId, collections::HashMap};
+
+/// The components of one type, packed for iteration, with their entities' rows in a map.
+pub(crate) struct SparseSet<T> {
+    dense: Vec<T>,
+    entities: Vec<Entity>,
+    rows: HashMap<Entity, usize>,
+}
+
+impl<T> Default for SparseSet<T> {
+    fn default() -> Self {
+        Self {
+            dense: Vec::new(),
+            entities: Vec::new(),
+            rows: HashMap::new(),
+        }
+    }
+}
+
+impl<T> SparseSet<T> {
+    /// Give `entity` a component, returning the one it replaces.
+    pub(crate) fn insert(&mut self, entity: Entity, value: T) -> Option<T> {
+        if let Some(&row) = self.rows.get(&entity) {
+            return Some(std::mem::replace(&mut self.dense[row], value));
+        }
+        self.rows.insert(entity, self.dense.len());
+        self.dense.push(value);
+        self.entities.push(entity);
+        None
+    }
+
+    pub(crate) fn remove(&mut self, entity: Entity) -> Option<T> {
+        let row = self.rows.remove(&entity)?;
+        self.entities.swap_remove(row);
+        if let Some(&moved) = self.entities.get(row) {
+            self.rows.insert(moved, row);
+        }
+        Some(self.dense.swap_remove(row))
+    }
+
+    pub(crate) fn contains(&self, entity: Entity) -> bool {
+        self.rows.contains_key(&entity)
+    }
+
+    pub(crate) fn get(&self, entity: Entity) -> Option<&T> {
+        Some(&self.dense[*self.rows.get(&entity)?])
+    }
+
+    pub(crate) fn get_mut(&mut self, entity: Entity) -> Option<&mut T> {
+        Some(&mut self.dense[*self.rows.get(&entity)?])
+    }
+
+    pub(crate) fn iter(&self) -> impl Iterator<Item = (Entity, &T)> {
+        self.entities.iter().copied().zip(&self.dense)
+    }
+
+    pub(crate) fn iter_mut(&mut self) -> impl Iterator<Item = (Entity, &mut T)> {
+        self.entities.iter().copied().zip(&mut self.dense)
+    }
+}
+
+/// A [`SparseSet`] whose component type is not known.
+pub trait AnySparseSet: Any + Send + Sync {
+    fn contains(&self, entity: Entity) -> bool;
+    fn get(&self, entity: Entity) -> Option<&dyn Component>;
+    fn get_mut(&mut self, entity: Entity) -> Option<&mut dyn Component>;
+    /// Give `entity` a component, which must be of this set's type.
+    fn insert_boxed(&mut self, entity: Entity, component: Box<dyn Component>);
+    /// Drop the component of `entity`, if it has one.
+    fn remove(&mut self, entity: Entity);
+}
+
+impl<T: Component> AnySparseSet for SparseSet<T> {
+    fn contains(&self, entity: Entity) -> bool {
+        SparseSet::contains(self, entity)
+    }
+
+    fn get(&self, entity: Entity) -> Option<&dyn Component> {
+        Some(SparseSet::get(self, entity)?)
+    }
+
+    fn get_mut(&mut self, entity: Entity) -> Option<&mut dyn Component> {
+        Some(SparseSet::get_mut(self, entity)?)
+    }
+
+    fn insert_boxed(&mut self, entity: Entity, component: Box<dyn Component>) {
+        let component: Box<dyn Any> = component;
+        let component = component
+            .downcast::<T>()
+            .expect("component inserted into a sparse set of another type");
+        self.insert(entity, *component);
+    }
+
+    fn remove(&mut self, entity: Entity) {
+        SparseSet::remove(self, entity);
+    }
+}
+
+/// The sparse-set stored components of a world, a set per type.
+#[derive(Default)]
+pub struct SparseSets {
+    sets: HashMap<TypeId, Box<dyn AnySparseSet>>,
+}
+
+impl SparseSets {
+    pub(crate) fn get<T: Component>(&self) -> Option<&SparseSet<T>> {
+        (self.sets.get(&TypeId::of::<T>())?.as_ref() as &dyn Any).downcast_ref()
+    }
+
+    pub(crate) fn get_mut<T: Component>(&mut self) -> Option<&mut SparseSet<T>> {
+        (self.sets.get_mut(&TypeId::of::<T>())?.as_mut() as &mut dyn Any).downcast_mut()
+    }
+
+    /// The sets of `A` and `B`, which must be different types.
+    pub(crate) fn get_pair_mut<A: Component, B: Component>(
+        &mut self,
+    ) -> Option<(&mut SparseSet<A>, &mut SparseSet<B>)> {
+        let [Some(a), Some(b)] = self
+            .sets
+            .get_disjoint_mut([&TypeId::of::<A>(), &TypeId::of::<B>()])
+        else {
+            return None;
+        };
+        let a = (a.as_mut() as &mut dyn Any).downcast_mut()?;
+        let b = (b.as_mut() as &mut dyn Any).downcast_mut()?;
+        Some((a, b))
+    }
+
+    pub(crate) fn get_or_default<T: Component>(&mut self) -> &mut SparseSet<T> {
+        let set = self
+            .sets
+            .entry(TypeId::of::<T>())
+            .or_insert_with(|| Box::new(SparseSet::<T>::default()));
+        (set.as_mut() as &mut dyn Any).downcast_mut().unwrap()
+    }
+
+    pub(crate) fn insert_boxed(&mut self, entity: Entity, component: Box<dyn Component>) {
+        debug_assert_eq!(component.storage_type(), StorageType::SparseSet);
+        self.sets
+            .entry(component.as_any().type_id())
+            .or_insert_with(|| component.empty_sparse_set())
+            .insert_boxed(entity, component);
+    }
+
+    pub(crate) fn contains(&self, type_id: TypeId, entity: Entity) -> bool {
+        self.sets
+            .get(&type_id)
+            .is_some_and(|set| set.contains(entity))
+    }
+
+    #[cfg(feature = "serde")]
+    pub(crate) fn contains_any(&self, types: impl Fn(TypeId) -> bool) -> bool {
+        self.sets.keys().any(|type_id| types(*type_id))
+    }
+
+    /// The components of `entity`.
+    pub(crate) fn components(&self, entity: Entity) -> impl Iterator<Item = &dyn Component> {
+        self.sets.values().filter_map(move |set| set.get(entity))
+    }
+
+    pub(crate) fn components_mut(
+        &mut self,
+        entity: Entity,
+    ) -> impl Iterator<Item = &mut dyn Component> {
+        self.sets
+            .values_mut()
+            .filter_map(move |set| set.get_mut(entity))
+    }
+
+    /// Move the sets of `types` into a new `SparseSets`.
+    pub(crate) fn take(&mut self, types: &[TypeId]) -> SparseSets {
+        let sets = types
+            .iter()
+            .filter_map(|type_id| Some((*type_id, self.sets.remove(type_id)?)))
+            .collect();
+        SparseSets { sets }
+    }
+
+    /// Put back sets moved out by [`take`](Self::take).
+    pub(crate) fn restore(&mut self, taken: SparseSets) {
+        self.sets.extend(taken.sets);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{spawn, system::World};
+    use jaren_ecs_derive::Component;
+
+    #[derive(Component, Debug, PartialEq)]
+    struct Position(i32);
+
+    #[derive(Component, Debug, PartialEq)]
+    #[component(storage = "sparse_set")]
+    struct Stunned(u32);
+
+    #[derive(Component, Debug, PartialEq)]
+    #[component(storage = "sparse_set")]
+    struct Hovered;
+
+    #[test]
+    fn test_sparse_components_join_table_components() {
+        let mut world = World::new();
+        let a = spawn!(world, Position(1), Stunned(2));
+        let b = spawn!(world, Position(2));
+        let c = spawn!(world, Stunned(5), Hovered);
+        let archetypes = world.archetypes.len();
+
+        world.insert_bundle(b, (Stunned(1), Hovered));
+        assert_eq!(world.remove_bundle::<Stunned>(a), Some(Stunned(2)));
+        assert_eq!(world.remove_bundle::<Hovered>(a), None);
+        world.insert_bundle(a, Hovered);
+        assert_eq!(world.archetypes.len(), archetypes);
+
+        let mut stunned: Vec<_> = world
+            .query::<Stunned>()
+            .iter()
+            .map(|(e, s)| (e, s.0))
+            .collect();
+        stunned.sort();
+        assert_eq!(stunned, vec![(b, 1), (c, 5)]);
+        let positions = world.query::<(Position, Hovered)>();
+        let mut hovered: Vec<_> = positions.iter().map(|(e, (p, _))| (e, p.0)).collect();
+        hovered.sort();
+        assert_eq!(hovered, vec![(a, 1), (b, 2)]);
+        let both = world.query::<(Hovered, Stunned)>();
+        let mut both: Vec<_> = both.iter().map(|(e, _)| e).collect();
+        both.sort();
+        assert_eq!(both, vec![b, c]);
+
+        world
+            .query_mut::<(Stunned, Position)>()
+            .for_each_mut(|_, (stunned, position)| position.0 += stunned.0 as i32);
+        let positions = world.query::<Position>();
+        let mut positions: Vec<_> = positions.iter().map(|(e, p)| (e, p.0)).collect();
+        positions.sort();
+        assert_eq!(positions, vec![(a, 1), (b, 3)]);
+        assert_eq!(world.components(c).len(), 2);
+    }
+}
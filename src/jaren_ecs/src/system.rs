@@ -1,31 +1,368 @@
+use crate::{
+    param::Access,
+    storage::{AnySparseSet, SparseSet, SparseSets},
+};
 use std::{any::{Any, TypeId}, collections::HashMap};
 
 pub type Entity = u64;
 
-pub trait Component: Any + Send + Sync + 'static {
+pub trait Component: Any + Send + Sync + 'static + ComponentStorage {
     fn as_any(&self) -> &dyn Any;
     fn as_any_mut(&mut self) -> &mut dyn Any;
+
+    /// How components of this type are stored, `#[component(storage = "...")]` when derived.
+    fn storage() -> StorageType
+    where
+        Self: Sized,
+    {
+        StorageType::Table
+    }
+
+    /// Called after this component is added to an entity, `#[component(on_add = ...)]`
+    /// when derived.
+    fn on_add(&self) -> Option<ComponentHook> {
+        None
+    }
+}
+
+/// Where a component type is kept.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum StorageType {
+    /// In the archetype tables, next to the entity's other components. Fastest to iterate.
+    #[default]
+    Table,
+    /// In a set of its own, for components that are added and removed often.
+    SparseSet,
+}
+
+/// Runs when a component is added to `entity`.
+pub type ComponentHook = fn(&mut World, Entity);
+
+/// The components of one type in an [`Archetype`], a row per entity.
+pub trait Column: Any + Send + Sync {
+    fn get(&self, row: usize) -> &dyn Component;
+    fn get_mut(&mut self, row: usize) -> &mut dyn Component;
+    /// Push a component, which must be of this column's type.
+    fn push_boxed(&mut self, component: Box<dyn Component>);
+    fn swap(&mut self, a: usize, b: usize);
+    /// Drop the component at `row`, moving the last one into its place.
+    fn swap_remove(&mut self, row: usize);
+    /// Move the component at `row` to the end of `to`, a column of the same type, moving
+    /// the last one into its place.
+    fn move_row(&mut self, row: usize, to: &mut dyn Column);
+    /// An empty column of the same type.
+    fn empty(&self) -> Box<dyn Column>;
+}
+
+impl<T: Component> Column for Vec<T> {
+    fn get(&self, row: usize) -> &dyn Component {
+        &self[row]
+    }
+
+    fn get_mut(&mut self, row: usize) -> &mut dyn Component {
+        &mut self[row]
+    }
+
+    fn push_boxed(&mut self, component: Box<dyn Component>) {
+        let component: Box<dyn Any> = component;
+        let component = component
+            .downcast::<T>()
+            .expect("component pushed onto a column of another type");
+        self.push(*component);
+    }
+
+    fn swap(&mut self, a: usize, b: usize) {
+        <[T]>::swap(self, a, b);
+    }
+
+    fn swap_remove(&mut self, row: usize) {
+        Vec::swap_remove(self, row);
+    }
+
+    fn move_row(&mut self, row: usize, to: &mut dyn Column) {
+        let to = (to as &mut dyn Any)
+            .downcast_mut::<Vec<T>>()
+            .expect("component moved to a column of another type");
+        to.push(Vec::swap_remove(self, row));
+    }
+
+    fn empty(&self) -> Box<dyn Column> {
+        Box::new(Vec::<T>::new())
+    }
+}
+
+/// How a component is stored, for components whose type is not known. Implemented for
+/// every [`Component`].
+pub trait ComponentStorage {
+    /// [`Component::storage`] of the component's type.
+    fn storage_type(&self) -> StorageType;
+    fn empty_column(&self) -> Box<dyn Column>;
+    fn empty_sparse_set(&self) -> Box<dyn AnySparseSet>;
+}
+
+impl<T: Component> ComponentStorage for T {
+    fn storage_type(&self) -> StorageType {
+        T::storage()
+    }
+
+    fn empty_column(&self) -> Box<dyn Column> {
+        Box::new(Vec::<T>::new())
+    }
+
+    fn empty_sparse_set(&self) -> Box<dyn AnySparseSet> {
+        Box::new(SparseSet::<T>::default())
+    }
+}
+
+/// Components added to and removed from an entity together, without boxing each one.
+/// Every component is a bundle, so are tuples of bundles, and `#[derive(Bundle)]` from
+/// `jaren_ecs_derive` makes one of a struct whose fields are bundles:
+///
+/// ```
+/// # use jaren_ecs::system::World;
+/// # use jaren_ecs_derive::{Bundle, Component};
+/// #[derive(Component)]
+/// struct Position(f32, f32);
+/// #[derive(Component)]
+/// struct Velocity(f32, f32);
+/// #[derive(Component)]
+/// struct Health(u32);
+///
+/// #[derive(Bundle)]
+/// struct Body {
+///     position: Position,
+///     velocity: Velocity,
+/// }
+///
+/// #[derive(Bundle)]
+/// struct Enemy {
+///     body: Body,
+///     health: Health,
+/// }
+///
+/// let mut world = World::new();
+/// let body = Body { position: Position(0.0, 0.0), velocity: Velocity(1.0, 0.0) };
+/// let enemy = world.spawn(Enemy { body, health: Health(3) });
+/// let body = world.remove_bundle::<Body>(enemy).unwrap();
+/// assert_eq!(body.velocity.0, 1.0);
+/// assert_eq!(world.query::<Health>().iter().count(), 1);
+/// ```
+pub trait Bundle: Send + Sync + 'static {
+    /// Append the type ids of the components, and how they are stored, to `types`.
+    fn component_types(types: &mut Vec<(TypeId, StorageType)>);
+    /// Add a column for each table-stored component to `archetype`, if it has none yet.
+    fn add_columns(archetype: &mut Archetype);
+    /// Give `entity`, in the last row of `archetype`, the components: push each onto its
+    /// column or insert it into its sparse set, and push its `on_add` hook onto `hooks`.
+    fn push(
+        self,
+        entity: Entity,
+        archetype: &mut Archetype,
+        sparse: &mut SparseSets,
+        hooks: &mut Vec<ComponentHook>,
+    );
+    /// Take the components of `entity`, at `row` of `archetype`, out of their columns and
+    /// sparse sets. Columns have their last row moved into its place.
+    fn take(entity: Entity, row: usize, archetype: &mut Archetype, sparse: &mut SparseSets)
+    -> Self;
+}
+
+impl<C: Component> Bundle for C {
+    fn component_types(types: &mut Vec<(TypeId, StorageType)>) {
+        types.push((TypeId::of::<C>(), C::storage()));
+    }
+
+    fn add_columns(archetype: &mut Archetype) {
+        if C::storage() == StorageType::Table {
+            archetype
+                .components
+                .entry(TypeId::of::<C>())
+                .or_insert_with(|| Box::new(Vec::<C>::new()));
+        }
+    }
+
+    fn push(
+        self,
+        entity: Entity,
+        archetype: &mut Archetype,
+        sparse: &mut SparseSets,
+        hooks: &mut Vec<ComponentHook>,
+    ) {
+        hooks.extend(self.on_add());
+        match C::storage() {
+            StorageType::Table => archetype
+                .column_mut::<C>()
+                .expect("archetype has no column for bundle component")
+                .push(self),
+            StorageType::SparseSet => {
+                sparse.get_or_default::<C>().insert(entity, self);
+            }
+        }
+    }
+
+    fn take(
+        entity: Entity,
+        row: usize,
+        archetype: &mut Archetype,
+        sparse: &mut SparseSets,
+    ) -> Self {
+        match C::storage() {
+            StorageType::Table => archetype
+                .column_mut::<C>()
+                .expect("archetype has no column for bundle component")
+                .swap_remove(row),
+            StorageType::SparseSet => sparse
+                .get_mut::<C>()
+                .and_then(|set| set.remove(entity))
+                .expect("entity has no bundle component"),
+        }
+    }
+}
+
+macro_rules! impl_bundle_tuple {
+    ($($name:ident),*) => {
+        #[allow(non_snake_case, unused_variables)]
+        impl<$($name: Bundle),*> Bundle for ($($name,)*) {
+            fn component_types(types: &mut Vec<(TypeId, StorageType)>) {
+                $($name::component_types(types);)*
+            }
+
+            fn add_columns(archetype: &mut Archetype) {
+                $($name::add_columns(archetype);)*
+            }
+
+            fn push(self, entity: Entity, archetype: &mut Archetype, sparse: &mut SparseSets, hooks: &mut Vec<ComponentHook>) {
+                let ($($name,)*) = self;
+                $($name.push(entity, archetype, sparse, hooks);)*
+            }
+
+            #[allow(clippy::unused_unit)]
+            fn take(entity: Entity, row: usize, archetype: &mut Archetype, sparse: &mut SparseSets) -> Self {
+                ($($name::take(entity, row, archetype, sparse),)*)
+            }
+        }
+    };
 }
 
-#[derive(Default)]
+impl_bundle_tuple!();
+impl_bundle_tuple!(B1);
+impl_bundle_tuple!(B1, B2);
+impl_bundle_tuple!(B1, B2, B3);
+impl_bundle_tuple!(B1, B2, B3, B4);
+impl_bundle_tuple!(B1, B2, B3, B4, B5);
+impl_bundle_tuple!(B1, B2, B3, B4, B5, B6);
+impl_bundle_tuple!(B1, B2, B3, B4, B5, B6, B7);
+impl_bundle_tuple!(B1, B2, B3, B4, B5, B6, B7, B8);
+impl_bundle_tuple!(B1, B2, B3, B4, B5, B6, B7, B8, B9);
+impl_bundle_tuple!(B1, B2, B3, B4, B5, B6, B7, B8, B9, B10);
+impl_bundle_tuple!(B1, B2, B3, B4, B5, B6, B7, B8, B9, B10, B11);
+impl_bundle_tuple!(B1, B2, B3, B4, B5, B6, B7, B8, B9, B10, B11, B12);
+
 pub struct World {
+    pub(crate) id: WorldId,
     next_entity: Entity,
-    archetypes: Vec<Archetype>,
+    // Only ever appended to, so archetype indices stay valid
+    pub(crate) archetypes: Vec<Archetype>,
+    // Archetype indices by their sorted component types.
+    archetype_ids: HashMap<Box<[TypeId]>, usize>,
+    // The archetype and row of every entity with components.
+    locations: HashMap<Entity, (usize, usize)>,
+    // Components with `StorageType::SparseSet`, which are not part of any archetype.
+    pub(crate) sparse_sets: SparseSets,
+    // Singletons that are not attached to an entity, e.g. the asset server.
+    resources: HashMap<TypeId, Box<dyn Any + Send + Sync>>,
+    // Changes queued by `Commands`, applied after their system has run.
+    pub(crate) commands: Vec<Command>,
+}
+
+/// Tells worlds apart, so state kept for one is not used with another.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct WorldId(u64);
+
+impl Default for World {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
+/// A change to the world queued by [`Commands`](crate::param::Commands). `Send + Sync` like
+/// resources, so a world with queued commands can still be shared between threads.
+pub type Command = Box<dyn FnOnce(&mut World) + Send + Sync>;
+
 impl World {
     pub fn new() -> Self {
+        static NEXT_ID: std::sync::atomic::AtomicU64 = std::sync::atomic::AtomicU64::new(0);
         Self {
+            id: WorldId(NEXT_ID.fetch_add(1, std::sync::atomic::Ordering::Relaxed)),
             next_entity: 0,
             archetypes: Vec::new(),
+            archetype_ids: HashMap::new(),
+            locations: HashMap::new(),
+            sparse_sets: SparseSets::default(),
+            resources: HashMap::new(),
+            commands: Vec::new(),
+        }
+    }
+
+    /// Apply the changes queued by [`Commands`](crate::param::Commands), in order.
+    pub fn apply_commands(&mut self) {
+        // Commands may queue more commands
+        while !self.commands.is_empty() {
+            for command in std::mem::take(&mut self.commands) {
+                command(self);
+            }
         }
     }
+
+    /// Store a resource, replacing any previous value of the same type. Resources are
+    /// `Send + Sync` so the world can be shared between threads.
+    pub fn insert_resource<R: Send + Sync + 'static>(&mut self, resource: R) {
+        self.resources.insert(TypeId::of::<R>(), Box::new(resource));
+    }
+
+    pub fn remove_resource<R: Send + Sync + 'static>(&mut self) -> Option<R> {
+        self.resources
+            .remove(&TypeId::of::<R>())
+            .and_then(|r| r.downcast::<R>().ok())
+            .map(|r| *r)
+    }
+
+    pub fn resource<R: Send + Sync + 'static>(&self) -> Option<&R> {
+        self.resources.get(&TypeId::of::<R>())?.downcast_ref::<R>()
+    }
+
+    pub fn resource_mut<R: Send + Sync + 'static>(&mut self) -> Option<&mut R> {
+        self.resources
+            .get_mut(&TypeId::of::<R>())?
+            .downcast_mut::<R>()
+    }
+
+    /// Take a resource out for the duration of `f`, so it can be used alongside mutable
+    /// access to the rest of the world. Returns `None` if the resource does not exist.
+    pub fn resource_scope<R: Send + Sync + 'static, U>(
+        &mut self,
+        f: impl FnOnce(&mut World, &mut R) -> U,
+    ) -> Option<U> {
+        let mut resource = self.remove_resource::<R>()?;
+        let result = f(self, &mut resource);
+        self.insert_resource(resource);
+        Some(result)
+    }
 }
 
+// An archetype's columns by component type.
+pub(crate) type Columns = HashMap<TypeId, Box<dyn Column>>;
+
 pub struct Archetype {
-    // components are a vec of anything that implements component
-    components: HashMap<TypeId, Vec<Box<dyn Component>>>,
-    entities: Vec<Entity>,
+    // one column per table-stored component type, each holding a row per entity
+    pub(crate) components: Columns,
+    pub(crate) entities: Vec<Entity>,
+}
+
+impl Default for Archetype {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Archetype {
@@ -35,17 +372,76 @@ impl Archetype {
             entities: Vec::new(),
         }
     }
+
+    pub(crate) fn column<T: Component>(&self) -> Option<&Vec<T>> {
+        (self.components.get(&TypeId::of::<T>())?.as_ref() as &dyn Any).downcast_ref()
+    }
+
+    pub(crate) fn column_mut<T: Component>(&mut self) -> Option<&mut Vec<T>> {
+        (self.components.get_mut(&TypeId::of::<T>())?.as_mut() as &mut dyn Any).downcast_mut()
+    }
+
+    /// Whether this archetype has a column for each of `types`.
+    pub(crate) fn contains_all(&self, types: &[TypeId]) -> bool {
+        types.iter().all(|t| self.components.contains_key(t))
+    }
 }
 
 pub trait SystemFn<World> {
     fn run(&mut self, world: &mut World);
+
+    fn name(&self) -> &'static str {
+        std::any::type_name::<Self>()
+    }
+
+    /// What the system reads and writes, for [`Scheduler::conflicts`].
+    fn access(&self) -> Access {
+        Access::default()
+    }
 }
 
+/// A function taking one [`SystemParam`] as a system. To take several, derive
+/// `SystemParam` for a struct of them.
 pub struct System<F, A> {
     func: F,
+    access: Access,
     _marker: std::marker::PhantomData<A>,
 }
 
+impl<F, A> System<F, A>
+where
+    // `FnMut(A)` lets `A` be inferred from the function's argument
+    for<'a> F: FnMut(<A as SystemParam>::Param<'a>) + FnMut(A) + 'static,
+    A: SystemParam,
+{
+    /// # Panics
+    /// If the parameter borrows something mutably that it also borrows elsewhere.
+    pub fn new(func: F) -> Self {
+        let mut access = Access::default();
+        A::access(&mut access);
+        Self {
+            func,
+            access,
+            _marker: std::marker::PhantomData,
+        }
+    }
+}
+
+// Puts back what a parameter took out of the world when dropped, so the world is whole
+// again even if the system panics.
+struct Restore<'w, A: SystemParam> {
+    world: &'w mut World,
+    taken: Option<A::Taken>,
+}
+
+impl<A: SystemParam> Drop for Restore<'_, A> {
+    fn drop(&mut self) {
+        if let Some(taken) = self.taken.take() {
+            A::restore(self.world, taken);
+        }
+    }
+}
+
 // Implement for function pointer types
 impl<F, A> SystemFn<World> for System<F, A>
 where
@@ -53,23 +449,65 @@ where
     A: SystemParam,
 {
     fn run(&mut self, world: &mut World) {
-        let param = A::fetch(world);
-        (self.func)(param);
+        let mut guard = Restore::<A> {
+            taken: Some(A::take(world)),
+            world: &mut *world,
+        };
+        let taken = guard.taken.as_mut().expect("taken until the guard drops");
+        (self.func)(A::fetch(guard.world, taken));
+        drop(guard);
+        world.apply_commands();
+    }
+
+    fn name(&self) -> &'static str {
+        std::any::type_name::<F>()
+    }
+
+    fn access(&self) -> Access {
+        self.access.clone()
     }
 }
 
-// SystemParam trait for argument extraction
+/// Something a system takes as its argument. Derive it with `#[derive(SystemParam)]` from
+/// `jaren_ecs_derive` for a struct of other parameters.
+///
+/// Parameters that write are handed what they write on their own: [`take`](Self::take)
+/// moves it out of the world before the system runs and [`restore`](Self::restore) puts
+/// it back after, so the rest of the world can be shared meanwhile. [`access`](Self::access)
+/// makes sure nothing is read and written at once.
 pub trait SystemParam {
     type Param<'a>;
-    fn fetch<'a>(world: &'a mut World) -> Self::Param<'a>;
+    /// What [`take`](Self::take) moves out of the world.
+    type Taken;
+    /// Record what the parameter reads and writes.
+    fn access(access: &mut Access);
+    fn take(world: &mut World) -> Self::Taken;
+    fn fetch<'a>(world: &'a World, taken: &'a mut Self::Taken) -> Self::Param<'a>;
+    fn restore(world: &mut World, taken: Self::Taken);
 }
 
 pub struct Scheduler {
     systems: Vec<Box<dyn SystemFn<World>>>,
 }
 
+impl Default for Scheduler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Two systems that touch the same data, one of them writing it, so the order they run
+/// in matters.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SystemConflict {
+    pub first: &'static str,
+    pub second: &'static str,
+    /// Names of the component and resource types both use.
+    pub types: Vec<&'static str>,
+}
+
 // Scheduler is the driver for the ECS. It is responsible for running systems.
-// Need to implement something to prevent the edge case of functions being run out of order causing unexpected behavior.
+// Systems run in the order they were added; `conflicts` lists the pairs that depend on it.
 impl Scheduler {
     pub fn new() -> Self {
         Self {
@@ -84,43 +522,242 @@ impl Scheduler {
             system.run(world);
         }
     }
+
+    /// Pairs of systems whose results depend on which runs first, in the order they were
+    /// added.
+    pub fn conflicts(&self) -> Vec<SystemConflict> {
+        let access: Vec<Access> = self.systems.iter().map(|system| system.access()).collect();
+        let mut conflicts = Vec::new();
+        for (i, first) in access.iter().enumerate() {
+            for (j, second) in access.iter().enumerate().skip(i + 1) {
+                let types = first.conflicts(second);
+                if !types.is_empty() {
+                    conflicts.push(SystemConflict {
+                        first: self.systems[i].name(),
+                        second: self.systems[j].name(),
+                        types,
+                    });
+                }
+            }
+        }
+        conflicts
+    }
 }
 
+/// Every entity with `T`, found by walking all archetypes on each call. Keep a
+/// [`QueryState`](crate::query::QueryState) for queries run every frame.
 pub struct Query<'a, T> {
     world: &'a World,
     _marker: std::marker::PhantomData<T>,
 }
 
 pub struct QueryMut<'a, T> {
-    world: &'a mut World,
+    // Entities and columns of the archetypes to walk, by archetype
+    tables: Vec<(&'a [Entity], &'a mut Columns)>,
+    sparse_sets: &'a mut SparseSets,
     _marker: std::marker::PhantomData<T>,
 }
 
+/// The columns and sparse sets a [`QueryMut`] parameter writes, moved out of the world
+/// while its system runs.
+pub struct TakenColumns {
+    // Archetype indices and the columns taken from them
+    tables: Vec<(usize, Columns)>,
+    sparse_sets: SparseSets,
+}
+
+impl TakenColumns {
+    // Query the taken columns, with the entities of `world`'s archetypes.
+    fn query_mut<'a, T>(&'a mut self, world: &'a World) -> QueryMut<'a, T> {
+        let tables = self
+            .tables
+            .iter_mut()
+            .map(|(index, columns)| (world.archetypes[*index].entities.as_slice(), columns))
+            .collect();
+        QueryMut {
+            tables,
+            sparse_sets: &mut self.sparse_sets,
+            _marker: std::marker::PhantomData,
+        }
+    }
+}
+
+// Entities with `T` in the archetypes at `archetypes`, then in the sparse set of `T`.
+// Shared by `Query` and `QueryState`.
+pub(crate) fn iter_archetypes<T: Component>(
+    world: &World,
+    archetypes: impl Iterator<Item = usize>,
+) -> impl Iterator<Item = (Entity, &T)> {
+    // T is either in columns or in a sparse set, the other side yields nothing. The walk
+    // over the archetypes ends before the first one if T is in a sparse set.
+    let table = T::storage() == StorageType::Table;
+    let tables = archetypes
+        .take_while(move |_| table)
+        .flat_map(move |index| {
+            let archetype = &world.archetypes[index];
+            // Archetypes without T have no column and yield nothing
+            let column = archetype.column::<T>().map_or(&[][..], Vec::as_slice);
+            archetype.entities.iter().copied().zip(column)
+        });
+    tables.chain(
+        world
+            .sparse_sets
+            .get::<T>()
+            .into_iter()
+            .flat_map(SparseSet::iter),
+    )
+}
+
 /// Implement Query for single component queries
 impl<'a, T: Component> Query<'a, T> {
     pub fn iter(&self) -> impl Iterator<Item = (Entity, &T)> {
-        self.world.archetypes.iter().flat_map(|archetype| {
-            // Get the Vec for T in this archetype
-            let vec = match archetype.components.get(&std::any::TypeId::of::<T>()) {
-                Some(vec) => vec,
-                None => return Vec::new().into_iter(),
-            };
-            // Iterate by index, matching with entities
-            (0..archetype.entities.len()).filter_map(move |i| {
-                let t_ref = vec[i].as_any().downcast_ref::<T>()?;
-                Some((archetype.entities[i], t_ref))
-            }).collect::<Vec<_>>().into_iter()
-        })
+        iter_archetypes(self.world, 0..self.world.archetypes.len())
     }
 }
 
 impl<'a, T: Component> QueryMut<'a, T> {
     pub fn for_each_mut<F: FnMut(Entity, &mut T)>(&mut self, mut f: F) {
-        for archetype in self.world.archetypes.iter_mut() {
-            if let Some(vec) = archetype.components.get_mut(&std::any::TypeId::of::<T>()) {
-                for i in 0..archetype.entities.len() {
-                    if let Some(t_ref) = vec[i].as_any_mut().downcast_mut::<T>() {
-                        f(archetype.entities[i], t_ref);
+        for (entities, components) in &mut self.tables {
+            if let Some(column) = components.get_mut(&TypeId::of::<T>()) {
+                let column = (column.as_mut() as &mut dyn Any)
+                    .downcast_mut::<Vec<T>>()
+                    .unwrap();
+                for (entity, component) in entities.iter().zip(column) {
+                    f(*entity, component);
+                }
+            }
+        }
+        if let Some(set) = self.sparse_sets.get_mut::<T>() {
+            for (entity, component) in set.iter_mut() {
+                f(entity, component);
+            }
+        }
+    }
+}
+
+// Entities with the table-stored `T` and the sparse-set-stored `S`, found by walking the
+// tables and looking each entity up in the set.
+fn join_table_sparse<T: Component, S: Component>(
+    world: &World,
+    archetypes: impl Iterator<Item = usize>,
+) -> impl Iterator<Item = (Entity, &T, &S)> {
+    let set = world.sparse_sets.get::<S>();
+    archetypes
+        .take_while(move |_| set.is_some())
+        .flat_map(move |index| {
+            let archetype = &world.archetypes[index];
+            let column = archetype.column::<T>().map_or(&[][..], Vec::as_slice);
+            archetype.entities.iter().copied().zip(column)
+        })
+        .filter_map(move |(entity, t)| Some((entity, t, set?.get(entity)?)))
+}
+
+// Entities with both `A` and `B`, in the archetypes at `archetypes` or in sparse sets.
+// Shared by `Query` and `QueryState`.
+pub(crate) fn iter_archetypes_pair<'s, 'w: 's, A: Component, B: Component>(
+    world: &'w World,
+    archetypes: impl Iterator<Item = usize> + 's,
+) -> Box<dyn Iterator<Item = (Entity, (&'w A, &'w B))> + 's> {
+    match (A::storage(), B::storage()) {
+        (StorageType::Table, StorageType::Table) => {
+            Box::new(archetypes.flat_map(move |index| {
+                let archetype = &world.archetypes[index];
+                // Archetypes missing either column yield nothing
+                let (a, b) = match (archetype.column::<A>(), archetype.column::<B>()) {
+                    (Some(a), Some(b)) => (a.as_slice(), b.as_slice()),
+                    _ => (&[][..], &[][..]),
+                };
+                archetype.entities.iter().copied().zip(a.iter().zip(b))
+            }))
+        }
+        (StorageType::Table, StorageType::SparseSet) => Box::new(
+            join_table_sparse::<A, B>(world, archetypes).map(|(entity, a, b)| (entity, (a, b))),
+        ),
+        (StorageType::SparseSet, StorageType::Table) => Box::new(
+            join_table_sparse::<B, A>(world, archetypes).map(|(entity, b, a)| (entity, (a, b))),
+        ),
+        (StorageType::SparseSet, StorageType::SparseSet) => {
+            let b = world.sparse_sets.get::<B>();
+            Box::new(
+                world
+                    .sparse_sets
+                    .get::<A>()
+                    .into_iter()
+                    .flat_map(SparseSet::iter)
+                    .filter_map(move |(entity, a)| Some((entity, (a, b?.get(entity)?)))),
+            )
+        }
+    }
+}
+
+fn join_table_sparse_mut<T: Component, S: Component>(
+    tables: &mut [(&[Entity], &mut Columns)],
+    sparse_sets: &mut SparseSets,
+    mut f: impl FnMut(Entity, &mut T, &mut S),
+) {
+    let Some(set) = sparse_sets.get_mut::<S>() else {
+        return;
+    };
+    for (entities, components) in tables {
+        if let Some(column) = components.get_mut(&TypeId::of::<T>()) {
+            let column = (column.as_mut() as &mut dyn Any)
+                .downcast_mut::<Vec<T>>()
+                .unwrap();
+            for (entity, t) in entities.iter().zip(column) {
+                if let Some(s) = set.get_mut(*entity) {
+                    f(*entity, t, s);
+                }
+            }
+        }
+    }
+}
+
+impl<'a, A: Component, B: Component> QueryMut<'a, (A, B)> {
+    /// `A` and `B` must be different types.
+    pub fn for_each_mut<F: FnMut(Entity, (&mut A, &mut B))>(&mut self, mut f: F) {
+        assert_ne!(
+            TypeId::of::<A>(),
+            TypeId::of::<B>(),
+            "QueryMut cannot borrow the same component twice"
+        );
+        match (A::storage(), B::storage()) {
+            (StorageType::Table, StorageType::Table) => {
+                for (entities, components) in &mut self.tables {
+                    if let [Some(a), Some(b)] =
+                        components.get_disjoint_mut([&TypeId::of::<A>(), &TypeId::of::<B>()])
+                    {
+                        let a = (a.as_mut() as &mut dyn Any)
+                            .downcast_mut::<Vec<A>>()
+                            .unwrap();
+                        let b = (b.as_mut() as &mut dyn Any)
+                            .downcast_mut::<Vec<B>>()
+                            .unwrap();
+                        for ((entity, a), b) in entities.iter().zip(a).zip(b) {
+                            f(*entity, (a, b));
+                        }
+                    }
+                }
+            }
+            (StorageType::Table, StorageType::SparseSet) => {
+                join_table_sparse_mut::<A, B>(
+                    &mut self.tables,
+                    self.sparse_sets,
+                    |entity, a, b| f(entity, (a, b)),
+                );
+            }
+            (StorageType::SparseSet, StorageType::Table) => {
+                join_table_sparse_mut::<B, A>(
+                    &mut self.tables,
+                    self.sparse_sets,
+                    |entity, b, a| f(entity, (a, b)),
+                );
+            }
+            (StorageType::SparseSet, StorageType::SparseSet) => {
+                if let Some((a_set, b_set)) = self.sparse_sets.get_pair_mut::<A, B>() {
+                    for (entity, a) in a_set.iter_mut() {
+                        if let Some(b) = b_set.get_mut(entity) {
+                            f(entity, (a, b));
+                        }
                     }
                 }
             }
@@ -131,27 +768,10 @@ impl<'a, T: Component> QueryMut<'a, T> {
 /// Implement Query for tuple component queries. Max size of 2.
 macro_rules! impl_query_iter_tuple {
     ($a:ident, $b:ident) => {
+        #[allow(non_snake_case)]
         impl<'a, $a: Component, $b: Component> Query<'a, ($a, $b)> {
             pub fn iter(&self) -> Box<dyn Iterator<Item = (Entity, (&$a, &$b))> + '_> {
-                Box::new(self.world.archetypes.iter().flat_map(|archetype| {
-                    let $a = match archetype.components.get(&std::any::TypeId::of::<$a>()) {
-                        Some(vec) => vec,
-                        None => return Box::new(std::iter::empty()) as Box<dyn Iterator<Item = (Entity, (&$a, &$b))> + '_>,
-                    };
-                    let $b = match archetype.components.get(&std::any::TypeId::of::<$b>()) {
-                        Some(vec) => vec,
-                        None => return Box::new(std::iter::empty()) as Box<dyn Iterator<Item = (Entity, (&$a, &$b))> + '_>,
-                    };
-                    Box::new((0..archetype.entities.len()).filter_map(move |i| {
-                        Some((
-                            archetype.entities[i],
-                            (
-                                $a[i].as_any().downcast_ref::<$a>()?,
-                                $b[i].as_any().downcast_ref::<$b>()?,
-                            )
-                        ))
-                    })) as Box<dyn Iterator<Item = (Entity, (&$a, &$b))> + '_>
-                }))
+                iter_archetypes_pair(self.world, 0..self.world.archetypes.len())
             }
         }
     };
@@ -161,12 +781,15 @@ impl_query_iter_tuple!(T1, T2);
 // Implement SystemParam for queries, resources, etc.
 impl<T: Component> SystemParam for Query<'_, T> {
     type Param<'a> = Query<'a, T>;
-    fn fetch<'a>(world: &'a mut World) -> Query<'a, T> {
-        Query {
-            world,
-            _marker: std::marker::PhantomData,
-        }
+    type Taken = ();
+    fn access(access: &mut Access) {
+        access.read_component::<T>();
+    }
+    fn take(_world: &mut World) {}
+    fn fetch<'a>(world: &'a World, _taken: &'a mut ()) -> Query<'a, T> {
+        world.query()
     }
+    fn restore(_world: &mut World, _taken: ()) {}
 }
 
 /// Implement SystemParam for tuple queries
@@ -174,16 +797,59 @@ macro_rules! impl_system_param_for_query_tuple {
     ($( $name:ident ),*) => {
         impl<'a, $( $name: Component ),*> SystemParam for Query<'a, ( $( $name ),* )> {
             type Param<'b> = Query<'b, ( $( $name ),* )>;
-            fn fetch<'b>(world: &'b mut World) -> Query<'b, ( $( $name ),+ )> {
-                Query {
-                    world,
-                    _marker: std::marker::PhantomData,
-                }
+            type Taken = ();
+            fn access(access: &mut Access) {
+                $( access.read_component::<$name>(); )*
+            }
+            fn take(_world: &mut World) {}
+            fn fetch<'b>(world: &'b World, _taken: &'b mut ()) -> Query<'b, ( $( $name ),+ )> {
+                world.query()
             }
+            fn restore(_world: &mut World, _taken: ()) {}
         }
     }
 }
 
+// A mutable query works on the columns it writes, taken out of the world while its system
+// runs.
+impl<T: Component> SystemParam for QueryMut<'_, T> {
+    type Param<'a> = QueryMut<'a, T>;
+    type Taken = TakenColumns;
+    fn access(access: &mut Access) {
+        access.write_component::<T>();
+    }
+    fn take(world: &mut World) -> TakenColumns {
+        world.take_columns(&[(TypeId::of::<T>(), T::storage())])
+    }
+    fn fetch<'a>(world: &'a World, taken: &'a mut TakenColumns) -> QueryMut<'a, T> {
+        taken.query_mut(world)
+    }
+    fn restore(world: &mut World, taken: TakenColumns) {
+        world.restore_columns(taken);
+    }
+}
+
+impl<A: Component, B: Component> SystemParam for QueryMut<'_, (A, B)> {
+    type Param<'a> = QueryMut<'a, (A, B)>;
+    type Taken = TakenColumns;
+    fn access(access: &mut Access) {
+        access.write_component::<A>();
+        access.write_component::<B>();
+    }
+    fn take(world: &mut World) -> TakenColumns {
+        world.take_columns(&[
+            (TypeId::of::<A>(), A::storage()),
+            (TypeId::of::<B>(), B::storage()),
+        ])
+    }
+    fn fetch<'a>(world: &'a World, taken: &'a mut TakenColumns) -> QueryMut<'a, (A, B)> {
+        taken.query_mut(world)
+    }
+    fn restore(world: &mut World, taken: TakenColumns) {
+        world.restore_columns(taken);
+    }
+}
+
 impl_system_param_for_query_tuple!(T1, T2);
 impl_system_param_for_query_tuple!(T1, T2, T3);
 impl_system_param_for_query_tuple!(T1, T2, T3, T4);
@@ -197,56 +863,418 @@ impl_system_param_for_query_tuple!(T1, T2, T3, T4);
 /// 
 /// # Example
 /// ```
+/// # use jaren_ecs::{spawn, system::{Component, World}};
+/// # use jaren_ecs_derive::Component;
 /// #[derive(Component)]
 /// struct Position(f32, f32);
 /// 
+/// # let mut world = World::new();
 /// let entity = spawn!(world, Position(0.0, 0.0));
 /// ```
 macro_rules! spawn {
     ($world:expr, $($component:expr),*) => {{
-        let entity = $world.next_entity;
-        $world.next_entity = $world.next_entity.wrapping_add(1);
-        let components_vec: Vec<Box<dyn Component>> = vec![$(Box::new($component)),*];
-        $world.get_archetype(entity, components_vec);
-        entity
+        $world.spawn($crate::__bundle!($($component),*))
     }};
 }
 
+#[doc(hidden)]
+#[macro_export]
+// Bundles are implemented for tuples of up to 12, so longer lists end in a nested tuple
+// holding the rest.
+macro_rules! __bundle {
+    (
+        $c1:expr, $c2:expr, $c3:expr, $c4:expr, $c5:expr, $c6:expr,
+        $c7:expr, $c8:expr, $c9:expr, $c10:expr, $c11:expr, $($rest:expr),+
+    ) => {
+        ($c1, $c2, $c3, $c4, $c5, $c6, $c7, $c8, $c9, $c10, $c11, $crate::__bundle!($($rest),+))
+    };
+    ($($component:expr),*) => {
+        ($($component,)*)
+    };
+}
+
 impl World {
+    /// Read-only query over every entity that has `T`.
+    pub fn query<T>(&self) -> Query<'_, T> {
+        Query {
+            world: self,
+            _marker: std::marker::PhantomData,
+        }
+    }
+
+    /// Mutable query over every entity that has `T`.
+    pub fn query_mut<T>(&mut self) -> QueryMut<'_, T> {
+        self.query_mut_archetypes(0..self.archetypes.len())
+    }
+
+    // Mutable query over the archetypes at `archetypes`, which must be ascending.
+    pub(crate) fn query_mut_archetypes<T>(
+        &mut self,
+        archetypes: impl Iterator<Item = usize>,
+    ) -> QueryMut<'_, T> {
+        let mut rest = self.archetypes.as_mut_slice();
+        let mut offset = 0;
+        let tables = archetypes
+            .map(|index| {
+                let (archetype, tail) = std::mem::take(&mut rest)[index - offset..]
+                    .split_first_mut()
+                    .unwrap();
+                rest = tail;
+                offset = index + 1;
+                (archetype.entities.as_slice(), &mut archetype.components)
+            })
+            .collect();
+        QueryMut {
+            tables,
+            sparse_sets: &mut self.sparse_sets,
+            _marker: std::marker::PhantomData,
+        }
+    }
+
+    /// Reserve the next entity id.
+    pub fn alloc_entity(&mut self) -> Entity {
+        let entity = self.next_entity;
+        self.next_entity = self.next_entity.wrapping_add(1);
+        entity
+    }
+
+    /// The `T` of `entity`, looked up through its location instead of a query.
+    pub(crate) fn component<T: Component>(&self, entity: Entity) -> Option<&T> {
+        match T::storage() {
+            StorageType::Table => {
+                let (index, row) = self.locate(entity)?;
+                self.archetypes[index].column::<T>()?.as_slice().get(row)
+            }
+            StorageType::SparseSet => self.sparse_sets.get::<T>()?.get(entity),
+        }
+    }
+
+    /// Every component of `entity`, in no particular order. Empty if it does not exist.
+    pub fn components(&self, entity: Entity) -> Vec<&dyn Component> {
+        let Some((index, row)) = self.locate(entity) else {
+            return Vec::new();
+        };
+        let table = self.archetypes[index]
+            .components
+            .values()
+            .map(|column| column.get(row));
+        table.chain(self.sparse_sets.components(entity)).collect()
+    }
+
+    pub fn components_mut(&mut self, entity: Entity) -> Vec<&mut dyn Component> {
+        let Some((index, row)) = self.locate(entity) else {
+            return Vec::new();
+        };
+        let table = self.archetypes[index]
+            .components
+            .values_mut()
+            .map(|column| column.get_mut(row));
+        table
+            .chain(self.sparse_sets.components_mut(entity))
+            .collect()
+    }
+
     /// Find or create an archetype for a set of components.
     pub fn get_archetype(&mut self, entity: Entity, components: Vec<Box<dyn Component>>) {
-        use std::any::TypeId;
-        // Get the set of component types for this entity
-        let incoming_types: Vec<TypeId> = components.iter().map(|c| c.as_any().type_id()).collect();
-
-        // Find an archetype with exactly this set of component types
-        let archetype = self.archetypes.iter_mut().find(|archetype| {
-            let archetype_types: Vec<TypeId> = archetype.components.keys().cloned().collect();
-            incoming_types.len() == archetype_types.len()
-                && incoming_types.iter().all(|t| archetype_types.contains(t))
+        let hooks: Vec<ComponentHook> = components.iter().filter_map(|c| c.on_add()).collect();
+        let (table, sparse): (Vec<_>, Vec<_>) = components
+            .into_iter()
+            .partition(|c| c.storage_type() == StorageType::Table);
+        // Get the set of table-stored component types for this entity
+        let incoming_types: Vec<TypeId> = table.iter().map(|c| c.as_any().type_id()).collect();
+
+        let index = self.archetype_index(&incoming_types).unwrap_or_else(|| {
+            let mut archetype = Archetype::new();
+            for component in &table {
+                archetype
+                    .components
+                    .insert(component.as_any().type_id(), component.empty_column());
+            }
+            self.add_archetype(archetype)
+        });
+        let archetype = &mut self.archetypes[index];
+        archetype.entities.push(entity);
+        self.locations
+            .insert(entity, (index, archetype.entities.len() - 1));
+        for component in table {
+            let type_id = component.as_any().type_id();
+            archetype
+                .components
+                .get_mut(&type_id)
+                .unwrap()
+                .push_boxed(component);
+        }
+        for component in sparse {
+            self.sparse_sets.insert_boxed(entity, component);
+        }
+
+        for hook in hooks {
+            hook(self, entity);
+        }
+    }
+
+    /// Spawn an entity with the components of `bundle`.
+    ///
+    /// # Panics
+    /// If the bundle holds the same component type twice.
+    pub fn spawn<B: Bundle>(&mut self, bundle: B) -> Entity {
+        let entity = self.alloc_entity();
+        self.place_bundle(entity, bundle, &BundleTypes::of::<B>().table);
+        entity
+    }
+
+    // Add `entity`, which has no components yet, with the components of `bundle`, whose
+    // table-stored types are `table`.
+    fn place_bundle<B: Bundle>(&mut self, entity: Entity, bundle: B, table: &[TypeId]) {
+        let index = self.archetype_index(table).unwrap_or_else(|| {
+            let mut archetype = Archetype::new();
+            B::add_columns(&mut archetype);
+            self.add_archetype(archetype)
+        });
+        let mut hooks = Vec::new();
+        let archetype = &mut self.archetypes[index];
+        archetype.entities.push(entity);
+        self.locations
+            .insert(entity, (index, archetype.entities.len() - 1));
+        bundle.push(entity, archetype, &mut self.sparse_sets, &mut hooks);
+        for hook in hooks {
+            hook(self, entity);
+        }
+    }
+
+    /// Add the components of `bundle` to `entity`, replacing those it already has. Adding
+    /// only sparse-set stored components leaves the entity in its archetype.
+    ///
+    /// # Panics
+    /// If the bundle holds the same component type twice.
+    pub fn insert_bundle<B: Bundle>(&mut self, entity: Entity, bundle: B) {
+        let inserted = BundleTypes::of::<B>().table;
+        let Some((source, row)) = self.locate(entity) else {
+            // An entity without components is in no archetype yet
+            self.place_bundle(entity, bundle, &inserted);
+            return;
+        };
+        let mut hooks = Vec::new();
+        if inserted.is_empty() {
+            bundle.push(
+                entity,
+                &mut self.archetypes[source],
+                &mut self.sparse_sets,
+                &mut hooks,
+            );
+            for hook in hooks {
+                hook(self, entity);
+            }
+            return;
+        }
+
+        let kept: Vec<TypeId> = self.archetypes[source]
+            .components
+            .keys()
+            .copied()
+            .filter(|t| !inserted.contains(t))
+            .collect();
+        let mut types = kept.clone();
+        types.extend(&inserted);
+        let target = self.archetype_index(&types).unwrap_or_else(|| {
+            let mut archetype = Archetype::new();
+            for t in &kept {
+                archetype
+                    .components
+                    .insert(*t, self.archetypes[source].components[t].empty());
+            }
+            B::add_columns(&mut archetype);
+            self.add_archetype(archetype)
         });
 
-        if let Some(archetype) = archetype {
-            archetype.entities.push(entity);
-            for component in components {
-                let type_id = component.as_any().type_id();
-                archetype.components.entry(type_id).or_default().push(component);
+        if target == source {
+            // Every component is replaced: move the entity to the last row, drop the old
+            // values and push the new ones in their place
+            let archetype = &mut self.archetypes[source];
+            let last = archetype.entities.len() - 1;
+            archetype.entities.swap(row, last);
+            self.locations
+                .insert(archetype.entities[row], (source, row));
+            self.locations.insert(entity, (source, last));
+            for (t, column) in &mut archetype.components {
+                column.swap(row, last);
+                if inserted.contains(t) {
+                    column.swap_remove(last);
+                }
             }
         } else {
+            let [from, to] = self.archetypes.get_disjoint_mut([source, target]).unwrap();
+            for (t, column) in &mut from.components {
+                if inserted.contains(t) {
+                    column.swap_remove(row);
+                } else {
+                    column.move_row(row, to.components.get_mut(t).unwrap().as_mut());
+                }
+            }
+            from.entities.swap_remove(row);
+            to.entities.push(entity);
+            if let Some(moved) = from.entities.get(row) {
+                self.locations.insert(*moved, (source, row));
+            }
+            self.locations
+                .insert(entity, (target, to.entities.len() - 1));
+        }
+        bundle.push(
+            entity,
+            &mut self.archetypes[target],
+            &mut self.sparse_sets,
+            &mut hooks,
+        );
+        for hook in hooks {
+            hook(self, entity);
+        }
+    }
+
+    /// Take the components of `B` off `entity`. Returns `None`, and removes nothing, if
+    /// the entity is missing any of them. Removing only sparse-set stored components
+    /// leaves the entity in its archetype.
+    ///
+    /// # Panics
+    /// If the bundle holds the same component type twice.
+    pub fn remove_bundle<B: Bundle>(&mut self, entity: Entity) -> Option<B> {
+        let BundleTypes {
+            table: removed,
+            sparse,
+        } = BundleTypes::of::<B>();
+        let (source, row) = self.locate(entity)?;
+        let components = &self.archetypes[source].components;
+        if !removed.iter().all(|t| components.contains_key(t))
+            || !sparse.iter().all(|t| self.sparse_sets.contains(*t, entity))
+        {
+            return None;
+        }
+        if removed.is_empty() {
+            return Some(B::take(
+                entity,
+                row,
+                &mut self.archetypes[source],
+                &mut self.sparse_sets,
+            ));
+        }
+        let kept: Vec<TypeId> = components
+            .keys()
+            .copied()
+            .filter(|t| !removed.contains(t))
+            .collect();
+        let target = self.archetype_index(&kept).unwrap_or_else(|| {
             let mut archetype = Archetype::new();
-            archetype.entities.push(entity);
-            for component in components {
-                let type_id = component.as_any().type_id();
-                archetype.components.entry(type_id).or_default().push(component);
+            for t in &kept {
+                archetype
+                    .components
+                    .insert(*t, self.archetypes[source].components[t].empty());
+            }
+            self.add_archetype(archetype)
+        });
+        let [from, to] = self.archetypes.get_disjoint_mut([source, target]).unwrap();
+        for (t, column) in &mut to.components {
+            from.components
+                .get_mut(t)
+                .unwrap()
+                .move_row(row, column.as_mut());
+        }
+        from.entities.swap_remove(row);
+        to.entities.push(entity);
+        if let Some(moved) = from.entities.get(row) {
+            self.locations.insert(*moved, (source, row));
+        }
+        self.locations
+            .insert(entity, (target, to.entities.len() - 1));
+        Some(B::take(entity, row, from, &mut self.sparse_sets))
+    }
+
+    // Move out the columns and sparse sets of `types`. Columns are taken from every
+    // archetype holding all the table-stored types.
+    fn take_columns(&mut self, types: &[(TypeId, StorageType)]) -> TakenColumns {
+        let (table, sparse): (Vec<_>, Vec<_>) = types
+            .iter()
+            .partition(|(_, storage)| *storage == StorageType::Table);
+        let table: Vec<TypeId> = table.iter().map(|(t, _)| *t).collect();
+        let sparse: Vec<TypeId> = sparse.iter().map(|(t, _)| *t).collect();
+        let mut tables = Vec::new();
+        if !table.is_empty() {
+            for (index, archetype) in self.archetypes.iter_mut().enumerate() {
+                if archetype.contains_all(&table) {
+                    let columns = table
+                        .iter()
+                        .map(|t| (*t, archetype.components.remove(t).unwrap()))
+                        .collect();
+                    tables.push((index, columns));
+                }
+            }
+        }
+        TakenColumns {
+            tables,
+            sparse_sets: self.sparse_sets.take(&sparse),
+        }
+    }
+
+    fn restore_columns(&mut self, taken: TakenColumns) {
+        for (index, columns) in taken.tables {
+            self.archetypes[index].components.extend(columns);
+        }
+        self.sparse_sets.restore(taken.sparse_sets);
+    }
+
+    /// The archetype holding exactly these table-stored component types.
+    fn archetype_index(&self, types: &[TypeId]) -> Option<usize> {
+        let mut signature = types.to_vec();
+        signature.sort_unstable();
+        self.archetype_ids.get(signature.as_slice()).copied()
+    }
+
+    fn add_archetype(&mut self, archetype: Archetype) -> usize {
+        let mut signature: Vec<TypeId> = archetype.components.keys().copied().collect();
+        signature.sort_unstable();
+        let index = self.archetypes.len();
+        self.archetype_ids.insert(signature.into(), index);
+        self.archetypes.push(archetype);
+        index
+    }
+
+    /// The archetype and row of `entity`.
+    fn locate(&self, entity: Entity) -> Option<(usize, usize)> {
+        self.locations.get(&entity).copied()
+    }
+}
+
+// The component types of a bundle, by storage.
+struct BundleTypes {
+    table: Vec<TypeId>,
+    sparse: Vec<TypeId>,
+}
+
+impl BundleTypes {
+    fn of<B: Bundle>() -> Self {
+        let mut types = Vec::new();
+        B::component_types(&mut types);
+        for (i, (t, _)) in types.iter().enumerate() {
+            assert!(
+                !types[..i].iter().any(|(other, _)| other == t),
+                "bundle holds the same component twice"
+            );
+        }
+        let mut bundle = Self {
+            table: Vec::new(),
+            sparse: Vec::new(),
+        };
+        for (t, storage) in types {
+            match storage {
+                StorageType::Table => bundle.table.push(t),
+                StorageType::SparseSet => bundle.sparse.push(t),
             }
-            self.archetypes.push(archetype);
         }
+        bundle
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use jaren_ecs_derive::Component;
+    use jaren_ecs_derive::{Bundle, Component};
 
     use super::*;
 
@@ -270,7 +1298,7 @@ mod tests {
         let mut world = World::new();
         let entity = spawn!(world, Position(0.0, 0.0));
         let entity2 = spawn!(world, Position(1.0, 0.0));
-        let query = Query::<Position> { world: &world, _marker: std::marker::PhantomData };
+        let query = world.query::<Position>();
         let results = query.iter().collect::<Vec<_>>();
         assert_eq!(results[0].0, entity);
         assert_eq!(results[1].0, entity2);
@@ -279,8 +1307,8 @@ mod tests {
     fn test_query_tuple() {
         let mut world = World::new();
         let entity = spawn!(world, Position(0.0, 0.0), Player);
-        let entity2 = spawn!(world, Position(1.0, 0.0));
-        let query = Query::<(Position, Player)> { world: &world, _marker: std::marker::PhantomData };
+        let _entity2 = spawn!(world, Position(1.0, 0.0));
+        let query = world.query::<(Position, Player)>();
         let results = query.iter().collect::<Vec<_>>();
         assert_eq!(results[0].0, entity);
         assert_eq!(results[0].0, entity);
@@ -289,32 +1317,193 @@ mod tests {
     #[test]
     fn test_query_mut() {
         let mut world = World::new();
-        let entity = spawn!(world, Position(0.0, 0.0));
-        let mut query = QueryMut::<Position> { world: &mut world, _marker: std::marker::PhantomData };
-        query.for_each_mut(|entity, mut position| {
+        let _entity = spawn!(world, Position(0.0, 0.0));
+        let mut query = world.query_mut::<Position>();
+        query.for_each_mut(|_entity, position| {
             position.0 += 1.0;
             position.1 += 2.0;
         });
 
-        let query = Query::<Position> { world: &world, _marker: std::marker::PhantomData };
+        let query = world.query::<Position>();
         let results = query.iter().collect::<Vec<_>>();
         assert_eq!(results[0].1.0, 1.0);
         assert_eq!(results[0].1.1, 2.0);
     }
 
+    #[derive(Component, PartialEq, Debug, Clone, Copy)]
+    struct Velocity(f32, f32);
+
+    #[test]
+    fn test_query_mut_pair() {
+        let mut world = World::new();
+        spawn!(world, Position(0.0, 0.0), Velocity(1.0, 2.0));
+        spawn!(world, Position(5.0, 5.0));
+
+        world
+            .query_mut::<(Position, Velocity)>()
+            .for_each_mut(|_entity, (pos, vel)| {
+                pos.0 += vel.0;
+                pos.1 += vel.1;
+                vel.0 = 0.0;
+            });
+
+        let positions: Vec<_> = world.query::<Position>().iter().map(|(_, p)| *p).collect();
+        assert!(positions.contains(&Position(1.0, 2.0)));
+        assert!(positions.contains(&Position(5.0, 5.0)));
+        let velocities: Vec<_> = world.query::<Velocity>().iter().map(|(_, v)| *v).collect();
+        assert_eq!(velocities, vec![Velocity(0.0, 2.0)]);
+    }
+
+    fn move_then_panic(mut moving: QueryMut<(Position, Velocity)>) {
+        moving.for_each_mut(|_, (pos, vel)| pos.0 += vel.0);
+        panic!("system failed");
+    }
+
+    #[test]
+    fn test_columns_restored_when_system_panics() {
+        let mut world = World::new();
+        let entity = spawn!(world, Position(0.0, 0.0), Velocity(1.0, 2.0));
+        let mut scheduler = Scheduler::new();
+        scheduler.add_system(System::new(move_then_panic));
+        let run =
+            std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| scheduler.run(&mut world)));
+        assert!(run.is_err());
+
+        assert_eq!(world.components(entity).len(), 2);
+        let positions: Vec<_> = world.query::<Position>().iter().map(|(_, p)| *p).collect();
+        assert_eq!(positions, vec![Position(1.0, 0.0)]);
+    }
+
+    #[derive(Component)]
+    #[component(storage = "sparse_set", on_add = count_added)]
+    struct Tag<T>(std::marker::PhantomData<fn() -> T>)
+    where
+        T: 'static;
+
+    #[derive(Default)]
+    struct Added(Vec<Entity>);
+
+    fn count_added(world: &mut World, entity: Entity) {
+        world.resource_mut::<Added>().unwrap().0.push(entity);
+    }
+
+    #[test]
+    fn test_component_attributes() {
+        let mut world = World::new();
+        world.insert_resource(Added::default());
+        let tagged = spawn!(
+            world,
+            Tag::<Player>(std::marker::PhantomData),
+            Position(0.0, 0.0)
+        );
+        spawn!(world, Position(1.0, 1.0));
+
+        assert_eq!(world.resource::<Added>().unwrap().0, vec![tagged]);
+        let tag = Tag::<Velocity>(std::marker::PhantomData);
+        assert_eq!(tag.storage_type(), StorageType::SparseSet);
+        assert_eq!(Position(0.0, 0.0).storage_type(), StorageType::Table);
+    }
+
+    #[derive(Bundle)]
+    struct Mover(Position, Velocity);
+
+    #[test]
+    fn test_insert_and_remove_bundles() {
+        let mut world = World::new();
+        let mover = world.spawn(Mover(Position(0.0, 0.0), Velocity(1.0, 1.0)));
+        let player = world.spawn((Position(5.0, 5.0), Player));
+
+        world.insert_bundle(player, Velocity(2.0, 0.0));
+        world.insert_bundle(mover, (Position(3.0, 3.0), Player));
+        world.insert_bundle(mover, Position(4.0, 4.0));
+        let mut positions: Vec<_> = world
+            .query::<(Position, Velocity)>()
+            .iter()
+            .map(|(e, (p, _))| (e, *p))
+            .collect();
+        positions.sort_by_key(|(e, _)| *e);
+        assert_eq!(
+            positions,
+            vec![(mover, Position(4.0, 4.0)), (player, Position(5.0, 5.0))]
+        );
+
+        assert!(world.remove_bundle::<(Player, Mover)>(player).is_some());
+        assert!(world.remove_bundle::<Player>(player).is_none());
+        let Mover(position, velocity) = world.remove_bundle::<Mover>(mover).unwrap();
+        assert_eq!(
+            (position, velocity),
+            (Position(4.0, 4.0), Velocity(1.0, 1.0))
+        );
+        assert_eq!(
+            world
+                .query::<Player>()
+                .iter()
+                .map(|(e, _)| e)
+                .collect::<Vec<_>>(),
+            vec![mover]
+        );
+        assert_eq!(world.components(player).len(), 0);
+    }
+
+    #[derive(Component, PartialEq, Debug)]
+    struct Slot<const N: usize>(u32);
+
+    #[test]
+    fn test_spawn_more_than_twelve_components() {
+        let mut world = World::new();
+        let entity = spawn!(
+            world,
+            Slot::<0>(0),
+            Slot::<1>(1),
+            Slot::<2>(2),
+            Slot::<3>(3),
+            Slot::<4>(4),
+            Slot::<5>(5),
+            Slot::<6>(6),
+            Slot::<7>(7),
+            Slot::<8>(8),
+            Slot::<9>(9),
+            Slot::<10>(10),
+            Slot::<11>(11),
+            Slot::<12>(12),
+            Slot::<13>(13),
+            Position(1.0, 2.0)
+        );
+        assert_eq!(world.components(entity).len(), 15);
+        assert_eq!(
+            world.query::<Slot<13>>().iter().next(),
+            Some((entity, &Slot(13)))
+        );
+        assert_eq!(
+            world.query::<Position>().iter().next(),
+            Some((entity, &Position(1.0, 2.0)))
+        );
+    }
+
+    #[test]
+    fn test_world_is_send_and_sync() {
+        fn send_and_sync<T: Send + Sync>(_: T) {}
+        let mut world = World::new();
+        world.insert_resource(Added::default());
+        world.commands.push(Box::new(|world: &mut World| {
+            spawn!(world, Position(0.0, 0.0));
+        }));
+        send_and_sync(world);
+    }
+
 #[test]
 fn test_query_mut_tuple() {
     let mut world = World::new();
     let entity_with_player = spawn!(world, Position(0.0, 0.0), Player);
-    let entity_without_player = spawn!(world, Position(1.0, 0.0));
+    let _entity_without_player = spawn!(world, Position(1.0, 0.0));
 
     // First, collect all entities that have both Position and Player
-    let query = Query::<(Position, Player)> { world: &world, _marker: std::marker::PhantomData };
+    let query = world.query::<(Position, Player)>();
     let entities_with_player: Vec<_> = query.iter().map(|(e, _)| e).collect();
 
     // Mutably update only those positions
     {
-        let mut mut_query = QueryMut::<Position> { world: &mut world, _marker: std::marker::PhantomData };
+        let mut mut_query = world.query_mut::<Position>();
         mut_query.for_each_mut(|entity, pos| {
             if entities_with_player.contains(&entity) {
                 pos.0 += 10.0;
@@ -324,7 +1513,7 @@ fn test_query_mut_tuple() {
     }
 
     // Check results
-    let query = Query::<Position> { world: &world, _marker: std::marker::PhantomData };
+    let query = world.query::<Position>();
     let results: Vec<_> = query.iter().collect();
 
     // Find the modified and unmodified entities
@@ -337,5 +1526,4 @@ fn test_query_mut_tuple() {
     assert_eq!(*pos_with_player, Position(10.0, 20.0));
     assert_eq!(*pos_without_player, Position(1.0, 0.0));
 }
-
 }
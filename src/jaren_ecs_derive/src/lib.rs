@@ -2,25 +2,436 @@
 extern crate proc_macro;
 use proc_macro::TokenStream;
 use quote::quote;
-use syn;
 
-#[proc_macro_derive(Component)]
+/// Implements `Component`. Generic parameters and where-clauses are kept, and the type
+/// must be `Send + Sync + 'static` for the impl to apply.
+///
+/// Options go in a `#[component(...)]` attribute:
+/// - `storage = "table"` (the default) or `storage = "sparse_set"`, see `StorageType`.
+/// - `on_add = path::to::hook`, a `fn(&mut World, Entity)` called after the component
+///   is added to an entity.
+#[proc_macro_derive(Component, attributes(component))]
 pub fn component_derive(input: TokenStream) -> TokenStream {
-    let ast = syn::parse(input).unwrap();
+    let ast = syn::parse_macro_input!(input as syn::DeriveInput);
     impl_component_macro(&ast)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
 }
 
-fn impl_component_macro(ast: &syn::DeriveInput) -> TokenStream {
+#[derive(Default)]
+struct ComponentOptions {
+    storage: Option<proc_macro2::TokenStream>,
+    on_add: Option<syn::Path>,
+}
+
+fn component_options(ast: &syn::DeriveInput) -> syn::Result<ComponentOptions> {
+    let mut options = ComponentOptions::default();
+    for attr in ast
+        .attrs
+        .iter()
+        .filter(|attr| attr.path().is_ident("component"))
+    {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("storage") {
+                let storage: syn::LitStr = meta.value()?.parse()?;
+                options.storage = Some(match storage.value().as_str() {
+                    "table" => quote!(::jaren_ecs::system::StorageType::Table),
+                    "sparse_set" => quote!(::jaren_ecs::system::StorageType::SparseSet),
+                    _ => {
+                        return Err(syn::Error::new_spanned(
+                            storage,
+                            "expected `\"table\"` or `\"sparse_set\"`",
+                        ))
+                    }
+                });
+                Ok(())
+            } else if meta.path.is_ident("on_add") {
+                options.on_add = Some(meta.value()?.parse()?);
+                Ok(())
+            } else {
+                Err(meta.error("expected `storage` or `on_add`"))
+            }
+        })?;
+    }
+    Ok(options)
+}
+
+fn impl_component_macro(ast: &syn::DeriveInput) -> syn::Result<proc_macro2::TokenStream> {
     let name = &ast.ident;
-    let gen = quote! {
-        impl Component for #name {
+    let options = component_options(ast)?;
+
+    let mut generics = ast.generics.clone();
+    generics
+        .make_where_clause()
+        .predicates
+        .push(syn::parse_quote!(Self: Send + Sync + 'static));
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+
+    let storage = options.storage.map(|storage| {
+        quote! {
+            fn storage() -> ::jaren_ecs::system::StorageType
+            where
+                Self: Sized,
+            {
+                #storage
+            }
+        }
+    });
+    let on_add = options.on_add.map(|hook| {
+        quote! {
+            fn on_add(&self) -> Option<::jaren_ecs::system::ComponentHook> {
+                Some(#hook)
+            }
+        }
+    });
+
+    Ok(quote! {
+        impl #impl_generics ::jaren_ecs::system::Component for #name #ty_generics #where_clause {
             fn as_any(&self) -> &dyn std::any::Any {
                 self
             }
             fn as_any_mut(&mut self) -> &mut dyn std::any::Any {
                 self
             }
+            #storage
+            #on_add
         }
+    })
+}
+
+/// Implements `Reflect` for a struct or enum, giving access to its fields by name (`0`,
+/// `1`, … for tuple fields). An enum has the fields of its current variant. Every field
+/// type must implement `Reflect` unless the field is marked `#[reflect(ignore)]`.
+#[proc_macro_derive(Reflect, attributes(reflect))]
+pub fn reflect_derive(input: TokenStream) -> TokenStream {
+    let ast = syn::parse_macro_input!(input as syn::DeriveInput);
+    impl_reflect_macro(&ast)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+fn is_ignored(field: &syn::Field) -> syn::Result<bool> {
+    let mut ignored = false;
+    for attr in field
+        .attrs
+        .iter()
+        .filter(|attr| attr.path().is_ident("reflect"))
+    {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("ignore") {
+                ignored = true;
+                Ok(())
+            } else {
+                Err(meta.error("expected `ignore`"))
+            }
+        })?;
+    }
+    Ok(ignored)
+}
+
+// The reflected fields of a struct or variant, as names, members and types.
+struct ReflectFields<'a> {
+    names: Vec<String>,
+    members: Vec<syn::Member>,
+    types: Vec<&'a syn::Type>,
+}
+
+fn reflect_fields(fields: &syn::Fields) -> syn::Result<ReflectFields<'_>> {
+    let mut reflected = ReflectFields {
+        names: Vec::new(),
+        members: Vec::new(),
+        types: Vec::new(),
     };
-    gen.into()
-}
\ No newline at end of file
+    for (index, field) in fields.iter().enumerate() {
+        if is_ignored(field)? {
+            continue;
+        }
+        let member = match &field.ident {
+            Some(ident) => syn::Member::Named(ident.clone()),
+            None => syn::Member::Unnamed(syn::Index::from(index)),
+        };
+        reflected.names.push(match &field.ident {
+            Some(ident) => ident.to_string(),
+            None => index.to_string(),
+        });
+        reflected.members.push(member);
+        reflected.types.push(&field.ty);
+    }
+    Ok(reflected)
+}
+
+fn impl_reflect_macro(ast: &syn::DeriveInput) -> syn::Result<proc_macro2::TokenStream> {
+    let name = &ast.ident;
+    let mut types = Vec::new();
+    let body = match &ast.data {
+        syn::Data::Struct(data) => {
+            let ReflectFields {
+                names,
+                members,
+                types: field_types,
+            } = reflect_fields(&data.fields)?;
+            types.extend(field_types);
+            quote! {
+                fn field_names(&self) -> &'static [&'static str] {
+                    &[#(#names),*]
+                }
+                fn field(&self, name: &str) -> Option<&dyn ::jaren_ecs::reflect::Reflect> {
+                    match name {
+                        #(#names => Some(&self.#members),)*
+                        _ => None,
+                    }
+                }
+                fn field_mut(&mut self, name: &str) -> Option<&mut dyn ::jaren_ecs::reflect::Reflect> {
+                    match name {
+                        #(#names => Some(&mut self.#members),)*
+                        _ => None,
+                    }
+                }
+            }
+        }
+        syn::Data::Enum(data) => {
+            // Every variant is matched as `Self::Variant { .. }`, which also fits tuple
+            // and unit variants, and fields as `Self::Variant { 0: value, .. }`.
+            let mut variants = Vec::new();
+            let mut variant_names = Vec::new();
+            let mut field_names = Vec::new();
+            let mut field_variants = Vec::new();
+            let mut field_members = Vec::new();
+            let mut field_keys = Vec::new();
+            for variant in &data.variants {
+                let ident = &variant.ident;
+                let fields = reflect_fields(&variant.fields)?;
+                variants.push(ident);
+                variant_names.push(ident.to_string());
+                let names = &fields.names;
+                field_names.push(quote!(&[#(#names),*]));
+                for (name, member) in fields.names.iter().zip(fields.members) {
+                    field_variants.push(ident);
+                    field_members.push(member);
+                    field_keys.push(name.clone());
+                }
+                types.extend(fields.types);
+            }
+            quote! {
+                fn field_names(&self) -> &'static [&'static str] {
+                    match self {
+                        #(Self::#variants { .. } => #field_names,)*
+                    }
+                }
+                fn field(&self, name: &str) -> Option<&dyn ::jaren_ecs::reflect::Reflect> {
+                    match (self, name) {
+                        #((Self::#field_variants { #field_members: value, .. }, #field_keys) => Some(value),)*
+                        _ => None,
+                    }
+                }
+                fn field_mut(&mut self, name: &str) -> Option<&mut dyn ::jaren_ecs::reflect::Reflect> {
+                    match (self, name) {
+                        #((Self::#field_variants { #field_members: value, .. }, #field_keys) => Some(value),)*
+                        _ => None,
+                    }
+                }
+                fn variant_name(&self) -> Option<&'static str> {
+                    match self {
+                        #(Self::#variants { .. } => Some(#variant_names),)*
+                    }
+                }
+            }
+        }
+        syn::Data::Union(_) => {
+            return Err(syn::Error::new_spanned(
+                &ast.ident,
+                "Reflect cannot be derived for unions",
+            ))
+        }
+    };
+
+    let mut generics = ast.generics.clone();
+    let where_clause = generics.make_where_clause();
+    for ty in &types {
+        where_clause
+            .predicates
+            .push(syn::parse_quote!(#ty: ::jaren_ecs::reflect::Reflect));
+    }
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+
+    Ok(quote! {
+        impl #impl_generics ::jaren_ecs::reflect::Reflect for #name #ty_generics #where_clause {
+            #body
+        }
+    })
+}
+
+/// Implements `Bundle` for a struct whose fields are components or other bundles, so
+/// they can be spawned, inserted and removed together.
+#[proc_macro_derive(Bundle)]
+pub fn bundle_derive(input: TokenStream) -> TokenStream {
+    let ast = syn::parse_macro_input!(input as syn::DeriveInput);
+    impl_bundle_macro(&ast)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+fn impl_bundle_macro(ast: &syn::DeriveInput) -> syn::Result<proc_macro2::TokenStream> {
+    let name = &ast.ident;
+    let fields = match &ast.data {
+        syn::Data::Struct(data) => &data.fields,
+        _ => {
+            return Err(syn::Error::new_spanned(
+                &ast.ident,
+                "Bundle can only be derived for structs",
+            ))
+        }
+    };
+
+    let members: Vec<_> = fields
+        .iter()
+        .enumerate()
+        .map(|(index, field)| match &field.ident {
+            Some(ident) => syn::Member::Named(ident.clone()),
+            None => syn::Member::Unnamed(syn::Index::from(index)),
+        })
+        .collect();
+    let types: Vec<_> = fields.iter().map(|field| &field.ty).collect();
+
+    let mut generics = ast.generics.clone();
+    let where_clause = generics.make_where_clause();
+    where_clause
+        .predicates
+        .push(syn::parse_quote!(Self: Send + Sync + 'static));
+    for ty in &types {
+        where_clause
+            .predicates
+            .push(syn::parse_quote!(#ty: ::jaren_ecs::system::Bundle));
+    }
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+
+    Ok(quote! {
+        impl #impl_generics ::jaren_ecs::system::Bundle for #name #ty_generics #where_clause {
+            fn component_types(
+                types: &mut Vec<(std::any::TypeId, ::jaren_ecs::system::StorageType)>,
+            ) {
+                #(<#types as ::jaren_ecs::system::Bundle>::component_types(types);)*
+            }
+            fn add_columns(archetype: &mut ::jaren_ecs::system::Archetype) {
+                #(<#types as ::jaren_ecs::system::Bundle>::add_columns(archetype);)*
+            }
+            fn push(
+                self,
+                entity: ::jaren_ecs::system::Entity,
+                archetype: &mut ::jaren_ecs::system::Archetype,
+                sparse: &mut ::jaren_ecs::storage::SparseSets,
+                hooks: &mut Vec<::jaren_ecs::system::ComponentHook>,
+            ) {
+                #(<#types as ::jaren_ecs::system::Bundle>::push(
+                    self.#members,
+                    entity,
+                    archetype,
+                    sparse,
+                    hooks,
+                );)*
+            }
+            fn take(
+                entity: ::jaren_ecs::system::Entity,
+                row: usize,
+                archetype: &mut ::jaren_ecs::system::Archetype,
+                sparse: &mut ::jaren_ecs::storage::SparseSets,
+            ) -> Self {
+                Self {
+                    #(#members: <#types as ::jaren_ecs::system::Bundle>::take(
+                        entity,
+                        row,
+                        archetype,
+                        sparse,
+                    ),)*
+                }
+            }
+        }
+    })
+}
+
+/// Implements `SystemParam` for a struct of other system parameters, so a system can take
+/// them as one argument. The struct may have one lifetime, which the parameters borrow
+/// the world for.
+#[proc_macro_derive(SystemParam)]
+pub fn system_param_derive(input: TokenStream) -> TokenStream {
+    let ast = syn::parse_macro_input!(input as syn::DeriveInput);
+    impl_system_param_macro(&ast)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+fn impl_system_param_macro(ast: &syn::DeriveInput) -> syn::Result<proc_macro2::TokenStream> {
+    let name = &ast.ident;
+    let fields = match &ast.data {
+        syn::Data::Struct(data) => &data.fields,
+        _ => {
+            return Err(syn::Error::new_spanned(
+                &ast.ident,
+                "SystemParam can only be derived for structs",
+            ))
+        }
+    };
+    let mut lifetimes = ast.generics.lifetimes();
+    if let (Some(_), Some(extra)) = (lifetimes.next(), lifetimes.next()) {
+        return Err(syn::Error::new_spanned(
+            extra,
+            "SystemParam structs can have at most one lifetime",
+        ));
+    }
+
+    let members: Vec<_> = fields
+        .iter()
+        .enumerate()
+        .map(|(index, field)| match &field.ident {
+            Some(ident) => syn::Member::Named(ident.clone()),
+            None => syn::Member::Unnamed(syn::Index::from(index)),
+        })
+        .collect();
+    let types: Vec<_> = fields.iter().map(|field| &field.ty).collect();
+    let taken: Vec<_> = (0..types.len())
+        .map(|index| quote::format_ident!("taken{}", index))
+        .collect();
+
+    // The same struct borrowing the world for `'__w` instead of its own lifetime.
+    let param_args = ast.generics.params.iter().map(|param| match param {
+        syn::GenericParam::Lifetime(_) => quote!('__w),
+        syn::GenericParam::Type(param) => {
+            let ident = &param.ident;
+            quote!(#ident)
+        }
+        syn::GenericParam::Const(param) => {
+            let ident = &param.ident;
+            quote!(#ident)
+        }
+    });
+    let param_ty = quote!(#name<#(#param_args),*>);
+
+    // No `FieldTy: SystemParam` bounds: they would hide the fields' own `Param` types.
+    let (impl_generics, ty_generics, where_clause) = ast.generics.split_for_impl();
+
+    Ok(quote! {
+        impl #impl_generics ::jaren_ecs::system::SystemParam for #name #ty_generics #where_clause {
+            type Param<'__w> = #param_ty;
+            type Taken = (#(<#types as ::jaren_ecs::system::SystemParam>::Taken,)*);
+            fn access(access: &mut ::jaren_ecs::param::Access) {
+                #(<#types as ::jaren_ecs::system::SystemParam>::access(access);)*
+            }
+            fn take(world: &mut ::jaren_ecs::system::World) -> Self::Taken {
+                (#(<#types as ::jaren_ecs::system::SystemParam>::take(world),)*)
+            }
+            fn fetch<'__w>(
+                world: &'__w ::jaren_ecs::system::World,
+                taken: &'__w mut Self::Taken,
+            ) -> Self::Param<'__w> {
+                let (#(#taken,)*) = taken;
+                #name {
+                    #(#members: <#types as ::jaren_ecs::system::SystemParam>::fetch(world, #taken),)*
+                }
+            }
+            fn restore(world: &mut ::jaren_ecs::system::World, taken: Self::Taken) {
+                let (#(#taken,)*) = taken;
+                #(<#types as ::jaren_ecs::system::SystemParam>::restore(world, #taken);)*
+            }
+        }
+    })
+}
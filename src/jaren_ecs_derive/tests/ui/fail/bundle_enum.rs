@@ -0,0 +1,9 @@
+use jaren_ecs_derive::Bundle;
+
+#[derive(Bundle)]
+enum Shape {
+    Circle,
+    Square,
+}
+
+fn main() {}
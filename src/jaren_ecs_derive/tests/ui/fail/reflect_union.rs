@@ -0,0 +1,9 @@
+use jaren_ecs_derive::Reflect;
+
+#[derive(Reflect)]
+union Bits {
+    int: u32,
+    float: f32,
+}
+
+fn main() {}
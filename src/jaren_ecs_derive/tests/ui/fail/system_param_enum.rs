@@ -0,0 +1,9 @@
+use jaren_ecs_derive::SystemParam;
+
+#[derive(SystemParam)]
+enum Input {
+    Keyboard,
+    Mouse,
+}
+
+fn main() {}
@@ -0,0 +1,7 @@
+use jaren_ecs_derive::Component;
+
+#[derive(Component)]
+#[component(sparse)]
+struct Position(f32, f32);
+
+fn main() {}
@@ -0,0 +1,16 @@
+use jaren_ecs::system::{Component, StorageType};
+use jaren_ecs_derive::Component;
+use std::marker::PhantomData;
+
+#[derive(Component)]
+struct Tag<T>(PhantomData<T>)
+where
+    T: Copy;
+
+fn is_component<C: Component>() -> StorageType {
+    C::storage()
+}
+
+fn main() {
+    assert_eq!(is_component::<Tag<u32>>(), StorageType::Table);
+}
@@ -0,0 +1,13 @@
+use jaren_ecs::system::{Component, Entity, StorageType, World};
+use jaren_ecs_derive::Component;
+
+fn on_marked(_world: &mut World, _entity: Entity) {}
+
+#[derive(Component)]
+#[component(storage = "sparse_set", on_add = on_marked)]
+struct Marked;
+
+fn main() {
+    assert_eq!(Marked::storage(), StorageType::SparseSet);
+    assert!(Marked.on_add().is_some());
+}
@@ -1,5 +1,13 @@
 // src/rendering/shader.wgsl
 
+// Camera shared by every pipeline
+struct CameraUniform {
+    view_proj: mat4x4<f32>,
+};
+
+@group(0) @binding(0)
+var<uniform> camera: CameraUniform;
+
 // Structures for ColorVertex
 struct ColorVertexInput {
     @location(0) position: vec3<f32>,
@@ -15,18 +23,20 @@ struct ColorVertexOutput {
 struct SpriteVertexInput {
     @location(0) position: vec3<f32>,
     @location(1) uv: vec2<f32>,
+    @location(2) color: vec4<f32>,
 };
 
 struct SpriteVertexOutput {
     @builtin(position) clip_position: vec4<f32>,
     @location(0) uv: vec2<f32>,
+    @location(1) color: vec4<f32>,
 };
 
 // Color Vertex Shader
 @vertex
 fn vs_color_main(model: ColorVertexInput) -> ColorVertexOutput {
     var out: ColorVertexOutput;
-    out.clip_position = vec4<f32>(model.position, 1.0);
+    out.clip_position = camera.view_proj * vec4<f32>(model.position, 1.0);
     out.color = model.color;
     return out;
 }
@@ -35,8 +45,9 @@ fn vs_color_main(model: ColorVertexInput) -> ColorVertexOutput {
 @vertex
 fn vs_sprite_main(model: SpriteVertexInput) -> SpriteVertexOutput {
     var out: SpriteVertexOutput;
-    out.clip_position = vec4<f32>(model.position, 1.0);
+    out.clip_position = camera.view_proj * vec4<f32>(model.position, 1.0);
     out.uv = model.uv;
+    out.color = model.color;
     return out;
 }
 
@@ -47,13 +58,23 @@ fn fs_color_main(in: ColorVertexOutput) -> @location(0) vec4<f32> {
 }
 
 // Texture and sampler for sprite
-@group(0) @binding(0)
+@group(1) @binding(0)
 var t_diffuse: texture_2d<f32>;
-@group(0) @binding(1)
+@group(1) @binding(1)
 var s_diffuse: sampler;
 
-// Sprite Fragment Shader
+// Sprite fragment shaders output premultiplied color, which every blend mode expects.
+
+// For textures with straight alpha
 @fragment
 fn fs_sprite_main(in: SpriteVertexOutput) -> @location(0) vec4<f32> {
-    return textureSample(t_diffuse, s_diffuse, in.uv);
+    let color = textureSample(t_diffuse, s_diffuse, in.uv) * in.color;
+    return vec4<f32>(color.rgb * color.a, color.a);
+}
+
+// For textures that are already premultiplied
+@fragment
+fn fs_sprite_premultiplied(in: SpriteVertexOutput) -> @location(0) vec4<f32> {
+    let tint = vec4<f32>(in.color.rgb * in.color.a, in.color.a);
+    return textureSample(t_diffuse, s_diffuse, in.uv) * tint;
 }
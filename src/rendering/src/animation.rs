@@ -0,0 +1,480 @@
+use crate::sprite::{Crossfade, Sprite};
+use assets::{Asset, AssetServer, Handle};
+use jaren_ecs::system::{Component, Entity, World};
+use std::{any::Any, collections::HashMap};
+
+// Keeps zero-length frames from stalling `Playhead::advance`.
+const MIN_FRAME_DURATION: f32 = 0.001;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum PlaybackMode {
+    #[default]
+    Loop,
+    /// Play forwards, then backwards, without repeating the end frames.
+    PingPong,
+    /// Stop on the last frame.
+    Once,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct AnimationFrame<E = ()> {
+    /// Cell of the sprite's texture atlas.
+    pub index: usize,
+    /// In seconds.
+    pub duration: f32,
+    /// Sent through [`AnimationEvents`] when the frame is entered.
+    #[cfg_attr(feature = "serde", serde(default = "Option::default"))]
+    pub event: Option<E>,
+}
+
+/// A sequence of atlas cells. `E` is the type of the events frames can be marked with.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct AnimationClip<E = ()> {
+    pub frames: Vec<AnimationFrame<E>>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub mode: PlaybackMode,
+}
+
+impl<E: Send + Sync + 'static> Asset for AnimationClip<E> {}
+
+impl<E> AnimationClip<E> {
+    /// Every frame shown for `frame_duration` seconds.
+    pub fn from_indices(
+        indices: impl IntoIterator<Item = usize>,
+        frame_duration: f32,
+        mode: PlaybackMode,
+    ) -> Self {
+        Self {
+            frames: indices
+                .into_iter()
+                .map(|index| AnimationFrame {
+                    index,
+                    duration: frame_duration,
+                    event: None,
+                })
+                .collect(),
+            mode,
+        }
+    }
+
+    /// Mark a frame (a position in `frames`, not an atlas index) with an event.
+    pub fn with_event(mut self, frame: usize, event: E) -> Self {
+        self.frames[frame].event = Some(event);
+        self
+    }
+
+    /// Length of one pass through the frames, in seconds.
+    pub fn duration(&self) -> f32 {
+        self.frames.iter().map(|f| f.duration).sum()
+    }
+}
+
+// Where playback is inside a clip.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+struct Playhead {
+    frame: usize,
+    elapsed: f32,
+    reverse: bool,
+    // Whether the event of the current frame has been sent.
+    entered: bool,
+    finished: bool,
+}
+
+impl Playhead {
+    fn advance<E: Clone>(&mut self, clip: &AnimationClip<E>, delta: f32, events: &mut Vec<E>) {
+        let len = clip.frames.len();
+        if len == 0 {
+            return;
+        }
+        self.frame = self.frame.min(len - 1);
+        if !self.entered {
+            self.entered = true;
+            events.extend(clip.frames[self.frame].event.clone());
+        }
+        if self.finished {
+            return;
+        }
+
+        self.elapsed += delta;
+        loop {
+            let duration = clip.frames[self.frame].duration.max(MIN_FRAME_DURATION);
+            if self.elapsed < duration {
+                return;
+            }
+            let next = match clip.mode {
+                PlaybackMode::Loop => (self.frame + 1) % len,
+                PlaybackMode::Once if self.frame + 1 == len => {
+                    self.finished = true;
+                    self.elapsed = 0.0;
+                    return;
+                }
+                PlaybackMode::Once => self.frame + 1,
+                PlaybackMode::PingPong if len == 1 => 0,
+                PlaybackMode::PingPong => {
+                    if self.reverse && self.frame == 0 || !self.reverse && self.frame + 1 == len {
+                        self.reverse = !self.reverse;
+                    }
+                    if self.reverse {
+                        self.frame - 1
+                    } else {
+                        self.frame + 1
+                    }
+                }
+            };
+            self.elapsed -= duration;
+            self.frame = next;
+            events.extend(clip.frames[next].event.clone());
+        }
+    }
+}
+
+/// What moves an [`Animator`] from one state to another.
+#[derive(Clone, Debug, PartialEq)]
+pub enum TransitionCondition {
+    /// The current clip reached its end. Only happens for [`PlaybackMode::Once`].
+    Finished,
+    /// [`Animator::trigger`] was called with this name.
+    Trigger(String),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct Transition {
+    // `None` matches every state.
+    from: Option<String>,
+    to: String,
+    condition: TransitionCondition,
+    blend: f32,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+struct Fade {
+    from_index: usize,
+    duration: f32,
+    elapsed: f32,
+}
+
+/// Plays [`AnimationClip`]s on a [`Sprite`] through a small state machine of named states.
+/// Advanced by [`animate_sprites`].
+///
+/// ```
+/// # use rendering::animation::{Animator, TransitionCondition};
+/// # fn clips(idle: assets::Handle<rendering::animation::AnimationClip>,
+/// #          attack: assets::Handle<rendering::animation::AnimationClip>) {
+/// let animator = Animator::new("idle", idle)
+///     .with_state("attack", attack)
+///     .with_transition("idle", "attack", TransitionCondition::Trigger("attack".into()), 0.0)
+///     .with_transition("attack", "idle", TransitionCondition::Finished, 0.1);
+/// # }
+/// ```
+pub struct Animator<E = ()> {
+    states: HashMap<String, Handle<AnimationClip<E>>>,
+    transitions: Vec<Transition>,
+    current: String,
+    playhead: Playhead,
+    fade: Option<Fade>,
+    triggers: Vec<String>,
+    frame_index: Option<usize>,
+    /// Playback speed multiplier.
+    pub speed: f32,
+}
+
+impl<E: Send + Sync + 'static> Component for Animator<E> {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+impl<E: Clone + Send + Sync + 'static> Animator<E> {
+    pub fn new(state: &str, clip: Handle<AnimationClip<E>>) -> Self {
+        Self {
+            states: HashMap::from([(state.to_string(), clip)]),
+            transitions: Vec::new(),
+            current: state.to_string(),
+            playhead: Playhead::default(),
+            fade: None,
+            triggers: Vec::new(),
+            frame_index: None,
+            speed: 1.0,
+        }
+    }
+
+    pub fn with_state(mut self, state: &str, clip: Handle<AnimationClip<E>>) -> Self {
+        self.states.insert(state.to_string(), clip);
+        self
+    }
+
+    /// Go from `from` to `to` when `condition` holds, cross-fading over `blend` seconds.
+    /// Transitions are checked in the order they were added.
+    pub fn with_transition(
+        mut self,
+        from: &str,
+        to: &str,
+        condition: TransitionCondition,
+        blend: f32,
+    ) -> Self {
+        self.transitions.push(Transition {
+            from: Some(from.to_string()),
+            to: to.to_string(),
+            condition,
+            blend,
+        });
+        self
+    }
+
+    /// Like [`with_transition`](Self::with_transition), from any state.
+    pub fn with_any_transition(
+        mut self,
+        to: &str,
+        condition: TransitionCondition,
+        blend: f32,
+    ) -> Self {
+        self.transitions.push(Transition {
+            from: None,
+            to: to.to_string(),
+            condition,
+            blend,
+        });
+        self
+    }
+
+    /// Fire a trigger for [`TransitionCondition::Trigger`]. Triggers that no transition
+    /// from the current state uses are dropped on the next update.
+    pub fn trigger(&mut self, name: &str) {
+        self.triggers.push(name.to_string());
+    }
+
+    /// Switch to `state` right away, restarting its clip. Unknown states are ignored.
+    pub fn play(&mut self, state: &str, blend: f32) {
+        if !self.states.contains_key(state) {
+            log::warn!("animator has no state named {state:?}");
+            return;
+        }
+        self.fade = match self.frame_index {
+            Some(from_index) if blend > 0.0 => Some(Fade {
+                from_index,
+                duration: blend,
+                elapsed: 0.0,
+            }),
+            _ => None,
+        };
+        self.current = state.to_string();
+        self.playhead = Playhead::default();
+    }
+
+    pub fn state(&self) -> &str {
+        &self.current
+    }
+
+    /// Atlas cell to show, `None` until the clip has loaded.
+    pub fn frame_index(&self) -> Option<usize> {
+        self.frame_index
+    }
+
+    /// Whether a [`PlaybackMode::Once`] clip has reached its end.
+    pub fn is_finished(&self) -> bool {
+        self.playhead.finished
+    }
+
+    /// The frame being faded out after a blended transition.
+    pub fn crossfade(&self) -> Option<Crossfade> {
+        self.fade.map(|fade| Crossfade {
+            index: fade.from_index,
+            weight: 1.0 - fade.elapsed / fade.duration,
+        })
+    }
+
+    /// Move playback forward by `delta` seconds, taking transitions as their conditions
+    /// are met. Events of the frames entered are pushed to `events`.
+    pub fn advance(&mut self, delta: f32, assets: &AssetServer, events: &mut Vec<E>) {
+        let delta = delta * self.speed;
+        if let Some(fade) = &mut self.fade {
+            fade.elapsed += delta;
+            if fade.elapsed >= fade.duration {
+                self.fade = None;
+            }
+        }
+        self.step(delta, assets, events);
+
+        let finished = self.playhead.finished;
+        let triggers = std::mem::take(&mut self.triggers);
+        let transition = self.transitions.iter().find(|t| {
+            t.from.as_ref().is_none_or(|from| *from == self.current)
+                && t.to != self.current
+                && match &t.condition {
+                    TransitionCondition::Finished => finished,
+                    TransitionCondition::Trigger(name) => triggers.contains(name),
+                }
+        });
+        if let Some(transition) = transition {
+            let (to, blend) = (transition.to.clone(), transition.blend);
+            self.play(&to, blend);
+            // Show the first frame of the new state this update, not the next one.
+            self.step(0.0, assets, events);
+        }
+    }
+
+    fn step(&mut self, delta: f32, assets: &AssetServer, events: &mut Vec<E>) {
+        let Some(clip) = self.states.get(&self.current).and_then(|h| assets.get(h)) else {
+            return;
+        };
+        self.playhead.advance(clip, delta, events);
+        self.frame_index = clip.frames.get(self.playhead.frame).map(|f| f.index);
+    }
+}
+
+/// Events sent by [`Animator`]s with event type `E` during the last update, tagged with
+/// the entity that sent them. Stored as a world resource.
+pub struct AnimationEvents<E> {
+    events: Vec<(Entity, E)>,
+}
+
+impl<E> Default for AnimationEvents<E> {
+    fn default() -> Self {
+        Self { events: Vec::new() }
+    }
+}
+
+impl<E> AnimationEvents<E> {
+    pub fn iter(&self) -> impl Iterator<Item = &(Entity, E)> {
+        self.events.iter()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.events.is_empty()
+    }
+}
+
+/// Advance every entity with an [`Animator<E>`] and a [`Sprite`] by `delta` seconds and
+/// show the resulting atlas cells. Replaces the [`AnimationEvents<E>`] resource with the
+/// events sent during this update.
+pub fn animate_sprites<E: Clone + Send + Sync + 'static>(world: &mut World, delta: f32) {
+    let mut sent = Vec::new();
+    world.resource_scope(|world, assets: &mut AssetServer| {
+        let mut events = Vec::new();
+        world
+            .query_mut::<(Animator<E>, Sprite)>()
+            .for_each_mut(|entity, (animator, sprite)| {
+                animator.advance(delta, assets, &mut events);
+                if let Some(index) = animator.frame_index() {
+                    sprite.set_atlas_index(index);
+                }
+                sprite.crossfade = animator.crossfade();
+                sent.extend(events.drain(..).map(|event| (entity, event)));
+            });
+    });
+    world.insert_resource(AnimationEvents { events: sent });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn play<E: Clone>(clip: &AnimationClip<E>, steps: usize, delta: f32) -> (Vec<usize>, Vec<E>) {
+        let mut playhead = Playhead::default();
+        let mut events = Vec::new();
+        let frames = (0..steps)
+            .map(|_| {
+                playhead.advance(clip, delta, &mut events);
+                clip.frames[playhead.frame].index
+            })
+            .collect();
+        (frames, events)
+    }
+
+    #[test]
+    fn test_playback_modes() {
+        let clip = |mode| AnimationClip::<()>::from_indices([10, 11, 12], 0.1, mode);
+        assert_eq!(
+            play(&clip(PlaybackMode::Loop), 7, 0.1).0,
+            [11, 12, 10, 11, 12, 10, 11]
+        );
+        assert_eq!(
+            play(&clip(PlaybackMode::PingPong), 7, 0.1).0,
+            [11, 12, 11, 10, 11, 12, 11]
+        );
+        assert_eq!(
+            play(&clip(PlaybackMode::Once), 5, 0.1).0,
+            [11, 12, 12, 12, 12]
+        );
+        // A long frame skips several frames at once.
+        assert_eq!(play(&clip(PlaybackMode::Loop), 1, 0.45).0, [11]);
+    }
+
+    #[derive(Clone, Debug, PartialEq)]
+    enum Cue {
+        Footstep,
+        Hit,
+    }
+
+    #[test]
+    fn test_events_on_marked_frames() {
+        let clip = AnimationClip::from_indices(0..4, 0.1, PlaybackMode::Loop)
+            .with_event(0, Cue::Footstep)
+            .with_event(2, Cue::Hit);
+        // Frame 0 on start, 2, then 0 again after wrapping around.
+        assert_eq!(
+            play(&clip, 4, 0.1).1,
+            [Cue::Footstep, Cue::Hit, Cue::Footstep]
+        );
+    }
+
+    #[test]
+    fn test_state_machine() {
+        let mut assets = AssetServer::new();
+        let idle = assets.add(AnimationClip::from_indices(0..2, 0.1, PlaybackMode::Loop));
+        let attack = assets.add(
+            AnimationClip::from_indices(4..6, 0.1, PlaybackMode::Once).with_event(1, Cue::Hit),
+        );
+        let mut animator = Animator::new("idle", idle)
+            .with_state("attack", attack)
+            .with_transition(
+                "idle",
+                "attack",
+                TransitionCondition::Trigger("attack".into()),
+                0.0,
+            )
+            .with_transition("attack", "idle", TransitionCondition::Finished, 0.2);
+        let mut events = Vec::new();
+
+        animator.advance(0.15, &assets, &mut events);
+        assert_eq!(
+            (animator.state(), animator.frame_index()),
+            ("idle", Some(1))
+        );
+
+        animator.trigger("attack");
+        animator.advance(0.0, &assets, &mut events);
+        assert_eq!(
+            (animator.state(), animator.frame_index()),
+            ("attack", Some(4))
+        );
+        assert_eq!(animator.crossfade(), None);
+
+        animator.advance(0.1, &assets, &mut events);
+        assert_eq!(events, [Cue::Hit]);
+        animator.advance(0.1, &assets, &mut events);
+        assert_eq!(
+            (animator.state(), animator.frame_index()),
+            ("idle", Some(0))
+        );
+        assert_eq!(
+            animator.crossfade(),
+            Some(Crossfade {
+                index: 5,
+                weight: 1.0
+            })
+        );
+
+        animator.advance(0.1, &assets, &mut events);
+        assert_eq!(animator.crossfade().map(|c| c.weight), Some(0.5));
+        animator.advance(0.1, &assets, &mut events);
+        assert_eq!(animator.crossfade(), None);
+    }
+}
@@ -0,0 +1,282 @@
+use assets::{Asset, AssetLoader, Handle, Image, LoadContext};
+use std::fmt;
+
+/// Pixel rectangle inside a texture, `max` is exclusive.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Default)]
+pub struct Rect {
+    pub min: [u32; 2],
+    pub max: [u32; 2],
+}
+
+impl Rect {
+    pub fn new(x: u32, y: u32, width: u32, height: u32) -> Self {
+        Self {
+            min: [x, y],
+            max: [x + width, y + height],
+        }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.max[0] - self.min[0]
+    }
+
+    pub fn height(&self) -> u32 {
+        self.max[1] - self.min[1]
+    }
+}
+
+/// Many sprites sharing one texture, so drawing them does not switch bind groups.
+#[derive(Clone, Debug)]
+pub struct TextureAtlas {
+    pub texture: Handle<Image>,
+    /// Size of `texture` in pixels.
+    pub size: [u32; 2],
+    pub rects: Vec<Rect>,
+}
+
+impl Asset for TextureAtlas {}
+
+impl TextureAtlas {
+    /// Cut a sprite sheet into equally sized tiles, row by row starting top-left.
+    /// `padding` is the gap between tiles and `offset` the margin before the first one.
+    pub fn from_grid(
+        texture: Handle<Image>,
+        size: [u32; 2],
+        tile_size: [u32; 2],
+        columns: u32,
+        rows: u32,
+        padding: [u32; 2],
+        offset: [u32; 2],
+    ) -> Self {
+        let mut rects = Vec::with_capacity((columns * rows) as usize);
+        for row in 0..rows {
+            for column in 0..columns {
+                rects.push(Rect::new(
+                    offset[0] + column * (tile_size[0] + padding[0]),
+                    offset[1] + row * (tile_size[1] + padding[1]),
+                    tile_size[0],
+                    tile_size[1],
+                ));
+            }
+        }
+        Self {
+            texture,
+            size,
+            rects,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.rects.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rects.is_empty()
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PackError {
+    /// Nothing to pack, or only empty images; textures cannot be 0x0.
+    Empty,
+    /// The rect at this index is wider than the atlas.
+    TooWide { index: usize, width: u32 },
+    /// The packed atlas would not fit in memory.
+    TooLarge { width: u32, height: u32 },
+}
+
+impl fmt::Display for PackError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PackError::Empty => write!(f, "the atlas has no pixels to pack"),
+            PackError::TooWide { index, width } => write!(
+                f,
+                "rect {index} is {width}px wide and does not fit in the atlas"
+            ),
+            PackError::TooLarge { width, height } => {
+                write!(f, "a {width}x{height} atlas is too large")
+            }
+        }
+    }
+}
+
+impl std::error::Error for PackError {}
+
+/// Places rects on shelves, tallest first. Simple, fast and deterministic:
+/// the same input always gives the same layout.
+pub struct RectPacker {
+    pub max_width: u32,
+    /// Empty pixels kept around every rect so sampling does not bleed into neighbours.
+    pub padding: u32,
+}
+
+impl RectPacker {
+    /// Returns one rect per input size, in input order, and the size of the atlas needed.
+    pub fn pack(&self, sizes: &[[u32; 2]]) -> Result<(Vec<Rect>, [u32; 2]), PackError> {
+        let mut order: Vec<usize> = (0..sizes.len()).collect();
+        // Stable sort, so equal sizes keep their input order.
+        order.sort_by(|&a, &b| {
+            sizes[b][1]
+                .cmp(&sizes[a][1])
+                .then(sizes[b][0].cmp(&sizes[a][0]))
+        });
+
+        let mut rects = vec![Rect::default(); sizes.len()];
+        let (mut x, mut y, mut shelf_height) = (self.padding, self.padding, 0);
+        let mut used_width = 0;
+        for index in order {
+            let [width, height] = sizes[index];
+            if width + self.padding * 2 > self.max_width {
+                return Err(PackError::TooWide { index, width });
+            }
+            if x + width + self.padding > self.max_width {
+                y += shelf_height + self.padding;
+                x = self.padding;
+                shelf_height = 0;
+            }
+            rects[index] = Rect::new(x, y, width, height);
+            x += width + self.padding;
+            used_width = used_width.max(x);
+            shelf_height = shelf_height.max(height);
+        }
+        let used_height = if sizes.is_empty() {
+            0
+        } else {
+            y + shelf_height + self.padding
+        };
+        Ok((rects, [used_width, used_height]))
+    }
+}
+
+/// Packs separate images into one [`Image`] plus the rect of each inside it.
+pub struct TextureAtlasBuilder {
+    pub packer: RectPacker,
+    images: Vec<Image>,
+}
+
+impl Default for TextureAtlasBuilder {
+    fn default() -> Self {
+        Self {
+            packer: RectPacker {
+                max_width: 2048,
+                padding: 1,
+            },
+            images: Vec::new(),
+        }
+    }
+}
+
+impl TextureAtlasBuilder {
+    pub fn add(&mut self, image: Image) -> &mut Self {
+        self.images.push(image);
+        self
+    }
+
+    /// The packed image and one rect per added image, in the order they were added.
+    pub fn build(&self) -> Result<(Image, Vec<Rect>), PackError> {
+        let sizes: Vec<[u32; 2]> = self.images.iter().map(|i| [i.width, i.height]).collect();
+        let (rects, [width, height]) = self.packer.pack(&sizes)?;
+        if width == 0 || height == 0 {
+            return Err(PackError::Empty);
+        }
+
+        let mut atlas =
+            Image::blank(width, height).map_err(|_| PackError::TooLarge { width, height })?;
+        for (image, rect) in self.images.iter().zip(&rects) {
+            let row_len = (image.width * 4) as usize;
+            for row in 0..image.height {
+                let src = (row * image.width * 4) as usize;
+                let dst = (((rect.min[1] + row) * width + rect.min[0]) * 4) as usize;
+                atlas.data[dst..dst + row_len].copy_from_slice(&image.data[src..src + row_len]);
+            }
+        }
+        Ok((atlas, rects))
+    }
+}
+
+/// Loads `.atlas` files: one image path per line (relative to the asset root), packed
+/// into a single texture when loaded. Atlas indices follow the line order.
+///
+/// The images are read with [`LoadContext::read`], so this loader does not work on the web.
+pub struct TextureAtlasLoader;
+
+impl AssetLoader for TextureAtlasLoader {
+    type Asset = TextureAtlas;
+    type Error = String;
+
+    fn extensions(&self) -> &[&str] {
+        &["atlas"]
+    }
+
+    async fn load(&self, bytes: &[u8], ctx: &mut LoadContext) -> Result<TextureAtlas, String> {
+        let list = std::str::from_utf8(bytes).map_err(|e| e.to_string())?;
+        let mut builder = TextureAtlasBuilder::default();
+        for line in list.lines().map(str::trim).filter(|l| !l.is_empty()) {
+            let bytes = ctx.read(line).map_err(|e| format!("{line}: {e}"))?;
+            builder.add(Image::from_bytes(&bytes).map_err(|e| format!("{line}: {e}"))?);
+        }
+        let (image, rects) = builder.build().map_err(|e| e.to_string())?;
+        let size = [image.width, image.height];
+        Ok(TextureAtlas {
+            texture: ctx.add(image),
+            size,
+            rects,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_packer_is_deterministic() {
+        let packer = RectPacker {
+            max_width: 64,
+            padding: 1,
+        };
+        let sizes = [[16, 16], [32, 8], [16, 32], [8, 8], [40, 10], [16, 16]];
+        let (rects, size) = packer.pack(&sizes).unwrap();
+        assert_eq!(
+            rects,
+            vec![
+                Rect::new(18, 1, 16, 16),
+                Rect::new(1, 45, 32, 8),
+                Rect::new(1, 1, 16, 32),
+                Rect::new(34, 45, 8, 8),
+                Rect::new(1, 34, 40, 10),
+                Rect::new(35, 1, 16, 16),
+            ]
+        );
+        assert_eq!(size, [52, 54]);
+        assert_eq!(packer.pack(&sizes).unwrap(), (rects, size));
+    }
+
+    #[test]
+    fn test_packer_rejects_oversized() {
+        let packer = RectPacker {
+            max_width: 16,
+            padding: 0,
+        };
+        assert_eq!(
+            packer.pack(&[[8, 8], [20, 4]]),
+            Err(PackError::TooWide {
+                index: 1,
+                width: 20
+            })
+        );
+    }
+
+    #[test]
+    fn test_empty_atlas_is_an_error() {
+        let mut builder = TextureAtlasBuilder::default();
+        assert_eq!(builder.build().err(), Some(PackError::Empty));
+        builder.packer.padding = 0;
+        builder.add(Image::blank(0, 4).unwrap());
+        assert_eq!(builder.build().err(), Some(PackError::Empty));
+        builder.add(Image::blank(2, 2).unwrap());
+        let (image, rects) = builder.build().unwrap();
+        assert_eq!([image.width, image.height], [2, 4]);
+        assert_eq!(rects[1], Rect::new(0, 0, 2, 2));
+    }
+}
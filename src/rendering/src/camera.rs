@@ -0,0 +1,218 @@
+use assets::{Handle, Image};
+use bytemuck::{Pod, Zeroable};
+use jaren_ecs_derive::Component;
+
+/// How a [`Camera2d`] maps world units onto its viewport.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ScalingMode {
+    /// One world unit per physical pixel.
+    WindowSize,
+    /// The visible area is always this many world units tall, width follows the aspect ratio.
+    FixedHeight(f32),
+    /// The visible area is always this many world units wide, height follows the aspect ratio.
+    FixedWidth(f32),
+    /// Scales a reference resolution by the largest integer factor that fits the viewport,
+    /// so one world unit is always a whole number of physical pixels.
+    PixelPerfect { width: u32, height: u32 },
+}
+
+/// Region of the render target a camera draws into, in physical pixels.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Viewport {
+    pub position: [u32; 2],
+    pub size: [u32; 2],
+}
+
+/// Where a [`Camera2d`] draws.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub enum RenderTarget {
+    /// The window.
+    #[default]
+    Window,
+    /// An image asset, which sprites can then show like any other image (minimaps,
+    /// portals, upscaling a low resolution scene). Only its size is used; the pixels
+    /// sprites see are whatever the camera last drew.
+    Image(Handle<Image>),
+}
+
+/// Orthographic 2D camera. World space is y-up with the camera centered on `position`.
+#[derive(Component, Clone, Debug, PartialEq)]
+pub struct Camera2d {
+    pub position: [f32; 2],
+    /// Values above 1.0 zoom in, values below zoom out.
+    pub zoom: f32,
+    pub scaling_mode: ScalingMode,
+    /// Draw into a sub-rectangle of the target instead of the whole thing.
+    pub viewport: Option<Viewport>,
+    pub target: RenderTarget,
+    /// Cameras render in ascending order, except that a camera rendering into an image
+    /// always goes before the cameras that show that image.
+    pub order: i32,
+    /// Color the target is cleared to first. `None` draws over what is already there,
+    /// for cameras layered on top of another one.
+    pub clear_color: Option<[f32; 4]>,
+}
+
+impl Default for Camera2d {
+    fn default() -> Self {
+        Self {
+            position: [0.0, 0.0],
+            zoom: 1.0,
+            // Same vertical extent as raw clip space, so a unit quad looks the same as before.
+            scaling_mode: ScalingMode::FixedHeight(2.0),
+            viewport: None,
+            target: RenderTarget::Window,
+            order: 0,
+            clear_color: Some([0.1, 0.2, 0.3, 1.0]),
+        }
+    }
+}
+
+impl Camera2d {
+    /// The viewport rectangle for a render target of `target_size`, clipped to the target.
+    /// Its size is zero in a direction the viewport lies entirely outside the target.
+    pub fn viewport_rect(&self, target_size: [u32; 2]) -> Viewport {
+        let Some(viewport) = self.viewport else {
+            return Viewport {
+                position: [0, 0],
+                size: target_size,
+            };
+        };
+        let position = [
+            viewport.position[0].min(target_size[0]),
+            viewport.position[1].min(target_size[1]),
+        ];
+        Viewport {
+            position,
+            size: [
+                viewport.size[0].min(target_size[0] - position[0]),
+                viewport.size[1].min(target_size[1] - position[1]),
+            ],
+        }
+    }
+
+    /// Width and height of the visible area in world units.
+    pub fn visible_size(&self, target_size: [u32; 2]) -> [f32; 2] {
+        let size = self.viewport_rect(target_size).size;
+        let (w, h) = (size[0].max(1) as f32, size[1].max(1) as f32);
+        let visible = match self.scaling_mode {
+            ScalingMode::WindowSize => [w, h],
+            ScalingMode::FixedHeight(height) => [height * w / h, height],
+            ScalingMode::FixedWidth(width) => [width, width * h / w],
+            ScalingMode::PixelPerfect { width, height } => {
+                let scale = (size[0] / width.max(1)).min(size[1] / height.max(1)).max(1) as f32;
+                [w / scale, h / scale]
+            }
+        };
+        let zoom = if self.zoom > 0.0 { self.zoom } else { 1.0 };
+        [visible[0] / zoom, visible[1] / zoom]
+    }
+
+    /// Column-major orthographic view-projection matrix mapping world space to clip space.
+    pub fn view_projection(&self, target_size: [u32; 2]) -> [[f32; 4]; 4] {
+        let [w, h] = self.visible_size(target_size);
+        let (sx, sy) = (2.0 / w, 2.0 / h);
+        [
+            [sx, 0.0, 0.0, 0.0],
+            [0.0, sy, 0.0, 0.0],
+            [0.0, 0.0, 1.0, 0.0],
+            [-self.position[0] * sx, -self.position[1] * sy, 0.0, 1.0],
+        ]
+    }
+
+    /// Convert a world position to screen coordinates (physical pixels, origin top-left, y down).
+    pub fn world_to_screen(&self, world: [f32; 2], target_size: [u32; 2]) -> [f32; 2] {
+        let viewport = self.viewport_rect(target_size);
+        let [w, h] = self.visible_size(target_size);
+        let ndc_x = (world[0] - self.position[0]) * 2.0 / w;
+        let ndc_y = (world[1] - self.position[1]) * 2.0 / h;
+        [
+            viewport.position[0] as f32 + (ndc_x + 1.0) * 0.5 * viewport.size[0] as f32,
+            viewport.position[1] as f32 + (1.0 - ndc_y) * 0.5 * viewport.size[1] as f32,
+        ]
+    }
+
+    /// Convert screen coordinates (physical pixels, origin top-left, y down) to a world position.
+    pub fn screen_to_world(&self, screen: [f32; 2], target_size: [u32; 2]) -> [f32; 2] {
+        let viewport = self.viewport_rect(target_size);
+        let [w, h] = self.visible_size(target_size);
+        let ndc_x =
+            (screen[0] - viewport.position[0] as f32) / viewport.size[0].max(1) as f32 * 2.0 - 1.0;
+        let ndc_y =
+            1.0 - (screen[1] - viewport.position[1] as f32) / viewport.size[1].max(1) as f32 * 2.0;
+        [
+            self.position[0] + ndc_x * w * 0.5,
+            self.position[1] + ndc_y * h * 0.5,
+        ]
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+pub(crate) struct CameraUniform {
+    view_proj: [[f32; 4]; 4],
+}
+
+impl CameraUniform {
+    pub(crate) fn new(camera: &Camera2d, target_size: [u32; 2]) -> Self {
+        Self {
+            view_proj: camera.view_projection(target_size),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_screen_world_round_trip() {
+        let camera = Camera2d {
+            position: [3.0, -2.0],
+            zoom: 2.0,
+            ..Default::default()
+        };
+        let target = [800, 600];
+        let screen = camera.world_to_screen([4.0, -1.5], target);
+        let world = camera.screen_to_world(screen, target);
+        assert!((world[0] - 4.0).abs() < 1e-4);
+        assert!((world[1] + 1.5).abs() < 1e-4);
+        assert_eq!(
+            camera.world_to_screen(camera.position, target),
+            [400.0, 300.0]
+        );
+    }
+
+    #[test]
+    fn test_pixel_perfect_uses_integer_scale() {
+        let camera = Camera2d {
+            scaling_mode: ScalingMode::PixelPerfect {
+                width: 320,
+                height: 180,
+            },
+            ..Default::default()
+        };
+        // 1000x700 fits 320x180 three times, so one world unit is three pixels.
+        let visible = camera.visible_size([1000, 700]);
+        assert_eq!(visible, [1000.0 / 3.0, 700.0 / 3.0]);
+    }
+
+    #[test]
+    fn test_viewport_is_clipped_to_target() {
+        let camera = Camera2d {
+            viewport: Some(Viewport {
+                position: [600, 100],
+                size: [400, 400],
+            }),
+            ..Default::default()
+        };
+        let clipped = Viewport {
+            position: [600, 100],
+            size: [200, 400],
+        };
+        assert_eq!(camera.viewport_rect([800, 600]), clipped);
+        let outside = camera.viewport_rect([500, 0]);
+        assert_eq!(outside.size, [0, 0]);
+        assert!(camera.visible_size([0, 0]).iter().all(|s| s.is_finite()));
+    }
+}
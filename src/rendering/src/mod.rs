@@ -1 +1,8 @@
+pub mod animation;
+pub mod atlas;
+pub mod camera;
 pub mod renderer;
+pub mod screenshot;
+pub mod sprite;
+pub mod surface;
+pub mod texture;
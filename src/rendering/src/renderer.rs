@@ -1,5 +1,20 @@
+use crate::{
+    atlas::{Rect, TextureAtlas},
+    camera::{Camera2d, CameraUniform, RenderTarget},
+    screenshot::{Readback, ScreenshotTarget},
+    sprite::{BlendMode, Sprite, SpriteSource, Transform2d},
+    surface::{SurfaceOptions, choose_format, choose_present_mode, choose_sample_count},
+    texture::GpuTexture,
+};
+use assets::{AssetServer, Handle, HandleId, Image, Shader};
 use bytemuck::{Pod, Zeroable};
-use std::sync::Arc;
+use jaren_ecs::system::World;
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    ops::Range,
+    sync::Arc,
+};
 use wgpu::util::DeviceExt;
 use winit::window::Window;
 
@@ -15,6 +30,7 @@ struct ColorVertex {
 struct SpriteVertex {
     position: [f32; 3],
     uv: [f32; 2],
+    color: [f32; 4],
 }
 
 impl SpriteVertex {
@@ -33,6 +49,11 @@ impl SpriteVertex {
                     shader_location: 1,
                     format: wgpu::VertexFormat::Float32x2,
                 },
+                wgpu::VertexAttribute {
+                    offset: std::mem::size_of::<[f32; 5]>() as wgpu::BufferAddress,
+                    shader_location: 2,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
             ],
         }
     }
@@ -74,172 +95,653 @@ const TRIANGLE_VERTICES: &[ColorVertex] = &[
     },
 ];
 
-const SPRITE_VERTICES: &[SpriteVertex] = &[
-    SpriteVertex {
-        position: [-0.5, -0.5, 0.0],
-        uv: [0.0, 1.0],
-    },
-    SpriteVertex {
-        position: [0.5, -0.5, 0.0],
-        uv: [1.0, 1.0],
-    },
-    SpriteVertex {
-        position: [0.5, 0.5, 0.0],
-        uv: [1.0, 0.0],
-    },
-    SpriteVertex {
-        position: [-0.5, 0.5, 0.0],
-        uv: [0.0, 0.0],
-    },
-];
+// Sprites drawn back to back with the same texture and blend mode.
+struct SpriteBatch {
+    texture: HandleId,
+    blend_mode: BlendMode,
+    indices: Range<u32>,
+}
 
-const SPRITE_INDICES: &[u16] = &[0, 1, 2, 2, 3, 0];
+// Room for this many sprites before the buffers have to grow.
+const INITIAL_SPRITE_CAPACITY: u64 = 64;
 
-const TRIANGLE_INDICES: &[ColorVertex] = &[
-    ColorVertex {
-        position: [0.0, 0.5, 0.0],
-        color: [1.0, 0.0, 0.0, 1.0],
-    },
-    ColorVertex {
-        position: [-0.5, -0.5, 0.0],
-        color: [0.0, 1.0, 0.0, 1.0],
-    },
-    ColorVertex {
-        position: [0.5, -0.5, 0.0],
-        color: [0.0, 0.0, 1.0, 1.0],
-    },
+fn sprite_buffers(device: &wgpu::Device, sprites: u64) -> (wgpu::Buffer, wgpu::Buffer) {
+    let vertices = device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("Sprite Vertex Buffer"),
+        size: sprites * 4 * std::mem::size_of::<SpriteVertex>() as u64,
+        usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    });
+    let indices = device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("Sprite Index Buffer"),
+        size: sprites * 6 * std::mem::size_of::<u32>() as u64,
+        usage: wgpu::BufferUsages::INDEX | wgpu::BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    });
+    (vertices, indices)
+}
+
+// A camera prepared for drawing, with its own uniform buffer.
+struct CameraView {
+    camera: Camera2d,
+    // `None` is the window.
+    target: Option<HandleId>,
+    target_size: [u32; 2],
+    buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+}
+
+// Order cameras, given as `(order, target)`, so that every camera rendering into a
+// sampled image goes before the cameras that may show it. Otherwise, and when two
+// image cameras show each other, `order` decides and the later one sees the previous frame.
+fn render_order(cameras: &[(i32, Option<HandleId>)], sampled: &HashSet<HandleId>) -> Vec<usize> {
+    let mut remaining: Vec<usize> = (0..cameras.len()).collect();
+    remaining.sort_by_key(|&i| cameras[i].0);
+    let depends_on = |i: usize, j: usize| {
+        cameras[j]
+            .1
+            .is_some_and(|target| sampled.contains(&target) && cameras[i].1 != Some(target))
+    };
+
+    let mut ordered = Vec::with_capacity(cameras.len());
+    while !remaining.is_empty() {
+        let ready = remaining
+            .iter()
+            .position(|&i| !remaining.iter().any(|&j| j != i && depends_on(i, j)))
+            .unwrap_or(0);
+        ordered.push(remaining.remove(ready));
+    }
+    ordered
+}
+
+// The image a sprite samples and the part of it to use. `None` for empty images,
+// which cannot be made into textures.
+fn resolve_sprite<'a>(
+    sprite: &'a Sprite,
+    assets: &'a AssetServer,
+) -> Option<(&'a Handle<Image>, &'a Image, Rect)> {
+    let resolved = match &sprite.source {
+        SpriteSource::Image(handle) => {
+            let image = assets.get(handle)?;
+            Some((handle, image, Rect::new(0, 0, image.width, image.height)))
+        }
+        SpriteSource::Atlas { atlas, index } => {
+            let atlas: &TextureAtlas = assets.get(atlas)?;
+            let image = assets.get(&atlas.texture)?;
+            Some((&atlas.texture, image, *atlas.rects.get(*index)?))
+        }
+    };
+    resolved.filter(|(_, image, _)| image.width > 0 && image.height > 0)
+}
+
+fn sprite_quad(
+    sprite: &Sprite,
+    transform: &Transform2d,
+    rect: Rect,
+    texture_size: [u32; 2],
+) -> [SpriteVertex; 4] {
+    let [width, height] = sprite
+        .custom_size
+        .unwrap_or([rect.width() as f32, rect.height() as f32]);
+    let (w, h) = (texture_size[0] as f32, texture_size[1] as f32);
+    let (mut u0, mut u1) = (rect.min[0] as f32 / w, rect.max[0] as f32 / w);
+    let (mut v0, mut v1) = (rect.min[1] as f32 / h, rect.max[1] as f32 / h);
+    if sprite.flip_x {
+        std::mem::swap(&mut u0, &mut u1);
+    }
+    if sprite.flip_y {
+        std::mem::swap(&mut v0, &mut v1);
+    }
+    // Counter-clockwise from bottom-left, texture rows run top to bottom.
+    let corners = [
+        ([-0.5, -0.5], [u0, v1]),
+        ([0.5, -0.5], [u1, v1]),
+        ([0.5, 0.5], [u1, v0]),
+        ([-0.5, 0.5], [u0, v0]),
+    ];
+    corners.map(|(corner, uv)| {
+        let [x, y] = transform.transform_point([corner[0] * width, corner[1] * height]);
+        SpriteVertex {
+            position: [x, y, transform.translation[2]],
+            uv,
+            color: sprite.color,
+        }
+    })
+}
+
+/// Where `shader.wgsl` lives in the source tree, for hot reloading during development.
+pub const SHADER_SOURCE_PATH: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/shader.wgsl");
+
+fn create_pipeline(
+    device: &wgpu::Device,
+    label: &str,
+    layout: &wgpu::PipelineLayout,
+    shader: &wgpu::ShaderModule,
+    (vs_entry, vertex_layout): (&str, wgpu::VertexBufferLayout),
+    (fs_entry, target): (&str, wgpu::ColorTargetState),
+    sample_count: u32,
+) -> wgpu::RenderPipeline {
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some(label),
+        layout: Some(layout),
+        vertex: wgpu::VertexState {
+            module: shader,
+            entry_point: vs_entry,
+            buffers: &[vertex_layout],
+            compilation_options: Default::default(),
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: shader,
+            entry_point: fs_entry,
+            targets: &[Some(target)],
+            compilation_options: Default::default(),
+        }),
+        primitive: wgpu::PrimitiveState {
+            topology: wgpu::PrimitiveTopology::TriangleList,
+            strip_index_format: None,
+            front_face: wgpu::FrontFace::Ccw,
+            // Sprites flipped with a negative scale wind the other way.
+            cull_mode: None,
+            polygon_mode: wgpu::PolygonMode::Fill,
+            unclipped_depth: false,
+            conservative: false,
+        },
+        depth_stencil: None,
+        multisample: wgpu::MultisampleState {
+            count: sample_count,
+            mask: !0,
+            alpha_to_coverage_enabled: false,
+        },
+        multiview: None,
+    })
+}
+
+// Every sprite fragment shader outputs premultiplied color, so one set of factors per
+// mode covers both straight and premultiplied textures.
+fn sprite_blend_state(mode: BlendMode) -> wgpu::BlendState {
+    use wgpu::{BlendComponent, BlendFactor, BlendOperation};
+    let component = |src_factor, dst_factor| BlendComponent {
+        src_factor,
+        dst_factor,
+        operation: BlendOperation::Add,
+    };
+    let over = component(BlendFactor::One, BlendFactor::OneMinusSrcAlpha);
+    match mode {
+        BlendMode::Alpha | BlendMode::Premultiplied => wgpu::BlendState {
+            color: over,
+            alpha: over,
+        },
+        BlendMode::Additive => wgpu::BlendState {
+            color: component(BlendFactor::One, BlendFactor::One),
+            alpha: component(BlendFactor::Zero, BlendFactor::One),
+        },
+        // dst * src where opaque, fading to dst where transparent.
+        BlendMode::Multiply => wgpu::BlendState {
+            color: component(BlendFactor::Dst, BlendFactor::OneMinusSrcAlpha),
+            alpha: component(BlendFactor::Zero, BlendFactor::One),
+        },
+    }
+}
+
+const BLEND_MODES: [BlendMode; 4] = [
+    BlendMode::Alpha,
+    BlendMode::Premultiplied,
+    BlendMode::Additive,
+    BlendMode::Multiply,
 ];
 
-pub struct Renderer {
+fn sprite_fragment_entry(mode: BlendMode) -> &'static str {
+    match mode {
+        BlendMode::Premultiplied => "fs_sprite_premultiplied",
+        _ => "fs_sprite_main",
+    }
+}
+
+fn create_color_pipeline(
+    device: &wgpu::Device,
+    layout: &wgpu::PipelineLayout,
+    shader: &wgpu::ShaderModule,
+    format: wgpu::TextureFormat,
+    sample_count: u32,
+) -> wgpu::RenderPipeline {
+    create_pipeline(
+        device,
+        "Color Render Pipeline",
+        layout,
+        shader,
+        ("vs_color_main", ColorVertex::desc()),
+        (
+            "fs_color_main",
+            wgpu::ColorTargetState {
+                format,
+                blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                write_mask: wgpu::ColorWrites::ALL,
+            },
+        ),
+        sample_count,
+    )
+}
+
+// Sprite pipelines differ by blend mode, target format and sample count.
+type SpritePipelineKey = (BlendMode, wgpu::TextureFormat, u32);
+
+fn create_sprite_pipeline(
+    device: &wgpu::Device,
+    layout: &wgpu::PipelineLayout,
+    shader: &wgpu::ShaderModule,
+    (mode, format, sample_count): SpritePipelineKey,
+) -> wgpu::RenderPipeline {
+    create_pipeline(
+        device,
+        &format!("Sprite Render Pipeline ({mode:?}, {sample_count}x)"),
+        layout,
+        shader,
+        ("vs_sprite_main", SpriteVertex::desc()),
+        (
+            sprite_fragment_entry(mode),
+            wgpu::ColorTargetState {
+                format,
+                blend: Some(sprite_blend_state(mode)),
+                write_mask: wgpu::ColorWrites::ALL,
+            },
+        ),
+        sample_count,
+    )
+}
+
+// Window settings closest to `options` the adapter supports, `None` if it cannot
+// present to `surface` at all. Falling back from what was asked for is logged.
+fn surface_config(
+    surface: &wgpu::Surface,
+    adapter: &wgpu::Adapter,
+    options: &SurfaceOptions,
+    size: winit::dpi::PhysicalSize<u32>,
+) -> Option<wgpu::SurfaceConfiguration> {
+    let caps = surface.get_capabilities(adapter);
+    let format = choose_format(options.format, &caps.formats)?;
+    if !options.format.matches(format) {
+        log::warn!(
+            "{:?} surface formats are not supported, using {format:?}",
+            options.format
+        );
+    }
+    let present_mode = choose_present_mode(options.present_mode, &caps.present_modes);
+    if present_mode != options.present_mode.to_wgpu() {
+        log::warn!(
+            "{:?} present mode is not supported, using {present_mode:?}",
+            options.present_mode
+        );
+    }
+    Some(wgpu::SurfaceConfiguration {
+        // Copying out of the frame is needed for screenshots.
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+            | (caps.usages & wgpu::TextureUsages::COPY_SRC),
+        format,
+        width: size.width,
+        height: size.height,
+        present_mode,
+        alpha_mode: *caps.alpha_modes.first()?,
+        view_formats: vec![],
+        desired_maximum_frame_latency: options.max_frame_latency.max(1),
+    })
+}
+
+// The supported sample count closest to `requested` for drawing into `format`.
+fn msaa_sample_count(
+    adapter: &wgpu::Adapter,
+    device: &wgpu::Device,
+    format: wgpu::TextureFormat,
+    requested: u32,
+) -> u32 {
+    let flags = adapter.get_texture_format_features(format).flags;
+    // Without adapter specific format features only 4x is allowed.
+    let adapter_specific = device
+        .features()
+        .contains(wgpu::Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES);
+    let count = choose_sample_count(requested, |count| {
+        (adapter_specific || count == 4) && flags.sample_count_supported(count)
+    });
+    if count != requested.max(1) {
+        log::warn!("{requested}x MSAA is not supported for {format:?}, using {count}x");
+    }
+    count
+}
+
+// Multisampled texture the window is drawn into and resolved from, `None` without MSAA.
+fn create_msaa_target(
+    device: &wgpu::Device,
+    format: wgpu::TextureFormat,
+    size: winit::dpi::PhysicalSize<u32>,
+    sample_count: u32,
+) -> Option<wgpu::TextureView> {
+    (sample_count > 1).then(|| {
+        device
+            .create_texture(&wgpu::TextureDescriptor {
+                label: Some("MSAA Target"),
+                size: wgpu::Extent3d {
+                    width: size.width,
+                    height: size.height,
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count,
+                dimension: wgpu::TextureDimension::D2,
+                format,
+                usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+                view_formats: &[],
+            })
+            .create_view(&wgpu::TextureViewDescriptor::default())
+    })
+}
+
+/// Parse and validate WGSL with naga so a bad edit is reported instead of
+/// tripping wgpu's device error handler.
+fn validate_wgsl(source: &str) -> Result<(), String> {
+    let module = naga::front::wgsl::parse_str(source).map_err(|e| e.emit_to_string(source))?;
+    naga::valid::Validator::new(
+        naga::valid::ValidationFlags::all(),
+        naga::valid::Capabilities::all(),
+    )
+    .validate(&module)
+    .map_err(|e| e.emit_to_string(source))?;
+    Ok(())
+}
+
+// The error caught since the matching `push_error_scope`. Native wgpu resolves the
+// scope right away; should it still be pending, the error goes to the device handler.
+fn pop_error_scope(device: &wgpu::Device) -> Option<wgpu::Error> {
+    let mut error = std::pin::pin!(device.pop_error_scope());
+    let mut context = std::task::Context::from_waker(std::task::Waker::noop());
+    match error.as_mut().poll(&mut context) {
+        std::task::Poll::Ready(error) => error,
+        std::task::Poll::Pending => None,
+    }
+}
+
+// The window a renderer presents to.
+struct WindowSurface {
+    window: Arc<Window>,
     surface: wgpu::Surface<'static>,
+    config: wgpu::SurfaceConfiguration,
+}
+
+// Where the frames drawn for `RenderTarget::Window` go.
+enum Target {
+    Window(WindowSurface),
+    // Stands in for the window when running headless.
+    Offscreen(GpuTexture),
+}
+
+// The texture a frame is drawn into: the window's next image, presented once drawn,
+// or the offscreen texture.
+enum Frame<'a> {
+    Window(wgpu::SurfaceTexture),
+    Offscreen(&'a wgpu::Texture),
+}
+
+impl Frame<'_> {
+    fn texture(&self) -> &wgpu::Texture {
+        match self {
+            Frame::Window(output) => &output.texture,
+            Frame::Offscreen(texture) => texture,
+        }
+    }
+}
+
+pub struct Renderer {
+    target: Target,
+    adapter: wgpu::Adapter,
     device: wgpu::Device,
     queue: wgpu::Queue,
-    config: wgpu::SurfaceConfiguration,
+    // Of the window surface or the offscreen texture.
+    format: wgpu::TextureFormat,
     size: winit::dpi::PhysicalSize<u32>,
-    window: Arc<Window>,
+    options: SurfaceOptions,
+    // What `options.msaa_samples` ended up as, for everything drawn to the window.
+    sample_count: u32,
+    msaa_target: Option<wgpu::TextureView>,
     color_vertex_buffer: wgpu::Buffer,
     sprite_vertex_buffer: wgpu::Buffer,
     sprite_index_buffer: wgpu::Buffer,
+    sprite_capacity: u64,
+    sprite_batches: Vec<SpriteBatch>,
+    color_render_pipeline_layout: wgpu::PipelineLayout,
+    sprite_render_pipeline_layout: wgpu::PipelineLayout,
     color_render_pipeline: wgpu::RenderPipeline,
-    sprite_render_pipeline: wgpu::RenderPipeline,
-    texture_bind_group: wgpu::BindGroup,
-    texture: wgpu::Texture,
+    // Created the first time a variant is drawn.
+    sprite_pipelines: HashMap<SpritePipelineKey, wgpu::RenderPipeline>,
+    shader: wgpu::ShaderModule,
+    // Source the pipelines are rebuilt from when it changes, and the version last built.
+    shader_source: Option<(Handle<Shader>, u64)>,
+    texture_bind_group_layout: wgpu::BindGroupLayout,
+    sampler: wgpu::Sampler,
+    // GPU copies of loaded images, with the asset version they were uploaded from.
+    textures: HashMap<HandleId, (u64, GpuTexture)>,
+    camera_bind_group_layout: wgpu::BindGroupLayout,
+    views: Vec<CameraView>,
+    // Taken from the next frame.
+    screenshot_requests: Vec<ScreenshotTarget>,
+    // Frames being copied back for screenshots.
+    readbacks: Vec<Readback>,
 }
 
-impl Renderer {
-    pub async fn new(window: Arc<Window>, texture_path: &str) -> Self {
-        let size = window.inner_size();
+fn create_instance(backends: wgpu::Backends) -> wgpu::Instance {
+    wgpu::Instance::new(wgpu::InstanceDescriptor {
+        backends,
+        dx12_shader_compiler: Default::default(),
+        flags: wgpu::InstanceFlags::default(),
+        gles_minor_version: wgpu::Gles3MinorVersion::default(),
+    })
+}
 
-        let instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
-            #[cfg(target_arch = "wasm32")]
-            backends: wgpu::Backends::GL,
-            #[cfg(not(target_arch = "wasm32"))]
-            backends: wgpu::Backends::PRIMARY,
-            dx12_shader_compiler: Default::default(),
-            flags: wgpu::InstanceFlags::default(),
-            gles_minor_version: wgpu::Gles3MinorVersion::default(),
-        });
+/// Why a [`Renderer`] could not be created or could not draw a frame.
+#[derive(Debug)]
+pub enum RendererError {
+    CreateSurface(wgpu::CreateSurfaceError),
+    /// No GPU adapter, not even a software fallback, can render to the target.
+    NoAdapter,
+    RequestDevice(wgpu::RequestDeviceError),
+    /// The adapter cannot present to the window at all.
+    UnsupportedSurface,
+    /// Getting the next frame failed in a way that reconfiguring the surface does not fix.
+    Surface(wgpu::SurfaceError),
+}
 
-        let surface = instance
-            .create_surface(window.clone())
-            .expect("Failed to create surface");
+impl fmt::Display for RendererError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RendererError::CreateSurface(err) => write!(f, "failed to create surface: {err}"),
+            RendererError::NoAdapter => write!(f, "no graphics adapter available"),
+            RendererError::RequestDevice(err) => {
+                write!(f, "failed to create graphics device: {err}")
+            }
+            RendererError::UnsupportedSurface => {
+                write!(f, "the graphics adapter cannot present to this window")
+            }
+            RendererError::Surface(err) => write!(f, "failed to get the next frame: {err}"),
+        }
+    }
+}
+
+impl std::error::Error for RendererError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            RendererError::CreateSurface(err) => Some(err),
+            RendererError::RequestDevice(err) => Some(err),
+            RendererError::Surface(err) => Some(err),
+            RendererError::NoAdapter | RendererError::UnsupportedSurface => None,
+        }
+    }
+}
 
+// Prefer a hardware adapter, fall back to a software one.
+async fn request_adapter(
+    instance: &wgpu::Instance,
+    surface: Option<&wgpu::Surface<'_>>,
+) -> Result<wgpu::Adapter, RendererError> {
+    for force_fallback_adapter in [false, true] {
         let adapter = instance
             .request_adapter(&wgpu::RequestAdapterOptions {
                 power_preference: wgpu::PowerPreference::default(),
-                compatible_surface: Some(&surface),
-                force_fallback_adapter: false,
+                compatible_surface: surface,
+                force_fallback_adapter,
             })
-            .await
-            .unwrap();
+            .await;
+        if let Some(adapter) = adapter {
+            return Ok(adapter);
+        }
+    }
+    Err(RendererError::NoAdapter)
+}
 
-        let (device, queue) = adapter
+// Ask for the default limits first and settle for lower ones on older hardware.
+async fn request_device(
+    adapter: &wgpu::Adapter,
+) -> Result<(wgpu::Device, wgpu::Queue), RendererError> {
+    let limits = if cfg!(target_arch = "wasm32") {
+        vec![wgpu::Limits::downlevel_webgl2_defaults()]
+    } else {
+        vec![
+            wgpu::Limits::default(),
+            wgpu::Limits::downlevel_defaults(),
+            wgpu::Limits::downlevel_webgl2_defaults(),
+        ]
+    };
+    let mut last_error = None;
+    for required_limits in limits {
+        let result = adapter
             .request_device(
                 &wgpu::DeviceDescriptor {
-                    required_features: wgpu::Features::empty(),
-                    required_limits: if cfg!(target_arch = "wasm32") {
-                        wgpu::Limits::downlevel_webgl2_defaults()
-                    } else {
-                        wgpu::Limits::default()
-                    },
+                    // Lets MSAA use every sample count the adapter supports, not just 4x.
+                    required_features: adapter.features()
+                        & wgpu::Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES,
+                    required_limits: required_limits.using_resolution(adapter.limits()),
                     label: None,
                 },
                 None,
             )
-            .await
-            .unwrap();
+            .await;
+        match result {
+            Ok(device) => return Ok(device),
+            Err(err) => {
+                log::warn!("failed to create device, retrying with lower limits: {err}");
+                last_error = Some(err);
+            }
+        }
+    }
+    Err(RendererError::RequestDevice(
+        last_error.expect("at least one set of limits is tried"),
+    ))
+}
 
-        let surface_caps = surface.get_capabilities(&adapter);
+impl Renderer {
+    pub async fn new(window: Arc<Window>, options: SurfaceOptions) -> Result<Self, RendererError> {
+        let size = window.inner_size();
 
-        let surface_format = surface_caps
-            .formats
-            .iter()
-            .find(|f| f.is_srgb())
-            .copied()
-            .unwrap_or(surface_caps.formats[0]);
-
-        let config = wgpu::SurfaceConfiguration {
-            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
-            format: surface_format,
-            width: size.width,
-            height: size.height,
-            present_mode: surface_caps.present_modes[0],
-            alpha_mode: surface_caps.alpha_modes[0],
-            view_formats: vec![],
-            desired_maximum_frame_latency: 2,
-        };
+        let instance = create_instance(if cfg!(target_arch = "wasm32") {
+            wgpu::Backends::GL
+        } else {
+            wgpu::Backends::PRIMARY
+        });
 
+        let surface = instance
+            .create_surface(window.clone())
+            .map_err(RendererError::CreateSurface)?;
+
+        let adapter = request_adapter(&instance, Some(&surface)).await?;
+        let (device, queue) = request_device(&adapter).await?;
+
+        let config = surface_config(&surface, &adapter, &options, size)
+            .ok_or(RendererError::UnsupportedSurface)?;
         surface.configure(&device, &config);
 
+        let format = config.format;
+        let surface = WindowSurface {
+            window,
+            surface,
+            config,
+        };
+        Ok(Self::from_device(
+            adapter,
+            device,
+            queue,
+            format,
+            size,
+            Some(surface),
+            options,
+        ))
+    }
+
+    /// A renderer without a window. Cameras targeting [`RenderTarget::Window`] draw into
+    /// an offscreen texture of `width` x `height` instead, read back with
+    /// [`read_pixels`](Self::read_pixels). Falls back to a software adapter when there is
+    /// no GPU.
+    pub async fn headless(width: u32, height: u32) -> Result<Self, RendererError> {
+        let instance = create_instance(if cfg!(target_arch = "wasm32") {
+            wgpu::Backends::GL
+        } else {
+            wgpu::Backends::all()
+        });
+        let adapter = request_adapter(&instance, None).await?;
+        let (device, queue) = request_device(&adapter).await?;
+        let size = winit::dpi::PhysicalSize::new(width.max(1), height.max(1));
+        Ok(Self::from_device(
+            adapter,
+            device,
+            queue,
+            GpuTexture::FORMAT,
+            size,
+            None,
+            SurfaceOptions::default(),
+        ))
+    }
+
+    // Everything that does not depend on where the frame ends up.
+    fn from_device(
+        adapter: wgpu::Adapter,
+        device: wgpu::Device,
+        queue: wgpu::Queue,
+        format: wgpu::TextureFormat,
+        size: winit::dpi::PhysicalSize<u32>,
+        surface: Option<WindowSurface>,
+        options: SurfaceOptions,
+    ) -> Self {
         let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
             label: Some("Shader"),
             source: wgpu::ShaderSource::Wgsl(include_str!("../shader.wgsl").into()),
         });
 
+        let camera_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                entries: &[wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::VERTEX,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                }],
+                label: Some("camera_bind_group_layout"),
+            });
+
         let color_render_pipeline_layout =
             device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
                 label: Some("Color Render Pipeline Layout"),
-                bind_group_layouts: &[],
+                bind_group_layouts: &[&camera_bind_group_layout],
                 push_constant_ranges: &[],
             });
 
-        let color_render_pipeline =
-            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-                label: Some("Color Render Pipeline"),
-                layout: Some(&color_render_pipeline_layout),
-                vertex: wgpu::VertexState {
-                    module: &shader,
-                    entry_point: "vs_color_main",
-                    buffers: &[ColorVertex::desc()],
-                    compilation_options: Default::default(),
-                },
-                fragment: Some(wgpu::FragmentState {
-                    module: &shader,
-                    entry_point: "fs_color_main",
-                    targets: &[Some(wgpu::ColorTargetState {
-                        format: config.format,
-                        blend: Some(wgpu::BlendState::REPLACE),
-                        write_mask: wgpu::ColorWrites::ALL,
-                    })],
-                    compilation_options: Default::default(),
-                }),
-                primitive: wgpu::PrimitiveState {
-                    topology: wgpu::PrimitiveTopology::TriangleList,
-                    strip_index_format: None,
-                    front_face: wgpu::FrontFace::Ccw,
-                    cull_mode: Some(wgpu::Face::Back),
-                    polygon_mode: wgpu::PolygonMode::Fill,
-                    unclipped_depth: false,
-                    conservative: false,
-                },
-                depth_stencil: None,
-                multisample: wgpu::MultisampleState {
-                    count: 1,
-                    mask: !0,
-                    alpha_to_coverage_enabled: false,
-                },
-                multiview: None,
-            });
+        let sample_count = msaa_sample_count(&adapter, &device, format, options.msaa_samples);
+        let msaa_target = create_msaa_target(&device, format, size, sample_count);
+        let color_render_pipeline = create_color_pipeline(
+            &device,
+            &color_render_pipeline_layout,
+            &shader,
+            format,
+            sample_count,
+        );
 
         let texture_bind_group_layout =
             device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
@@ -267,102 +769,19 @@ impl Renderer {
         let sprite_render_pipeline_layout =
             device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
                 label: Some("Sprite Render Pipeline Layout"),
-                bind_group_layouts: &[&texture_bind_group_layout],
+                bind_group_layouts: &[&camera_bind_group_layout, &texture_bind_group_layout],
                 push_constant_ranges: &[],
             });
 
-        let sprite_render_pipeline =
-            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-                label: Some("Sprite Render Pipeline"),
-                layout: Some(&sprite_render_pipeline_layout),
-                vertex: wgpu::VertexState {
-                    module: &shader,
-                    entry_point: "vs_sprite_main",
-                    buffers: &[SpriteVertex::desc()],
-                    compilation_options: Default::default(),
-                },
-                fragment: Some(wgpu::FragmentState {
-                    module: &shader,
-                    entry_point: "fs_sprite_main",
-                    targets: &[Some(wgpu::ColorTargetState {
-                        format: config.format,
-                        blend: Some(wgpu::BlendState::REPLACE),
-                        write_mask: wgpu::ColorWrites::ALL,
-                    })],
-                    compilation_options: Default::default(),
-                }),
-                primitive: wgpu::PrimitiveState {
-                    topology: wgpu::PrimitiveTopology::TriangleList,
-                    strip_index_format: None,
-                    front_face: wgpu::FrontFace::Ccw,
-                    cull_mode: Some(wgpu::Face::Back),
-                    polygon_mode: wgpu::PolygonMode::Fill,
-                    unclipped_depth: false,
-                    conservative: false,
-                },
-                depth_stencil: None,
-                multisample: wgpu::MultisampleState {
-                    count: 1,
-                    mask: !0,
-                    alpha_to_coverage_enabled: false,
-                },
-                multiview: None,
-            });
-
         let color_vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
             label: Some("Color Vertex Buffer"),
             contents: bytemuck::cast_slice(TRIANGLE_VERTICES),
             usage: wgpu::BufferUsages::VERTEX,
         });
 
-        let sprite_vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Sprite Vertex Buffer"),
-            contents: bytemuck::cast_slice(SPRITE_VERTICES),
-            usage: wgpu::BufferUsages::VERTEX,
-        });
-
-        let sprite_index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Sprite Index Buffer"),
-            contents: bytemuck::cast_slice(SPRITE_INDICES),
-            usage: wgpu::BufferUsages::INDEX,
-        });
+        let (sprite_vertex_buffer, sprite_index_buffer) =
+            sprite_buffers(&device, INITIAL_SPRITE_CAPACITY);
 
-        let img = image::open(texture_path).expect("Failed to load texture image");
-        let rgba = img.to_rgba8();
-        let dimensions = rgba.dimensions();
-        let texture_size = wgpu::Extent3d {
-            width: dimensions.0,
-            height: dimensions.1,
-            depth_or_array_layers: 1,
-        };
-        let texture = device.create_texture(&wgpu::TextureDescriptor {
-            size: texture_size,
-            mip_level_count: 1,
-            sample_count: 1,
-            dimension: wgpu::TextureDimension::D2,
-            format: wgpu::TextureFormat::Rgba8UnormSrgb,
-            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
-            label: Some("diffuse_texture"),
-            view_formats: &[],
-        });
-
-        queue.write_texture(
-            wgpu::ImageCopyTexture {
-                texture: &texture,
-                mip_level: 0,
-                origin: wgpu::Origin3d::ZERO,
-                aspect: wgpu::TextureAspect::All,
-            },
-            &rgba,
-            wgpu::ImageDataLayout {
-                offset: 0,
-                bytes_per_row: Some(dimensions.0 * 4),
-                rows_per_image: Some(dimensions.1),
-            },
-            texture_size,
-        );
-
-        let texture_view = texture.create_view(&wgpu::TextureViewDescriptor::default());
         let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
             address_mode_u: wgpu::AddressMode::ClampToEdge,
             address_mode_v: wgpu::AddressMode::ClampToEdge,
@@ -373,61 +792,443 @@ impl Renderer {
             ..Default::default()
         });
 
-        let texture_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            layout: &texture_bind_group_layout,
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: wgpu::BindingResource::TextureView(&texture_view),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: wgpu::BindingResource::Sampler(&sampler),
-                },
-            ],
-            label: Some("diffuse_bind_group"),
-        });
+        let target = match surface {
+            Some(surface) => Target::Window(surface),
+            None => Target::Offscreen(GpuTexture::render_target(
+                &device,
+                [size.width, size.height],
+                &texture_bind_group_layout,
+                &sampler,
+            )),
+        };
 
         Self {
-            surface,
+            target,
+            adapter,
             device,
             queue,
-            config,
+            format,
             size,
-            window,
+            options,
+            sample_count,
+            msaa_target,
             color_vertex_buffer,
             sprite_vertex_buffer,
             sprite_index_buffer,
+            sprite_capacity: INITIAL_SPRITE_CAPACITY,
+            sprite_batches: Vec::new(),
+            color_render_pipeline_layout,
+            sprite_render_pipeline_layout,
             color_render_pipeline,
-            sprite_render_pipeline,
-            texture_bind_group,
-            texture,
+            sprite_pipelines: HashMap::new(),
+            shader,
+            shader_source: None,
+            texture_bind_group_layout,
+            sampler,
+            textures: HashMap::new(),
+            camera_bind_group_layout,
+            views: Vec::new(),
+            screenshot_requests: Vec::new(),
+            readbacks: Vec::new(),
         }
     }
 
-    pub fn window(&self) -> &Window {
-        &self.window
+    /// `None` when headless.
+    pub fn window(&self) -> Option<&Window> {
+        match &self.target {
+            Target::Window(surface) => Some(&surface.window),
+            Target::Offscreen(_) => None,
+        }
     }
 
     pub fn size(&self) -> winit::dpi::PhysicalSize<u32> {
         self.size
     }
 
+    /// The options last asked for, which may be more than the adapter supports.
+    pub fn surface_options(&self) -> &SurfaceOptions {
+        &self.options
+    }
+
+    /// Format of the frames drawn for [`RenderTarget::Window`].
+    pub fn format(&self) -> wgpu::TextureFormat {
+        self.format
+    }
+
+    /// MSAA samples per pixel the window is actually drawn with.
+    pub fn sample_count(&self) -> u32 {
+        self.sample_count
+    }
+
+    /// How frames are actually presented to the window, `None` when headless.
+    pub fn present_mode(&self) -> Option<wgpu::PresentMode> {
+        match &self.target {
+            Target::Window(surface) => Some(surface.config.present_mode),
+            Target::Offscreen(_) => None,
+        }
+    }
+
+    /// Reconfigure the window surface and MSAA target for `options`, keeping everything
+    /// else (textures, cameras, pipelines that still fit) as is. Headless renderers
+    /// always draw into [`GpuTexture::FORMAT`] and only apply the MSAA sample count.
+    /// Settings the adapter does not support fall back to the closest ones it does, with
+    /// a warning; [`present_mode`](Self::present_mode), [`format`](Self::format) and
+    /// [`sample_count`](Self::sample_count) tell what was applied.
+    pub fn set_surface_options(&mut self, options: SurfaceOptions) {
+        let previous = (self.format, self.sample_count);
+        if let Target::Window(surface) = &mut self.target {
+            match surface_config(&surface.surface, &self.adapter, &options, self.size) {
+                Some(config) => {
+                    surface.config = config;
+                    surface.surface.configure(&self.device, &surface.config);
+                    self.format = surface.config.format;
+                }
+                None => log::error!(
+                    "the adapter cannot present with these options, keeping the previous ones"
+                ),
+            }
+        }
+        let sample_count = msaa_sample_count(
+            &self.adapter,
+            &self.device,
+            self.format,
+            options.msaa_samples,
+        );
+        self.sample_count = sample_count;
+        self.msaa_target = create_msaa_target(&self.device, self.format, self.size, sample_count);
+        if previous != (self.format, sample_count) {
+            self.color_render_pipeline = create_color_pipeline(
+                &self.device,
+                &self.color_render_pipeline_layout,
+                &self.shader,
+                self.format,
+                sample_count,
+            );
+        }
+        self.options = options;
+    }
+
     pub fn resize(&mut self, new_size: winit::dpi::PhysicalSize<u32>) {
         if new_size.width > 0 && new_size.height > 0 {
             self.size = new_size;
-            self.config.width = new_size.width;
-            self.config.height = new_size.height;
-            self.surface.configure(&self.device, &self.config);
+            match &mut self.target {
+                Target::Window(surface) => {
+                    surface.config.width = new_size.width;
+                    surface.config.height = new_size.height;
+                    surface.surface.configure(&self.device, &surface.config);
+                }
+                Target::Offscreen(texture) => {
+                    *texture = GpuTexture::render_target(
+                        &self.device,
+                        [new_size.width, new_size.height],
+                        &self.texture_bind_group_layout,
+                        &self.sampler,
+                    );
+                }
+            }
+            self.msaa_target =
+                create_msaa_target(&self.device, self.format, new_size, self.sample_count);
         }
     }
 
-    pub fn update(&mut self) {}
+    /// Rebuild the pipelines from `shader` whenever it is (re)loaded, instead of the
+    /// copy of `shader.wgsl` baked in at compile time.
+    pub fn watch_shader(&mut self, shader: Handle<Shader>) {
+        self.shader_source = Some((shader, 0));
+    }
+
+    /// Drop GPU textures whose asset was freed and recompile the shader if it changed.
+    pub fn prepare_assets(&mut self, assets: &AssetServer) {
+        self.textures.retain(|id, _| assets.contains(*id));
+
+        if let Some((handle, built)) = &self.shader_source
+            && let Some(shader) = assets.get(handle)
+        {
+            let version = assets.version(handle.id());
+            if version != *built {
+                let handle = handle.clone();
+                match self.rebuild_pipelines(&shader.source) {
+                    Ok(()) => log::info!("shader reloaded"),
+                    Err(err) => log::error!(
+                        "shader failed to reload, keeping the previous pipelines:\n{err}"
+                    ),
+                }
+                self.shader_source = Some((handle, version));
+            }
+        }
+    }
 
-    pub fn render(&mut self) -> Result<(), wgpu::SurfaceError> {
-        let output = self.surface.get_current_texture()?;
-        let view = output
-            .texture
+    // Upload an image the first time it is drawn and again after it was reloaded.
+    // Render targets are left alone, their contents live on the GPU.
+    fn ensure_texture(&mut self, handle: &Handle<Image>, image: &Image, version: u64) {
+        let stale = self
+            .textures
+            .get(&handle.id())
+            .is_none_or(|(uploaded, texture)| *uploaded != version && !texture.is_render_target());
+        if stale {
+            let texture = GpuTexture::from_image(
+                &self.device,
+                &self.queue,
+                image,
+                &self.texture_bind_group_layout,
+                &self.sampler,
+            );
+            self.textures.insert(handle.id(), (version, texture));
+        }
+    }
+
+    /// Collect every entity with a [`Sprite`] and [`Transform2d`] into vertex batches for
+    /// the next frame. Sprites are drawn back to front by `translation[2]`; those sharing
+    /// a texture and blend mode are drawn with a single call. Sprites whose image is not
+    /// loaded yet are skipped.
+    pub fn prepare_sprites(&mut self, world: &World, assets: &AssetServer) {
+        let mut quads = Vec::new();
+        for (_, (sprite, transform)) in world.query::<(Sprite, Transform2d)>().iter() {
+            let Some((handle, image, rect)) = resolve_sprite(sprite, assets) else {
+                continue;
+            };
+            self.ensure_texture(handle, image, assets.version(handle.id()));
+            let quad = sprite_quad(sprite, transform, rect, [image.width, image.height]);
+            let key = (sprite.blend_mode, handle.id());
+            quads.push((transform.translation[2], key, quad));
+
+            // Same key and depth, and the sort below is stable, so it lands right after.
+            if let Some(fade) = sprite.crossfade
+                && matches!(sprite.source, SpriteSource::Atlas { .. })
+            {
+                let mut faded = sprite.clone();
+                faded.set_atlas_index(fade.index);
+                faded.color[3] *= fade.weight;
+                if let Some((_, _, rect)) = resolve_sprite(&faded, assets) {
+                    let quad = sprite_quad(&faded, transform, rect, [image.width, image.height]);
+                    quads.push((transform.translation[2], key, quad));
+                }
+            }
+        }
+        quads.sort_by(|a, b| a.0.total_cmp(&b.0).then(a.1.cmp(&b.1)));
+
+        let mut vertices = Vec::with_capacity(quads.len() * 4);
+        let mut indices = Vec::with_capacity(quads.len() * 6);
+        self.sprite_batches.clear();
+        for (_, (blend_mode, texture), quad) in quads {
+            let base = vertices.len() as u32;
+            vertices.extend_from_slice(&quad);
+            let start = indices.len() as u32;
+            indices.extend_from_slice(&[base, base + 1, base + 2, base + 2, base + 3, base]);
+            let end = indices.len() as u32;
+            match self.sprite_batches.last_mut() {
+                Some(batch) if batch.texture == texture && batch.blend_mode == blend_mode => {
+                    batch.indices.end = end
+                }
+                _ => self.sprite_batches.push(SpriteBatch {
+                    texture,
+                    blend_mode,
+                    indices: start..end,
+                }),
+            }
+        }
+
+        let needed = (vertices.len() / 4) as u64;
+        if needed > self.sprite_capacity {
+            self.sprite_capacity = needed.next_power_of_two();
+            (self.sprite_vertex_buffer, self.sprite_index_buffer) =
+                sprite_buffers(&self.device, self.sprite_capacity);
+        }
+        self.queue.write_buffer(
+            &self.sprite_vertex_buffer,
+            0,
+            bytemuck::cast_slice(&vertices),
+        );
+        self.queue
+            .write_buffer(&self.sprite_index_buffer, 0, bytemuck::cast_slice(&indices));
+    }
+
+    // Keeps the current pipelines if the new source does not validate or does not fit
+    // them. naga finds syntax and type errors, the error scope everything wgpu checks on
+    // top, like missing entry points and bindings that differ from the layouts.
+    fn rebuild_pipelines(&mut self, source: &str) -> Result<(), String> {
+        validate_wgsl(source)?;
+        self.device.push_error_scope(wgpu::ErrorFilter::Validation);
+        let shader = self
+            .device
+            .create_shader_module(wgpu::ShaderModuleDescriptor {
+                label: Some("Shader"),
+                source: wgpu::ShaderSource::Wgsl(source.into()),
+            });
+        let color_render_pipeline = create_color_pipeline(
+            &self.device,
+            &self.color_render_pipeline_layout,
+            &shader,
+            self.format,
+            self.sample_count,
+        );
+        // Every blend mode, so modes first drawn after the reload build too.
+        let keys = BLEND_MODES
+            .map(|mode| (mode, self.format, self.sample_count))
+            .into_iter()
+            .chain(self.sprite_pipelines.keys().copied());
+        let mut sprite_pipelines = HashMap::new();
+        for key in keys {
+            let layout = &self.sprite_render_pipeline_layout;
+            let pipeline = create_sprite_pipeline(&self.device, layout, &shader, key);
+            sprite_pipelines.insert(key, pipeline);
+        }
+        if let Some(err) = pop_error_scope(&self.device) {
+            return Err(err.to_string());
+        }
+        self.shader = shader;
+        self.color_render_pipeline = color_render_pipeline;
+        self.sprite_pipelines = sprite_pipelines;
+        Ok(())
+    }
+
+    fn ensure_render_target(&mut self, handle: &Handle<Image>, image: &Image, version: u64) {
+        let size = [image.width, image.height];
+        let ready = self.textures.get(&handle.id()).is_some_and(|(_, texture)| {
+            let extent = texture.texture.size();
+            texture.is_render_target() && [extent.width, extent.height] == size
+        });
+        if !ready {
+            let texture = GpuTexture::render_target(
+                &self.device,
+                size,
+                &self.texture_bind_group_layout,
+                &self.sampler,
+            );
+            self.textures.insert(handle.id(), (version, texture));
+        }
+    }
+
+    /// Collect every [`Camera2d`] in the world for the next frame. Without any, a default
+    /// camera draws to the window. Cameras whose target image is not loaded yet are skipped.
+    pub fn prepare_cameras(&mut self, world: &World, assets: &AssetServer) {
+        let mut cameras: Vec<Camera2d> = world
+            .query::<Camera2d>()
+            .iter()
+            .map(|(_, camera)| camera.clone())
+            .collect();
+        if cameras.is_empty() {
+            cameras.push(Camera2d::default());
+        }
+
+        let mut prepared = Vec::with_capacity(cameras.len());
+        for camera in cameras {
+            let (target, target_size) = match &camera.target {
+                RenderTarget::Window => (None, [self.size.width, self.size.height]),
+                RenderTarget::Image(handle) => {
+                    let Some(image) = assets.get(handle) else {
+                        continue;
+                    };
+                    self.ensure_render_target(handle, image, assets.version(handle.id()));
+                    (Some(handle.id()), [image.width, image.height])
+                }
+            };
+            prepared.push((camera, target, target_size));
+        }
+
+        self.views.truncate(prepared.len());
+        for (index, (camera, target, target_size)) in prepared.into_iter().enumerate() {
+            if let Some(view) = self.views.get_mut(index) {
+                view.camera = camera;
+                view.target = target;
+                view.target_size = target_size;
+                continue;
+            }
+            let buffer = self.device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("Camera Buffer"),
+                size: std::mem::size_of::<CameraUniform>() as u64,
+                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            });
+            let bind_group = self.device.create_bind_group(&wgpu::BindGroupDescriptor {
+                layout: &self.camera_bind_group_layout,
+                entries: &[wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: buffer.as_entire_binding(),
+                }],
+                label: Some("camera_bind_group"),
+            });
+            self.views.push(CameraView {
+                camera,
+                target,
+                target_size,
+                buffer,
+                bind_group,
+            });
+        }
+    }
+
+    pub fn update(&mut self) {
+        for view in &mut self.views {
+            // The window may have been resized since the cameras were prepared.
+            if view.target.is_none() {
+                view.target_size = [self.size.width, self.size.height];
+            }
+            let uniform = CameraUniform::new(&view.camera, view.target_size);
+            self.queue
+                .write_buffer(&view.buffer, 0, bytemuck::cast_slice(&[uniform]));
+        }
+    }
+
+    // Create the sprite pipelines the next frame needs.
+    fn ensure_sprite_pipelines(&mut self) {
+        let targets: HashSet<(wgpu::TextureFormat, u32)> = self
+            .views
+            .iter()
+            .map(|view| match view.target {
+                None => (self.format, self.sample_count),
+                Some(_) => (GpuTexture::FORMAT, 1),
+            })
+            .collect();
+        for (format, sample_count) in targets {
+            for batch in &self.sprite_batches {
+                let key = (batch.blend_mode, format, sample_count);
+                if !self.sprite_pipelines.contains_key(&key) {
+                    let pipeline = create_sprite_pipeline(
+                        &self.device,
+                        &self.sprite_render_pipeline_layout,
+                        &self.shader,
+                        key,
+                    );
+                    self.sprite_pipelines.insert(key, pipeline);
+                }
+            }
+        }
+    }
+
+    /// Draw every camera, those rendering into images first, then present the window.
+    /// A lost or outdated window surface is reconfigured and the frame skipped.
+    pub fn render(&mut self) -> Result<(), RendererError> {
+        // A minimized window has no frame to draw into.
+        if let Target::Window(surface) = &self.target {
+            let size = surface.window.inner_size();
+            if size.width == 0 || size.height == 0 {
+                return Ok(());
+            }
+        }
+        self.ensure_sprite_pipelines();
+        if !self.readbacks.is_empty() {
+            self.device.poll(wgpu::Maintain::Poll);
+            self.readbacks.retain_mut(|readback| !readback.try_finish());
+        }
+
+        let frame = match &self.target {
+            Target::Window(surface) => match surface.surface.get_current_texture() {
+                Ok(output) => Frame::Window(output),
+                Err(wgpu::SurfaceError::Lost | wgpu::SurfaceError::Outdated) => {
+                    surface.surface.configure(&self.device, &surface.config);
+                    return Ok(());
+                }
+                Err(wgpu::SurfaceError::Timeout) => {
+                    log::warn!("timed out waiting for the next frame, skipping it");
+                    return Ok(());
+                }
+                Err(err) => return Err(RendererError::Surface(err)),
+            },
+            Target::Offscreen(offscreen) => Frame::Offscreen(&offscreen.texture),
+        };
+        let surface_view = frame
+            .texture()
             .create_view(&wgpu::TextureViewDescriptor::default());
 
         let mut encoder = self
@@ -436,19 +1237,48 @@ impl Renderer {
                 label: Some("Render Encoder"),
             });
 
-        {
+        let sampled: HashSet<HandleId> = self.sprite_batches.iter().map(|b| b.texture).collect();
+        let cameras: Vec<_> = self
+            .views
+            .iter()
+            .map(|view| (view.camera.order, view.target))
+            .collect();
+        let mut window_drawn = false;
+        for index in render_order(&cameras, &sampled) {
+            let view = &self.views[index];
+            // With MSAA the window is drawn multisampled and resolved into the frame.
+            let (target_view, resolve_target, format, sample_count) = match view.target {
+                None => match &self.msaa_target {
+                    Some(msaa) => (msaa, Some(&surface_view), self.format, self.sample_count),
+                    None => (&surface_view, None, self.format, 1),
+                },
+                Some(id) => match self.textures.get(&id) {
+                    Some((_, texture)) => (&texture.view, None, GpuTexture::FORMAT, 1),
+                    None => continue,
+                },
+            };
+            let viewport = view.camera.viewport_rect(view.target_size);
+            if viewport.size.contains(&0) {
+                continue;
+            }
+            window_drawn |= view.target.is_none();
+            let load = match view.camera.clear_color {
+                Some([r, g, b, a]) => wgpu::LoadOp::Clear(wgpu::Color {
+                    r: r as f64,
+                    g: g as f64,
+                    b: b as f64,
+                    a: a as f64,
+                }),
+                None => wgpu::LoadOp::Load,
+            };
+
             let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
                 label: Some("Render Pass"),
                 color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                    view: &view,
-                    resolve_target: None,
+                    view: target_view,
+                    resolve_target,
                     ops: wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(wgpu::Color {
-                            r: 0.1,
-                            g: 0.2,
-                            b: 0.3,
-                            a: 1.0,
-                        }),
+                        load,
                         store: wgpu::StoreOp::Store,
                     },
                 })],
@@ -457,23 +1287,265 @@ impl Renderer {
                 timestamp_writes: None,
             });
 
-            render_pass.set_pipeline(&self.color_render_pipeline);
-            render_pass.set_vertex_buffer(0, self.color_vertex_buffer.slice(..));
-            render_pass.draw(0..TRIANGLE_VERTICES.len() as u32, 0..1);
+            if view.camera.viewport.is_some() {
+                render_pass.set_viewport(
+                    viewport.position[0] as f32,
+                    viewport.position[1] as f32,
+                    viewport.size[0] as f32,
+                    viewport.size[1] as f32,
+                    0.0,
+                    1.0,
+                );
+            }
+
+            render_pass.set_bind_group(0, &view.bind_group, &[]);
+            if view.target.is_none() {
+                render_pass.set_pipeline(&self.color_render_pipeline);
+                render_pass.set_vertex_buffer(0, self.color_vertex_buffer.slice(..));
+                render_pass.draw(0..TRIANGLE_VERTICES.len() as u32, 0..1);
+            }
 
-            render_pass.set_pipeline(&self.sprite_render_pipeline);
-            render_pass.set_bind_group(0, &self.texture_bind_group, &[]);
             render_pass.set_vertex_buffer(0, self.sprite_vertex_buffer.slice(..));
             render_pass.set_index_buffer(
                 self.sprite_index_buffer.slice(..),
-                wgpu::IndexFormat::Uint16,
+                wgpu::IndexFormat::Uint32,
             );
-            render_pass.draw_indexed(0..SPRITE_INDICES.len() as u32, 0, 0..1);
+            let mut bound_mode = None;
+            for batch in &self.sprite_batches {
+                // A texture cannot be sampled while it is being drawn into.
+                if view.target == Some(batch.texture) {
+                    continue;
+                }
+                let Some((_, texture)) = self.textures.get(&batch.texture) else {
+                    continue;
+                };
+                if bound_mode != Some(batch.blend_mode) {
+                    render_pass.set_pipeline(
+                        &self.sprite_pipelines[&(batch.blend_mode, format, sample_count)],
+                    );
+                    bound_mode = Some(batch.blend_mode);
+                }
+                render_pass.set_bind_group(1, &texture.bind_group, &[]);
+                render_pass.draw_indexed(batch.indices.clone(), 0, 0..1);
+            }
+        }
+
+        // Every camera draws into an image, still clear the window.
+        if !window_drawn {
+            let [r, g, b, a] = Camera2d::default().clear_color.unwrap_or_default();
+            encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Clear Pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: &surface_view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color {
+                            r: r as f64,
+                            g: g as f64,
+                            b: b as f64,
+                            a: a as f64,
+                        }),
+                        store: wgpu::StoreOp::Store,
+                    },
+                })],
+                depth_stencil_attachment: None,
+                occlusion_query_set: None,
+                timestamp_writes: None,
+            });
+        }
+
+        let mut readback = None;
+        if !self.screenshot_requests.is_empty() {
+            let targets = std::mem::take(&mut self.screenshot_requests);
+            let frame = frame.texture();
+            if !frame.usage().contains(wgpu::TextureUsages::COPY_SRC) {
+                log::error!("the window surface does not support screenshots");
+            } else {
+                readback = Readback::new(&self.device, &mut encoder, frame, targets);
+                if readback.is_none() {
+                    log::error!("cannot take screenshots of {:?} frames", frame.format());
+                }
+            }
         }
 
         self.queue.submit(std::iter::once(encoder.finish()));
-        output.present();
+        if let Some(mut readback) = readback {
+            readback.map();
+            self.readbacks.push(readback);
+        }
+        if let Frame::Window(output) = frame {
+            output.present();
+        }
 
         Ok(())
     }
+
+    /// Copy the last frame drawn for [`RenderTarget::Window`] back to the CPU, waiting
+    /// for the GPU. Only headless renderers keep their frames around, `None` with a window.
+    pub fn read_pixels(&self) -> Option<Image> {
+        let Target::Offscreen(offscreen) = &self.target else {
+            return None;
+        };
+        let texture = &offscreen.texture;
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Readback Encoder"),
+            });
+        let mut readback = Readback::new(&self.device, &mut encoder, texture, Vec::new())?;
+        self.queue.submit(std::iter::once(encoder.finish()));
+        readback.map();
+        self.device.poll(wgpu::Maintain::Wait);
+        let data = readback.try_read()?.ok()?;
+        Image::new(texture.width(), texture.height(), data).ok()
+    }
+
+    /// Capture the next frame and save it as PNG, or pass it to a callback:
+    /// `request_screenshot("shot.png")` or
+    /// `request_screenshot(ScreenshotTarget::callback(|image| ...))`.
+    /// The frame is read back over the following frames and encoded off the render
+    /// thread, so taking screenshots does not stall rendering.
+    pub fn request_screenshot(&mut self, target: impl Into<ScreenshotTarget>) {
+        self.screenshot_requests.push(target.into());
+    }
+
+    /// Wait for every screenshot in flight to be read back, e.g. before exiting.
+    /// Saving them may still be in progress on a background thread when this returns.
+    pub fn flush_screenshots(&mut self) {
+        if !self.readbacks.is_empty() {
+            self.device.poll(wgpu::Maintain::Wait);
+            self.readbacks.retain_mut(|readback| !readback.try_finish());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Machines without any adapter fail the GPU tests, unless they are skipped on
+    // purpose with `RUSTY_ENGINE_SKIP_GPU_TESTS`.
+    fn headless(width: u32, height: u32) -> Option<Renderer> {
+        match pollster::block_on(Renderer::headless(width, height)) {
+            Ok(renderer) => Some(renderer),
+            Err(err) if std::env::var_os("RUSTY_ENGINE_SKIP_GPU_TESTS").is_some() => {
+                eprintln!("skipping GPU test: {err}");
+                None
+            }
+            Err(err) => panic!("{err}, set RUSTY_ENGINE_SKIP_GPU_TESTS=1 to skip GPU tests"),
+        }
+    }
+
+    #[test]
+    fn test_render_order_puts_sampled_targets_first() {
+        let mut assets = AssetServer::new();
+        let mut image = || assets.add(Image::blank(1, 1).unwrap()).id();
+        let (minimap, portal, unused) = (image(), image(), image());
+        let cameras = [
+            (0, None),
+            (5, Some(minimap)),
+            (-1, Some(portal)),
+            (3, Some(unused)),
+        ];
+        let sampled = HashSet::from([minimap, portal]);
+        // The unsampled image camera keeps its place by `order`.
+        assert_eq!(render_order(&cameras, &sampled), [2, 1, 0, 3]);
+
+        // Two images showing each other fall back to `order`.
+        let cameras = [(1, Some(minimap)), (0, Some(portal))];
+        assert_eq!(render_order(&cameras, &sampled), [1, 0]);
+    }
+
+    #[test]
+    fn test_shader_has_every_sprite_entry_point() {
+        let source = include_str!("../shader.wgsl");
+        validate_wgsl(source).unwrap();
+        let module = naga::front::wgsl::parse_str(source).unwrap();
+        for mode in BLEND_MODES {
+            let entry = sprite_fragment_entry(mode);
+            assert!(
+                module.entry_points.iter().any(|e| e.name == entry),
+                "{entry} is missing from shader.wgsl"
+            );
+        }
+    }
+
+    #[test]
+    fn test_headless_frame_reads_back_clear_color() {
+        let Some(mut renderer) = headless(70, 30) else {
+            return;
+        };
+        renderer.prepare_cameras(&World::new(), &AssetServer::new());
+        renderer.update();
+        renderer.render().unwrap();
+        let frame = renderer.read_pixels().unwrap();
+        assert_eq!((frame.width, frame.height), (70, 30));
+        // Bottom-left corner, away from the triangle.
+        let [r, g, b, a] = frame.data[(29 * 70) * 4..][..4] else {
+            unreachable!()
+        };
+        assert_eq!((r, g, b, a), (89, 124, 149, 255));
+    }
+
+    #[test]
+    fn test_reload_keeps_pipelines_when_entry_point_is_renamed() {
+        let Some(mut renderer) = headless(70, 30) else {
+            return;
+        };
+        let source = include_str!("../shader.wgsl");
+        let renamed = source.replace("fn vs_color_main", "fn vs_color");
+        let err = renderer.rebuild_pipelines(&renamed).unwrap_err();
+        assert!(err.contains("vs_color_main"), "{err}");
+        let unbound = source.replace("@group(1) @binding(1)", "@group(1) @binding(2)");
+        assert!(renderer.rebuild_pipelines(&unbound).is_err());
+
+        renderer.prepare_cameras(&World::new(), &AssetServer::new());
+        renderer.update();
+        renderer.render().unwrap();
+        let frame = renderer.read_pixels().unwrap();
+        let center = &frame.data[(15 * 70 + 35) * 4..][..4];
+        assert!(center[0] > 100, "{center:?}");
+        renderer.rebuild_pipelines(source).unwrap();
+    }
+
+    #[test]
+    fn test_msaa_resolves_into_frame() {
+        let Some(mut renderer) = headless(70, 30) else {
+            return;
+        };
+        renderer.set_surface_options(SurfaceOptions {
+            msaa_samples: 4,
+            ..Default::default()
+        });
+        assert!(matches!(renderer.sample_count(), 1 | 4));
+        renderer.prepare_cameras(&World::new(), &AssetServer::new());
+        renderer.update();
+        renderer.render().unwrap();
+        let frame = renderer.read_pixels().unwrap();
+        assert_eq!(frame.data[(29 * 70) * 4..][..4], [89, 124, 149, 255]);
+        // The middle of the triangle, mostly red at the top vertex blending to the others.
+        let center = &frame.data[(15 * 70 + 35) * 4..][..4];
+        assert!(center[0] > 100 && center[3] == 255, "{center:?}");
+    }
+
+    #[test]
+    fn test_screenshot_callback() {
+        let Some(mut renderer) = headless(300, 2) else {
+            return;
+        };
+        let (sender, receiver) = std::sync::mpsc::channel();
+        renderer.request_screenshot(ScreenshotTarget::callback(move |image| {
+            let _ = sender.send(image);
+        }));
+        renderer.prepare_cameras(&World::new(), &AssetServer::new());
+        renderer.update();
+        renderer.render().unwrap();
+        renderer.flush_screenshots();
+
+        let image = receiver
+            .recv_timeout(std::time::Duration::from_secs(10))
+            .unwrap();
+        // 300 pixels is not a multiple of the row alignment, so rows were padded.
+        assert_eq!(image, renderer.read_pixels().unwrap());
+    }
 }
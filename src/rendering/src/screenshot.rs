@@ -0,0 +1,267 @@
+use assets::Image;
+use std::{
+    path::{Path, PathBuf},
+    sync::mpsc::{self, Receiver, TryRecvError},
+};
+
+/// Where a frame captured with
+/// [`Renderer::request_screenshot`](crate::renderer::Renderer::request_screenshot) goes.
+pub enum ScreenshotTarget {
+    /// Saved as a PNG file.
+    Path(PathBuf),
+    /// Called with the frame, on a background thread on native.
+    Callback(Box<dyn FnOnce(Image) + Send + Sync>),
+}
+
+impl ScreenshotTarget {
+    pub fn callback(f: impl FnOnce(Image) + Send + Sync + 'static) -> Self {
+        Self::Callback(Box::new(f))
+    }
+}
+
+impl From<PathBuf> for ScreenshotTarget {
+    fn from(path: PathBuf) -> Self {
+        Self::Path(path)
+    }
+}
+
+impl From<&Path> for ScreenshotTarget {
+    fn from(path: &Path) -> Self {
+        Self::Path(path.to_path_buf())
+    }
+}
+
+impl From<&str> for ScreenshotTarget {
+    fn from(path: &str) -> Self {
+        Self::Path(path.into())
+    }
+}
+
+impl From<String> for ScreenshotTarget {
+    fn from(path: String) -> Self {
+        Self::Path(path.into())
+    }
+}
+
+fn bytes_per_pixel(format: wgpu::TextureFormat) -> Option<u32> {
+    use wgpu::TextureFormat::*;
+    match format {
+        Rgba8Unorm | Rgba8UnormSrgb | Bgra8Unorm | Bgra8UnormSrgb => Some(4),
+        Rgba16Float => Some(8),
+        _ => None,
+    }
+}
+
+fn f16_to_f32(bits: u16) -> f32 {
+    let sign = if bits & 0x8000 != 0 { -1.0 } else { 1.0 };
+    let exponent = ((bits >> 10) & 0x1f) as i32;
+    let mantissa = (bits & 0x3ff) as f32;
+    sign * match exponent {
+        0 => mantissa * 2f32.powi(-24),
+        0x1f if mantissa == 0.0 => f32::INFINITY,
+        0x1f => f32::NAN,
+        _ => (1.0 + mantissa / 1024.0) * 2f32.powi(exponent - 15),
+    }
+}
+
+fn linear_to_srgb(value: f32) -> u8 {
+    let value = value.clamp(0.0, 1.0);
+    let encoded = if value <= 0.003_130_8 {
+        value * 12.92
+    } else {
+        1.055 * value.powf(1.0 / 2.4) - 0.055
+    };
+    (encoded * 255.0).round() as u8
+}
+
+/// Turn tightly packed texels of `format` into RGBA8 as a PNG viewer would show them.
+/// 8-bit formats are already in display encoding whether or not the texture is sRGB, they
+/// only need their channels reordered. Float formats hold linear values and get encoded.
+pub(crate) fn to_rgba8(format: wgpu::TextureFormat, mut data: Vec<u8>) -> Option<Vec<u8>> {
+    use wgpu::TextureFormat::*;
+    match format {
+        Rgba8Unorm | Rgba8UnormSrgb => Some(data),
+        Bgra8Unorm | Bgra8UnormSrgb => {
+            for pixel in data.chunks_exact_mut(4) {
+                pixel.swap(0, 2);
+            }
+            Some(data)
+        }
+        Rgba16Float => Some(
+            data.chunks_exact(8)
+                .flat_map(|texel| {
+                    let channel =
+                        |i: usize| f16_to_f32(u16::from_le_bytes([texel[i], texel[i + 1]]));
+                    let alpha = (channel(6).clamp(0.0, 1.0) * 255.0).round() as u8;
+                    [
+                        linear_to_srgb(channel(0)),
+                        linear_to_srgb(channel(2)),
+                        linear_to_srgb(channel(4)),
+                        alpha,
+                    ]
+                })
+                .collect(),
+        ),
+        _ => None,
+    }
+}
+
+fn deliver(
+    targets: Vec<ScreenshotTarget>,
+    format: wgpu::TextureFormat,
+    size: [u32; 2],
+    data: Vec<u8>,
+) {
+    let Some(data) = to_rgba8(format, data) else {
+        log::error!("cannot take screenshots of {format:?} frames");
+        return;
+    };
+    let image = match Image::new(size[0], size[1], data) {
+        Ok(image) => image,
+        Err(err) => {
+            log::error!("failed to read back a screenshot: {err}");
+            return;
+        }
+    };
+    for target in targets {
+        match target {
+            ScreenshotTarget::Path(path) => match image.save_png(&path) {
+                Ok(()) => log::info!("saved screenshot to {}", path.display()),
+                Err(err) => log::error!("failed to save screenshot to {}: {err}", path.display()),
+            },
+            ScreenshotTarget::Callback(callback) => callback(image.clone()),
+        }
+    }
+}
+
+/// Copy of a frame on its way back from the GPU.
+pub(crate) struct Readback {
+    buffer: wgpu::Buffer,
+    format: wgpu::TextureFormat,
+    size: [u32; 2],
+    padded_row_len: u32,
+    mapped: Option<Receiver<Result<(), wgpu::BufferAsyncError>>>,
+    targets: Vec<ScreenshotTarget>,
+}
+
+impl Readback {
+    /// Record a copy of `texture`. Call [`map`](Self::map) once the encoder is submitted.
+    /// `None` if the format cannot be converted to an image.
+    pub(crate) fn new(
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        texture: &wgpu::Texture,
+        targets: Vec<ScreenshotTarget>,
+    ) -> Option<Self> {
+        let format = texture.format();
+        let (width, height) = (texture.width(), texture.height());
+        // Buffer rows have to be aligned, the padding is dropped again when reading.
+        let padded_row_len =
+            (width * bytes_per_pixel(format)?).next_multiple_of(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT);
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Readback Buffer"),
+            size: (padded_row_len * height) as u64,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        encoder.copy_texture_to_buffer(
+            texture.as_image_copy(),
+            wgpu::ImageCopyBuffer {
+                buffer: &buffer,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(padded_row_len),
+                    rows_per_image: Some(height),
+                },
+            },
+            texture.size(),
+        );
+        Some(Self {
+            buffer,
+            format,
+            size: [width, height],
+            padded_row_len,
+            mapped: None,
+            targets,
+        })
+    }
+
+    pub(crate) fn map(&mut self) {
+        let (sender, receiver) = mpsc::channel();
+        self.buffer
+            .slice(..)
+            .map_async(wgpu::MapMode::Read, move |result| {
+                let _ = sender.send(result);
+            });
+        self.mapped = Some(receiver);
+    }
+
+    /// The texels without row padding, once the copy has arrived. The device has to be
+    /// polled for that to happen.
+    pub(crate) fn try_read(&self) -> Option<Result<Vec<u8>, wgpu::BufferAsyncError>> {
+        match self.mapped.as_ref()?.try_recv() {
+            Ok(Ok(())) => {}
+            Ok(Err(err)) => return Some(Err(err)),
+            Err(TryRecvError::Empty) => return None,
+            Err(TryRecvError::Disconnected) => return Some(Err(wgpu::BufferAsyncError)),
+        }
+        let row_len = (self.size[0] * bytes_per_pixel(self.format)?) as usize;
+        let mut data = Vec::with_capacity(row_len * self.size[1] as usize);
+        for row in self
+            .buffer
+            .slice(..)
+            .get_mapped_range()
+            .chunks(self.padded_row_len as usize)
+        {
+            data.extend_from_slice(&row[..row_len]);
+        }
+        self.buffer.unmap();
+        Some(Ok(data))
+    }
+
+    /// Hand the frame to its targets if it has arrived. Returns whether the readback is done.
+    pub(crate) fn try_finish(&mut self) -> bool {
+        let data = match self.try_read() {
+            None => return false,
+            Some(Ok(data)) => data,
+            Some(Err(err)) => {
+                log::error!("failed to read back screenshot: {err}");
+                return true;
+            }
+        };
+        let targets = std::mem::take(&mut self.targets);
+        let (format, size) = (self.format, self.size);
+        // Converting and encoding can take a while for big frames.
+        #[cfg(not(target_arch = "wasm32"))]
+        std::thread::spawn(move || deliver(targets, format, size, data));
+        #[cfg(target_arch = "wasm32")]
+        deliver(targets, format, size, data);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_to_rgba8() {
+        let bgra = vec![10, 20, 30, 40];
+        assert_eq!(
+            to_rgba8(wgpu::TextureFormat::Bgra8UnormSrgb, bgra),
+            Some(vec![30, 20, 10, 40])
+        );
+
+        // Linear 0.5, 1.0, 0.0 and alpha 1.0 as half floats.
+        let half = [0x3800u16, 0x3c00, 0x0000, 0x3c00]
+            .iter()
+            .flat_map(|c| c.to_le_bytes())
+            .collect();
+        assert_eq!(
+            to_rgba8(wgpu::TextureFormat::Rgba16Float, half),
+            Some(vec![188, 255, 0, 255])
+        );
+
+        assert_eq!(to_rgba8(wgpu::TextureFormat::R8Unorm, vec![0]), None);
+    }
+}
@@ -0,0 +1,124 @@
+use crate::atlas::TextureAtlas;
+use assets::{Handle, Image};
+use jaren_ecs_derive::Component;
+
+/// What a [`Sprite`] samples from.
+#[derive(Clone, Debug, PartialEq)]
+pub enum SpriteSource {
+    Image(Handle<Image>),
+    /// One cell of a texture atlas.
+    Atlas {
+        atlas: Handle<TextureAtlas>,
+        index: usize,
+    },
+}
+
+/// A textured quad. Drawn for every entity that also has a [`Transform2d`].
+#[derive(Component, Clone, Debug, PartialEq)]
+pub struct Sprite {
+    pub source: SpriteSource,
+    /// Multiplied with the texture color.
+    pub color: [f32; 4],
+    /// Size in world units. Defaults to the size of the image (or atlas cell) in pixels.
+    pub custom_size: Option<[f32; 2]>,
+    pub flip_x: bool,
+    pub flip_y: bool,
+    pub blend_mode: BlendMode,
+    /// Another atlas cell drawn over this one, used to blend animation states.
+    pub crossfade: Option<Crossfade>,
+}
+
+/// How a sprite is combined with what is already drawn behind it.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum BlendMode {
+    /// Regular transparency, for textures with straight (non-premultiplied) alpha.
+    #[default]
+    Alpha,
+    /// Transparency for textures whose color is already multiplied by their alpha.
+    Premultiplied,
+    /// Adds to the color behind, for light and glow effects.
+    Additive,
+    /// Multiplies the color behind, for shadows and tinting.
+    Multiply,
+}
+
+/// The atlas cell at `index`, drawn on top of the sprite with `weight` opacity.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Crossfade {
+    pub index: usize,
+    pub weight: f32,
+}
+
+impl Sprite {
+    fn new(source: SpriteSource) -> Self {
+        Self {
+            source,
+            color: [1.0, 1.0, 1.0, 1.0],
+            custom_size: None,
+            flip_x: false,
+            flip_y: false,
+            blend_mode: BlendMode::Alpha,
+            crossfade: None,
+        }
+    }
+
+    pub fn from_image(image: Handle<Image>) -> Self {
+        Self::new(SpriteSource::Image(image))
+    }
+
+    pub fn from_atlas(atlas: Handle<TextureAtlas>, index: usize) -> Self {
+        Self::new(SpriteSource::Atlas { atlas, index })
+    }
+
+    pub fn with_blend_mode(mut self, blend_mode: BlendMode) -> Self {
+        self.blend_mode = blend_mode;
+        self
+    }
+
+    /// Switch to another cell of the atlas. Does nothing for plain image sprites.
+    pub fn set_atlas_index(&mut self, new_index: usize) {
+        if let SpriteSource::Atlas { index, .. } = &mut self.source {
+            *index = new_index;
+        }
+    }
+}
+
+/// Position, rotation and scale of an entity in world space. `translation[2]` orders
+/// sprites, higher values draw on top.
+#[derive(Component, Copy, Clone, Debug, PartialEq)]
+pub struct Transform2d {
+    pub translation: [f32; 3],
+    /// Counter-clockwise, in radians.
+    pub rotation: f32,
+    pub scale: [f32; 2],
+}
+
+impl Default for Transform2d {
+    fn default() -> Self {
+        Self {
+            translation: [0.0, 0.0, 0.0],
+            rotation: 0.0,
+            scale: [1.0, 1.0],
+        }
+    }
+}
+
+impl Transform2d {
+    pub fn from_translation(x: f32, y: f32, z: f32) -> Self {
+        Self {
+            translation: [x, y, z],
+            ..Default::default()
+        }
+    }
+
+    /// Map a point from local space to world space.
+    pub fn transform_point(&self, point: [f32; 2]) -> [f32; 2] {
+        let (sin, cos) = self.rotation.sin_cos();
+        let x = point[0] * self.scale[0];
+        let y = point[1] * self.scale[1];
+        [
+            x * cos - y * sin + self.translation[0],
+            x * sin + y * cos + self.translation[1],
+        ]
+    }
+}
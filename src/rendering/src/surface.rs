@@ -0,0 +1,155 @@
+/// How finished frames are handed to the display.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum PresentMode {
+    /// Vsync: wait for the display, no tearing. Supported everywhere.
+    #[default]
+    Fifo,
+    /// Replace the queued frame with the newest one, no tearing and lower latency than
+    /// [`Fifo`](Self::Fifo). Falls back to `Fifo`.
+    Mailbox,
+    /// Show frames as soon as they are done, may tear. Falls back to
+    /// [`Mailbox`](Self::Mailbox), then [`Fifo`](Self::Fifo).
+    Immediate,
+}
+
+/// Which kind of window surface format to prefer.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum SurfaceFormat {
+    /// 8 bits per channel, encoded to sRGB when written.
+    #[default]
+    Srgb,
+    /// 8 bits per channel, written as is. Colors look darker unless the shader encodes them.
+    Linear,
+    /// 16-bit float, linear and allowed to go above 1.0. Falls back to
+    /// [`Srgb`](Self::Srgb) where the display does not support it.
+    Hdr,
+}
+
+impl PresentMode {
+    pub(crate) fn to_wgpu(self) -> wgpu::PresentMode {
+        match self {
+            PresentMode::Fifo => wgpu::PresentMode::Fifo,
+            PresentMode::Mailbox => wgpu::PresentMode::Mailbox,
+            PresentMode::Immediate => wgpu::PresentMode::Immediate,
+        }
+    }
+}
+
+impl SurfaceFormat {
+    /// Whether `format` is of this kind.
+    pub(crate) fn matches(self, format: wgpu::TextureFormat) -> bool {
+        match self {
+            SurfaceFormat::Srgb => format.is_srgb(),
+            SurfaceFormat::Linear => matches!(
+                format,
+                wgpu::TextureFormat::Bgra8Unorm | wgpu::TextureFormat::Rgba8Unorm
+            ),
+            SurfaceFormat::Hdr => format == wgpu::TextureFormat::Rgba16Float,
+        }
+    }
+}
+
+/// Settings for the frames a [`Renderer`](crate::renderer::Renderer) draws to the window,
+/// changeable at runtime with
+/// [`Renderer::set_surface_options`](crate::renderer::Renderer::set_surface_options).
+/// Anything the adapter does not support falls back to the closest setting it does.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct SurfaceOptions {
+    pub present_mode: PresentMode,
+    pub format: SurfaceFormat,
+    /// Samples per pixel for anti-aliasing the window, resolved before presenting.
+    /// 1 turns MSAA off; 4 is supported everywhere, other counts depend on the adapter.
+    pub msaa_samples: u32,
+    /// How many frames may be queued ahead of the display. Lower is more responsive,
+    /// higher smooths out uneven frame times.
+    pub max_frame_latency: u32,
+}
+
+impl Default for SurfaceOptions {
+    fn default() -> Self {
+        Self {
+            present_mode: PresentMode::Fifo,
+            format: SurfaceFormat::Srgb,
+            msaa_samples: 1,
+            max_frame_latency: 2,
+        }
+    }
+}
+
+/// The first of `requested` and its fallbacks that is `available`.
+pub(crate) fn choose_present_mode(
+    requested: PresentMode,
+    available: &[wgpu::PresentMode],
+) -> wgpu::PresentMode {
+    let candidates: &[PresentMode] = match requested {
+        PresentMode::Fifo => &[],
+        PresentMode::Mailbox => &[PresentMode::Mailbox],
+        PresentMode::Immediate => &[PresentMode::Immediate, PresentMode::Mailbox],
+    };
+    candidates
+        .iter()
+        .map(|mode| mode.to_wgpu())
+        .find(|mode| available.contains(mode))
+        .unwrap_or(wgpu::PresentMode::Fifo)
+}
+
+/// The format in `available` closest to `requested`, `None` if there is none at all.
+pub(crate) fn choose_format(
+    requested: SurfaceFormat,
+    available: &[wgpu::TextureFormat],
+) -> Option<wgpu::TextureFormat> {
+    let find = |kind: SurfaceFormat| available.iter().copied().find(|f| kind.matches(*f));
+    find(requested)
+        .or_else(|| find(SurfaceFormat::Srgb))
+        .or(available.first().copied())
+}
+
+/// The highest sample count up to `requested` that is `supported`, at least 1.
+pub(crate) fn choose_sample_count(requested: u32, supported: impl Fn(u32) -> bool) -> u32 {
+    [16, 8, 4, 2]
+        .into_iter()
+        .find(|&count| count <= requested && supported(count))
+        .unwrap_or(1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wgpu::TextureFormat;
+
+    #[test]
+    fn test_surface_option_fallbacks() {
+        use wgpu::PresentMode as Wgpu;
+        assert_eq!(
+            choose_present_mode(PresentMode::Immediate, &[Wgpu::Fifo, Wgpu::Mailbox]),
+            Wgpu::Mailbox
+        );
+        assert_eq!(
+            choose_present_mode(PresentMode::Mailbox, &[Wgpu::Fifo, Wgpu::Immediate]),
+            Wgpu::Fifo
+        );
+
+        let formats = [TextureFormat::Bgra8Unorm, TextureFormat::Bgra8UnormSrgb];
+        assert_eq!(
+            choose_format(SurfaceFormat::Srgb, &formats),
+            Some(TextureFormat::Bgra8UnormSrgb)
+        );
+        assert_eq!(
+            choose_format(SurfaceFormat::Linear, &formats),
+            Some(TextureFormat::Bgra8Unorm)
+        );
+        assert_eq!(
+            choose_format(SurfaceFormat::Hdr, &formats),
+            Some(TextureFormat::Bgra8UnormSrgb)
+        );
+        assert_eq!(choose_format(SurfaceFormat::Hdr, &[]), None);
+
+        assert_eq!(choose_sample_count(8, |count| count == 4), 4);
+        assert_eq!(choose_sample_count(3, |_| true), 2);
+        assert_eq!(choose_sample_count(1, |_| true), 1);
+    }
+}
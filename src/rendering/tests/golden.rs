@@ -0,0 +1,414 @@
+//! Golden-image tests: render a scene headless and compare it with a reference PNG.
+//!
+//! References live in `tests/golden` and are (re)written by running
+//! `cargo test -p rendering --test golden` with `GOLDEN_UPDATE=1`. When a comparison fails,
+//! the rendered frame and an image highlighting the differences are written to
+//! `target/golden`, or `GOLDEN_OUTPUT_DIR`. Without a GPU adapter, not even a software
+//! one, the tests fail unless `RUSTY_ENGINE_SKIP_GPU_TESTS` is set.
+
+use assets::{AssetServer, Image};
+use jaren_ecs::{spawn, system::World};
+use rendering::{
+    camera::{Camera2d, RenderTarget, ScalingMode},
+    renderer::Renderer,
+    sprite::{BlendMode, Sprite, Transform2d},
+};
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+// How long `GoldenScene::render` waits for assets loaded from disk.
+const LOAD_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Machines without any adapter fail the GPU tests, unless they are skipped on purpose
+// with `RUSTY_ENGINE_SKIP_GPU_TESTS`.
+fn headless(width: u32, height: u32) -> Option<Renderer> {
+    match pollster::block_on(Renderer::headless(width, height)) {
+        Ok(renderer) => Some(renderer),
+        Err(err) if std::env::var_os("RUSTY_ENGINE_SKIP_GPU_TESTS").is_some() => {
+            eprintln!("skipping GPU test: {err}");
+            None
+        }
+        Err(err) => panic!("{err}, set RUSTY_ENGINE_SKIP_GPU_TESTS=1 to skip GPU tests"),
+    }
+}
+
+/// A world and its assets, rendered with a headless [`Renderer`].
+struct GoldenScene {
+    world: World,
+    assets: AssetServer,
+    size: [u32; 2],
+}
+
+impl GoldenScene {
+    /// Empty scene rendered at `width` x `height`. Without a camera, the default one is used.
+    fn new(width: u32, height: u32) -> Self {
+        Self {
+            world: World::new(),
+            assets: AssetServer::new(),
+            size: [width, height],
+        }
+    }
+
+    /// Wait for pending assets, then draw one frame. `None` if the GPU tests are skipped.
+    fn render(mut self) -> Option<Image> {
+        let mut renderer = headless(self.size[0], self.size[1])?;
+        let start = Instant::now();
+        self.assets.update();
+        while self.assets.is_loading() && start.elapsed() < LOAD_TIMEOUT {
+            std::thread::sleep(Duration::from_millis(1));
+            self.assets.update();
+        }
+
+        renderer.prepare_assets(&self.assets);
+        renderer.prepare_cameras(&self.world, &self.assets);
+        renderer.prepare_sprites(&self.world, &self.assets);
+        renderer.update();
+        renderer.render().unwrap();
+        renderer.read_pixels()
+    }
+}
+
+/// How far a frame may drift from its reference. Small differences are expected between
+/// GPUs and drivers, mostly along edges where rasterization and filtering differ.
+#[derive(Copy, Clone, Debug, PartialEq)]
+struct Tolerance {
+    /// Largest difference in any channel (0-255) for which pixels still count as equal.
+    per_pixel: u8,
+    /// Pixels that differ by more than `per_pixel` are only counted as different when
+    /// their perceptual (YIQ) color distance is above this, from 0.0 to 1.0.
+    perceptual: f32,
+    /// Fraction of pixels that may be different before the comparison fails.
+    max_different: f32,
+}
+
+impl Default for Tolerance {
+    fn default() -> Self {
+        Self {
+            per_pixel: 2,
+            perceptual: 0.05,
+            max_different: 0.001,
+        }
+    }
+}
+
+/// Result of comparing a frame against its reference.
+struct Comparison {
+    different_pixels: usize,
+    total_pixels: usize,
+    /// The reference faded to gray, with pixels that differ in red and pixels within
+    /// tolerance but not identical in yellow.
+    diff: Image,
+}
+
+impl Comparison {
+    fn passes(&self, tolerance: &Tolerance) -> bool {
+        self.different_pixels as f32 <= self.total_pixels as f32 * tolerance.max_different
+    }
+}
+
+#[derive(Debug)]
+enum GoldenError {
+    /// The reference could not be read or decoded.
+    Reference { path: PathBuf, message: String },
+    SizeMismatch {
+        expected: [u32; 2],
+        actual: [u32; 2],
+    },
+    /// Too many pixels differ. Holds where the frame and the diff were written.
+    Mismatch {
+        different_pixels: usize,
+        total_pixels: usize,
+        actual: PathBuf,
+        diff: PathBuf,
+    },
+}
+
+impl fmt::Display for GoldenError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GoldenError::Reference { path, message } => write!(
+                f,
+                "could not read reference {}: {message} (run with GOLDEN_UPDATE=1 to create it)",
+                path.display()
+            ),
+            GoldenError::SizeMismatch { expected, actual } => write!(
+                f,
+                "frame is {}x{}, the reference is {}x{}",
+                actual[0], actual[1], expected[0], expected[1]
+            ),
+            GoldenError::Mismatch {
+                different_pixels,
+                total_pixels,
+                actual,
+                diff,
+            } => write!(
+                f,
+                "{different_pixels} of {total_pixels} pixels differ, see {} and {}",
+                actual.display(),
+                diff.display()
+            ),
+        }
+    }
+}
+
+impl std::error::Error for GoldenError {}
+
+// Brightness and chrominance of a pixel blended over white, as in pixelmatch.
+fn yiq(pixel: &[u8]) -> [f32; 3] {
+    let alpha = pixel[3] as f32 / 255.0;
+    let [r, g, b] = [0, 1, 2].map(|i| 255.0 + (pixel[i] as f32 - 255.0) * alpha);
+    [
+        r * 0.298_895_3 + g * 0.586_622_5 + b * 0.114_482_2,
+        r * 0.595_977_99 - g * 0.274_176_1 - b * 0.321_801_9,
+        r * 0.211_470_17 - g * 0.522_617_1 + b * 0.311_146_94,
+    ]
+}
+
+// Squared YIQ distance scaled so black against white is 1.0.
+fn perceptual_distance(a: &[u8], b: &[u8]) -> f32 {
+    const MAX_DELTA: f32 = 35215.0;
+    let ([ya, ia, qa], [yb, ib, qb]) = (yiq(a), yiq(b));
+    let (y, i, q) = (ya - yb, ia - ib, qa - qb);
+    (0.5053 * y * y + 0.299 * i * i + 0.1957 * q * q) / MAX_DELTA
+}
+
+/// Compare two frames pixel by pixel. `None` if their sizes differ.
+fn compare(actual: &Image, expected: &Image, tolerance: &Tolerance) -> Option<Comparison> {
+    if [actual.width, actual.height] != [expected.width, expected.height] {
+        return None;
+    }
+    let threshold = tolerance.perceptual * tolerance.perceptual;
+    let mut different_pixels = 0;
+    let mut diff = Vec::with_capacity(expected.data.len());
+    for (a, e) in actual.data.chunks(4).zip(expected.data.chunks(4)) {
+        let channel_delta = a.iter().zip(e).map(|(a, e)| a.abs_diff(*e)).max();
+        let color = if channel_delta <= Some(tolerance.per_pixel) {
+            let gray = 255 - ((255.0 - yiq(e)[0]) * 0.1) as u8;
+            if a == e {
+                [gray, gray, gray, 255]
+            } else {
+                [255, 255, 0, 255]
+            }
+        } else if perceptual_distance(a, e) > threshold {
+            different_pixels += 1;
+            [255, 0, 0, 255]
+        } else {
+            [255, 255, 0, 255]
+        };
+        diff.extend_from_slice(&color);
+    }
+    Some(Comparison {
+        different_pixels,
+        total_pixels: (expected.width * expected.height) as usize,
+        diff: Image::new(expected.width, expected.height, diff)
+            .expect("one diff pixel per expected pixel"),
+    })
+}
+
+fn output_dir() -> PathBuf {
+    std::env::var_os("GOLDEN_OUTPUT_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| Path::new(env!("CARGO_MANIFEST_DIR")).join("../../target/golden"))
+}
+
+/// Compare `actual` with the PNG at `reference`, writing the frame and a diff image on
+/// failure. With `GOLDEN_UPDATE` set, the reference is replaced instead.
+fn check_golden(
+    reference: impl AsRef<Path>,
+    actual: &Image,
+    tolerance: &Tolerance,
+) -> Result<(), GoldenError> {
+    let reference = reference.as_ref();
+    if std::env::var_os("GOLDEN_UPDATE").is_some() {
+        return actual
+            .save_png(reference)
+            .map_err(|e| GoldenError::Reference {
+                path: reference.to_path_buf(),
+                message: e.to_string(),
+            });
+    }
+
+    let expected = std::fs::read(reference)
+        .map_err(|e| e.to_string())
+        .and_then(|bytes| Image::from_bytes(&bytes).map_err(|e| e.to_string()))
+        .map_err(|message| GoldenError::Reference {
+            path: reference.to_path_buf(),
+            message,
+        })?;
+    let comparison = compare(actual, &expected, tolerance).ok_or(GoldenError::SizeMismatch {
+        expected: [expected.width, expected.height],
+        actual: [actual.width, actual.height],
+    })?;
+    if comparison.passes(tolerance) {
+        return Ok(());
+    }
+
+    let dir = output_dir();
+    let name = reference
+        .file_stem()
+        .map_or("frame".into(), |s| s.to_string_lossy());
+    let actual_path = dir.join(format!("{name}.actual.png"));
+    let diff_path = dir.join(format!("{name}.diff.png"));
+    // Best effort, the mismatch is reported either way.
+    let _ = std::fs::create_dir_all(&dir);
+    let _ = actual.save_png(&actual_path);
+    let _ = comparison.diff.save_png(&diff_path);
+    Err(GoldenError::Mismatch {
+        different_pixels: comparison.different_pixels,
+        total_pixels: comparison.total_pixels,
+        actual: actual_path,
+        diff: diff_path,
+    })
+}
+fn reference(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/golden")
+        .join(format!("{name}.png"))
+}
+
+// 8x8 checkerboard of opaque white and half transparent orange.
+fn checker() -> Image {
+    let mut data = Vec::new();
+    for y in 0..8 {
+        for x in 0..8 {
+            let pixel = if (x + y) % 2 == 0 {
+                [255, 255, 255, 255]
+            } else {
+                [255, 128, 0, 128]
+            };
+            data.extend_from_slice(&pixel);
+        }
+    }
+    Image::new(8, 8, data).unwrap()
+}
+
+fn pixel_camera() -> Camera2d {
+    Camera2d {
+        scaling_mode: ScalingMode::WindowSize,
+        ..Default::default()
+    }
+}
+
+#[test]
+fn test_compare_tolerance() {
+    let expected = Image::new(2, 1, vec![100, 100, 100, 255, 0, 0, 0, 255]).unwrap();
+    let tolerance = Tolerance {
+        max_different: 0.0,
+        ..Default::default()
+    };
+
+    let close = Image::new(2, 1, vec![101, 99, 100, 255, 2, 0, 0, 255]).unwrap();
+    let result = compare(&close, &expected, &tolerance).unwrap();
+    assert!(result.passes(&tolerance));
+    assert_eq!(&result.diff.data[..4], [255, 255, 0, 255]);
+
+    // Over the per-pixel limit but hard to see.
+    let subtle = Image::new(2, 1, vec![100, 100, 106, 255, 0, 0, 0, 255]).unwrap();
+    assert!(
+        compare(&subtle, &expected, &tolerance)
+            .unwrap()
+            .passes(&tolerance)
+    );
+
+    let wrong = Image::new(2, 1, vec![100, 100, 100, 255, 255, 0, 0, 255]).unwrap();
+    let result = compare(&wrong, &expected, &tolerance).unwrap();
+    assert_eq!(result.different_pixels, 1);
+    assert_eq!(&result.diff.data[4..], [255, 0, 0, 255]);
+
+    assert!(compare(&Image::blank(1, 1).unwrap(), &expected, &tolerance).is_none());
+}
+
+#[test]
+fn test_golden_sprite_blend_modes() {
+    let mut scene = GoldenScene::new(64, 64);
+    let backdrop = scene
+        .assets
+        .add(Image::new(2, 1, vec![40, 90, 200, 255, 230, 200, 40, 255]).unwrap());
+    let checker = scene.assets.add(checker());
+    let world = &mut scene.world;
+    spawn!(world, pixel_camera());
+    spawn!(
+        world,
+        Sprite {
+            custom_size: Some([64.0, 64.0]),
+            ..Sprite::from_image(backdrop)
+        },
+        Transform2d::default()
+    );
+    let modes = [
+        (BlendMode::Alpha, [-16.0, 16.0]),
+        (BlendMode::Premultiplied, [16.0, 16.0]),
+        (BlendMode::Additive, [-16.0, -16.0]),
+        (BlendMode::Multiply, [16.0, -16.0]),
+    ];
+    for (mode, [x, y]) in modes {
+        let sprite = Sprite {
+            custom_size: Some([24.0, 24.0]),
+            ..Sprite::from_image(checker.clone()).with_blend_mode(mode)
+        };
+        spawn!(world, sprite, Transform2d::from_translation(x, y, 1.0));
+    }
+
+    let Some(frame) = scene.render() else {
+        return;
+    };
+    check_golden(
+        reference("sprite_blend_modes"),
+        &frame,
+        &Tolerance::default(),
+    )
+    .unwrap();
+}
+
+#[test]
+fn test_golden_render_to_texture() {
+    let mut scene = GoldenScene::new(64, 64);
+    let minimap = scene.assets.add(Image::blank(32, 32).unwrap());
+    let checker = scene.assets.add(checker());
+    let world = &mut scene.world;
+    spawn!(world, pixel_camera());
+    // Zoomed in on the checkerboard, rendered before the window camera shows it.
+    spawn!(
+        world,
+        Camera2d {
+            position: [-16.0, 0.0],
+            zoom: 2.0,
+            target: RenderTarget::Image(minimap.clone()),
+            order: 1,
+            clear_color: Some([0.0, 0.5, 0.0, 1.0]),
+            ..pixel_camera()
+        }
+    );
+    spawn!(
+        world,
+        Sprite {
+            custom_size: Some([16.0, 16.0]),
+            ..Sprite::from_image(checker)
+        },
+        Transform2d {
+            rotation: std::f32::consts::FRAC_PI_4,
+            ..Transform2d::from_translation(-16.0, 0.0, 0.0)
+        }
+    );
+    spawn!(
+        world,
+        Sprite {
+            flip_y: true,
+            ..Sprite::from_image(minimap)
+        },
+        Transform2d::from_translation(16.0, 0.0, 0.0)
+    );
+
+    let Some(frame) = scene.render() else {
+        return;
+    };
+    check_golden(
+        reference("render_to_texture"),
+        &frame,
+        &Tolerance::default(),
+    )
+    .unwrap();
+}